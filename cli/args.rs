@@ -0,0 +1,70 @@
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use crate::CliResult;
+
+/// Just enough argument parsing for the subcommands: positionals, `--flag`s and `--option value`s
+pub(crate) struct Args {
+    positionals: Vec<String>,
+    flags: HashSet<String>,
+    options: HashMap<String, String>,
+}
+
+impl Args {
+    /// `takes_value` lists the options that consume the argument after them
+    pub fn parse(args: impl Iterator<Item = String>, takes_value: &[&str]) -> CliResult<Self> {
+        let mut parsed = Self {
+            positionals: Vec::new(),
+            flags: HashSet::new(),
+            options: HashMap::new(),
+        };
+        let mut args = args.peekable();
+
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                parsed.positionals.push(arg);
+            } else if let Some((name, value)) = arg.split_once('=') {
+                parsed.options.insert(name.to_string(), value.to_string());
+            } else if takes_value.contains(&arg.as_str()) {
+                let value = args.next().ok_or_else(|| format!("{arg} needs a value"))?;
+                parsed.options.insert(arg, value);
+            } else {
+                parsed.flags.insert(arg);
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Fails on any flag or option that isn't in `known`, to catch typos
+    pub fn only_allow(&self, known: &[&str]) -> CliResult<()> {
+        let names = self.flags.iter().chain(self.options.keys());
+        match names
+            .into_iter()
+            .find(|name| !known.contains(&name.as_str()))
+        {
+            Some(unknown) => Err(format!("unknown option {unknown}").into()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn positional(&self, index: usize, name: &str) -> CliResult<&str> {
+        match self.positionals.get(index) {
+            Some(value) => Ok(value),
+            None => Err(format!("missing <{name}> argument").into()),
+        }
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.contains(name)
+    }
+
+    pub fn value<T: FromStr>(&self, name: &str) -> CliResult<Option<T>> {
+        match self.options.get(name) {
+            Some(value) => match value.parse() {
+                Ok(parsed) => Ok(Some(parsed)),
+                Err(_) => Err(format!("invalid value for {name}: {value}").into()),
+            },
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use hardqoi::common::{compact_rgba8_to_rgb8, rgba8_to_pixels, QOIHeader, RGBA};
+use hardqoi::{decode_with_backend, encode_with_backend, Backend};
+
+use crate::args::Args;
+use crate::convert::load_image;
+use crate::CliResult;
+
+pub(crate) const USAGE: &str = "\
+hardqoi bench <directory> [options]
+    Encodes and decodes every image in a directory with each hardqoi backend this CPU supports,
+    plus qoi_rs and rapid-qoi when built with the competitors feature.
+    Speeds are medians over the timed runs, in megabytes of RGBA pixels per second.
+    --iterations <n>    timed runs of each codec on each image, 10 by default
+    --json              print JSON instead of a table";
+
+/// An image from the benchmark directory, decoded up front so only the codecs are timed
+struct BenchImage {
+    name: String,
+    header: QOIHeader,
+    pixels: Vec<RGBA>,
+    /// The pixels packed with 3 or 4 bytes each, matching `header.has_alpha`
+    #[cfg_attr(not(feature = "competitors"), allow(dead_code))]
+    packed: Vec<u8>,
+}
+
+impl BenchImage {
+    fn channels(&self) -> usize {
+        3 + self.header.has_alpha as usize
+    }
+
+    fn rgba_size(&self) -> usize {
+        self.pixels.len() * 4
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Codec {
+    Hardqoi(Backend),
+    #[cfg(feature = "competitors")]
+    QoiRs,
+    #[cfg(feature = "competitors")]
+    RapidQoi,
+}
+
+impl Codec {
+    fn all() -> Vec<Codec> {
+        let competitors = [
+            #[cfg(feature = "competitors")]
+            Codec::QoiRs,
+            #[cfg(feature = "competitors")]
+            Codec::RapidQoi,
+        ];
+        let backends = Backend::available().into_iter().map(Codec::Hardqoi);
+        backends.chain(competitors).collect()
+    }
+
+    fn name(self) -> String {
+        match self {
+            Codec::Hardqoi(backend) => format!("hardqoi/{}", backend.name()),
+            #[cfg(feature = "competitors")]
+            Codec::QoiRs => "qoi_rs".to_string(),
+            #[cfg(feature = "competitors")]
+            Codec::RapidQoi => "rapid-qoi".to_string(),
+        }
+    }
+
+    /// Runs one encode and one decode, returning the encoded size and how long each took.
+    /// Returns `None` if the codec can't handle the image at all.
+    fn run_once(self, image: &BenchImage) -> CliResult<Option<(usize, Duration, Duration)>> {
+        match self {
+            Codec::Hardqoi(backend) => {
+                let mut encoded = Vec::with_capacity(image.rgba_size() / 8);
+                let mut decoded = Vec::with_capacity(image.pixels.len());
+
+                let start = Instant::now();
+                encode_with_backend(&image.pixels, &mut encoded, image.header, backend).map_err(
+                    |(found, expected)| format!("expected {expected} pixels, found {found}"),
+                )?;
+                let encode_time = start.elapsed();
+
+                let start = Instant::now();
+                decode_with_backend(&encoded, &mut decoded, backend).map_err(
+                    |(found, expected)| format!("expected {expected} pixels, found {found}"),
+                )?;
+                let decode_time = start.elapsed();
+
+                check_round_trip(decoded == image.pixels)?;
+                Ok(Some((encoded.len(), encode_time, decode_time)))
+            }
+            #[cfg(feature = "competitors")]
+            Codec::QoiRs => {
+                let (Ok(width), Ok(height)) = (
+                    u16::try_from(image.header.width),
+                    u16::try_from(image.header.height),
+                ) else {
+                    // qoi_rs stores its dimensions as u16
+                    return Ok(None);
+                };
+                let channels = image.channels() as u32;
+                let input = qoi_rs::Image {
+                    pixels: image.packed.clone().into_boxed_slice(),
+                    width,
+                    height,
+                };
+
+                let start = Instant::now();
+                let encoded = qoi_rs::encode(input, channels).ok_or("qoi_rs failed to encode")?;
+                let encode_time = start.elapsed();
+
+                let start = Instant::now();
+                let decoded =
+                    qoi_rs::decode(&encoded, channels).ok_or("qoi_rs failed to decode")?;
+                let decode_time = start.elapsed();
+
+                check_round_trip(*decoded.pixels == *image.packed)?;
+                Ok(Some((encoded.len(), encode_time, decode_time)))
+            }
+            #[cfg(feature = "competitors")]
+            Codec::RapidQoi => {
+                use rapid_qoi::{Colors, Qoi};
+                let qoi = Qoi {
+                    width: image.header.width,
+                    height: image.header.height,
+                    colors: if image.header.has_alpha {
+                        Colors::Rgba
+                    } else {
+                        Colors::Rgb
+                    },
+                };
+
+                let start = Instant::now();
+                let encoded = qoi.encode_alloc(&image.packed)?;
+                let encode_time = start.elapsed();
+
+                let start = Instant::now();
+                let (_, decoded) = Qoi::decode_alloc(&encoded)?;
+                let decode_time = start.elapsed();
+
+                check_round_trip(decoded == image.packed)?;
+                Ok(Some((encoded.len(), encode_time, decode_time)))
+            }
+        }
+    }
+}
+
+fn check_round_trip(matches: bool) -> CliResult<()> {
+    match matches {
+        true => Ok(()),
+        false => Err("decoding the encoded image didn't give back the original pixels".into()),
+    }
+}
+
+/// Timings of one part of a codec, one sample per iteration
+struct Samples(Vec<Duration>);
+
+impl Samples {
+    fn median(&self) -> f64 {
+        let mut seconds: Vec<f64> = self.0.iter().map(Duration::as_secs_f64).collect();
+        seconds.sort_by(f64::total_cmp);
+        match seconds.len() {
+            0 => f64::NAN,
+            n if n % 2 == 1 => seconds[n / 2],
+            n => (seconds[n / 2 - 1] + seconds[n / 2]) / 2.0,
+        }
+    }
+
+    fn mean(&self) -> f64 {
+        self.0.iter().map(Duration::as_secs_f64).sum::<f64>() / self.0.len() as f64
+    }
+
+    fn variance(&self) -> f64 {
+        let mean = self.mean();
+        let squares: f64 = self
+            .0
+            .iter()
+            .map(|sample| (sample.as_secs_f64() - mean).powi(2))
+            .sum();
+        squares / self.0.len() as f64
+    }
+
+    /// The standard deviation as a percentage of the mean, comparable across image sizes
+    fn relative_deviation(&self) -> f64 {
+        100.0 * self.variance().sqrt() / self.mean()
+    }
+}
+
+struct Measurement {
+    image: usize,
+    codec: Codec,
+    encoded_size: usize,
+    encode: Samples,
+    decode: Samples,
+}
+
+fn megabytes_per_second(bytes: usize, seconds: f64) -> f64 {
+    bytes as f64 / 1e6 / seconds
+}
+
+fn load_images(directory: &Path) -> CliResult<Vec<BenchImage>> {
+    let entries =
+        std::fs::read_dir(directory).map_err(|why| format!("{}: {why}", directory.display()))?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+
+    let mut images = Vec::new();
+    for path in paths {
+        let loaded = match load_image(&path) {
+            Ok(loaded) => loaded,
+            Err(why) => {
+                eprintln!("skipping {why}");
+                continue;
+            }
+        };
+        let header = QOIHeader::from(&loaded.image);
+        let mut packed = loaded.image.into_rgba8().into_raw();
+        let pixels = rgba8_to_pixels(&packed);
+        if !header.has_alpha {
+            compact_rgba8_to_rgb8(&mut packed);
+        }
+        images.push(BenchImage {
+            name: path.file_name().unwrap().to_string_lossy().into_owned(),
+            header,
+            pixels,
+            packed,
+        });
+    }
+    Ok(images)
+}
+
+fn measure(
+    image_index: usize,
+    image: &BenchImage,
+    codec: Codec,
+    iterations: usize,
+) -> CliResult<Option<Measurement>> {
+    let with_context = |why| format!("{} with {}: {why}", image.name, codec.name());
+
+    // the first run checks the round trip and warms the caches, so it isn't counted
+    let encoded_size = match codec.run_once(image).map_err(with_context)? {
+        Some((encoded_size, _, _)) => encoded_size,
+        None => return Ok(None),
+    };
+
+    let (mut encode, mut decode) = (Vec::new(), Vec::new());
+    for _ in 0..iterations {
+        if let Some((_, encode_time, decode_time)) = codec.run_once(image).map_err(with_context)? {
+            encode.push(encode_time);
+            decode.push(decode_time);
+        }
+    }
+    Ok(Some(Measurement {
+        image: image_index,
+        codec,
+        encoded_size,
+        encode: Samples(encode),
+        decode: Samples(decode),
+    }))
+}
+
+fn print_table(images: &[BenchImage], measurements: &[Measurement]) {
+    println!(
+        "{:<28} {:<18} {:>12} {:>7} {:>17} {:>9} {:>17} {:>9}",
+        "image", "codec", "size", "ratio", "encode MB/s", "Mpx/s", "decode MB/s", "Mpx/s"
+    );
+    for measurement in measurements {
+        let image = &images[measurement.image];
+        let speeds = |samples: &Samples| {
+            let median = samples.median();
+            (
+                format!(
+                    "{:.1} ±{:.1}%",
+                    megabytes_per_second(image.rgba_size(), median),
+                    samples.relative_deviation()
+                ),
+                format!("{:.1}", image.pixels.len() as f64 / 1e6 / median),
+            )
+        };
+        let (encode_speed, encode_pixels) = speeds(&measurement.encode);
+        let (decode_speed, decode_pixels) = speeds(&measurement.decode);
+        println!(
+            "{:<28} {:<18} {:>12} {:>6.1}% {:>17} {:>9} {:>17} {:>9}",
+            image.name,
+            measurement.codec.name(),
+            measurement.encoded_size,
+            100.0 * measurement.encoded_size as f64 / image.rgba_size() as f64,
+            encode_speed,
+            encode_pixels,
+            decode_speed,
+            decode_pixels
+        );
+    }
+
+    // totals weigh each image by its size, as if the whole directory were one big image
+    println!();
+    for codec in Codec::all() {
+        let runs: Vec<&Measurement> = measurements
+            .iter()
+            .filter(|measurement| measurement.codec == codec)
+            .collect();
+        if runs.is_empty() {
+            continue;
+        }
+        let rgba_size: usize = runs.iter().map(|run| images[run.image].rgba_size()).sum();
+        let encoded_size: usize = runs.iter().map(|run| run.encoded_size).sum();
+        let encode_time: f64 = runs.iter().map(|run| run.encode.median()).sum();
+        let decode_time: f64 = runs.iter().map(|run| run.decode.median()).sum();
+        let megapixels = rgba_size as f64 / 4e6;
+        println!(
+            "{:<28} {:<18} {:>12} {:>6.1}% {:>17.1} {:>9.1} {:>17.1} {:>9.1}",
+            format!("total ({} images)", runs.len()),
+            codec.name(),
+            encoded_size,
+            100.0 * encoded_size as f64 / rgba_size as f64,
+            megabytes_per_second(rgba_size, encode_time),
+            megapixels / encode_time,
+            megabytes_per_second(rgba_size, decode_time),
+            megapixels / decode_time
+        );
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn json_samples(samples: &Samples, image: &BenchImage) -> String {
+    let median = samples.median();
+    format!(
+        "{{\"median_seconds\": {median:e}, \"mean_seconds\": {:e}, \"variance_seconds2\": {:e}, \"mb_per_second\": {:.3}, \"pixels_per_second\": {:.0}}}",
+        samples.mean(),
+        samples.variance(),
+        megabytes_per_second(image.rgba_size(), median),
+        image.pixels.len() as f64 / median
+    )
+}
+
+fn print_json(images: &[BenchImage], measurements: &[Measurement], iterations: usize) {
+    let results: Vec<String> = measurements
+        .iter()
+        .map(|measurement| {
+            let image = &images[measurement.image];
+            format!(
+                "    {{\"image\": {}, \"codec\": {}, \"width\": {}, \"height\": {}, \"channels\": {}, \"encoded_size\": {},\n     \"encode\": {},\n     \"decode\": {}}}",
+                json_string(&image.name),
+                json_string(&measurement.codec.name()),
+                image.header.width,
+                image.header.height,
+                image.channels(),
+                measurement.encoded_size,
+                json_samples(&measurement.encode, image),
+                json_samples(&measurement.decode, image)
+            )
+        })
+        .collect();
+    println!(
+        "{{\n  \"iterations\": {iterations},\n  \"results\": [\n{}\n  ]\n}}",
+        results.join(",\n")
+    );
+}
+
+pub(crate) fn run(args: impl Iterator<Item = String>) -> CliResult<()> {
+    let args = Args::parse(args, &["--iterations"])?;
+    args.only_allow(&["--iterations", "--json"])?;
+    let directory = Path::new(args.positional(0, "directory")?);
+    let iterations = match args.value::<usize>("--iterations")? {
+        Some(0) => return Err("--iterations must be at least 1".into()),
+        Some(iterations) => iterations,
+        None => 10,
+    };
+
+    let images = load_images(directory)?;
+    if images.is_empty() {
+        return Err(format!("{} has no images in it", directory.display()).into());
+    }
+
+    let mut measurements = Vec::new();
+    for (index, image) in images.iter().enumerate() {
+        for codec in Codec::all() {
+            measurements.extend(measure(index, image, codec, iterations)?);
+        }
+    }
+
+    if args.flag("--json") {
+        print_json(&images, &measurements, iterations);
+    } else {
+        print_table(&images, &measurements);
+    }
+    Ok(())
+}
@@ -0,0 +1,238 @@
+use std::path::Path;
+
+use image::{DynamicImage, GenericImageView};
+
+use hardqoi::common::{QOIHeader, MAGIC_QOIF};
+use hardqoi::integrity::append_trailer;
+use hardqoi::metadata::{append_metadata, Chunk, Metadata};
+use hardqoi::{
+    decode, decode_to_dynamic_image, encode_dynamic_image_with_header, png_linear_rgb,
+    read_icc_profile, validate, OpError,
+};
+
+use crate::args::Args;
+use crate::CliResult;
+
+pub(crate) const USAGE: &str = "\
+hardqoi convert <input> <output> [options]
+    Converts between QOI and any format the image crate can read or write,
+    picking the direction from the output's extension. QOI output keeps the input's ICC profile,
+    and all of its metadata when the input is QOI too.
+    --channels <3|4>    channels to encode, instead of 4 only when some pixel isn't opaque
+    --linear, --srgb    colorspace to encode, instead of reading it from PNG sRGB/gAMA chunks
+    --verify            decode the output again and compare it pixel by pixel with the input
+    --checksum          append an integrity trailer to QOI output, checked whenever hardqoi reads it back";
+
+#[derive(Clone, Copy, Default)]
+pub(crate) struct ConvertOptions {
+    pub verify: bool,
+    pub channels: Option<u8>,
+    pub linear_rgb: Option<bool>,
+    pub checksum: bool,
+}
+
+/// The sizes involved in one conversion, for reporting
+pub(crate) struct Conversion {
+    pub width: u32,
+    pub height: u32,
+    pub channels: u8,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+}
+
+/// An image loaded from disk, with the colorspace that its file declared if it declared one,
+/// and the metadata to carry over to QOI: all of it from a QOI image, or the ICC profile from anything else
+pub(crate) struct LoadedImage {
+    pub image: DynamicImage,
+    pub linear_rgb: Option<bool>,
+    pub metadata: Metadata,
+    pub file_size: u64,
+}
+
+impl ConvertOptions {
+    /// Reads `--verify`, `--channels`, `--linear`, `--srgb` and `--checksum`
+    pub fn from_args(args: &Args) -> CliResult<Self> {
+        Ok(Self {
+            verify: args.flag("--verify"),
+            channels: match args.value::<u8>("--channels")? {
+                Some(channels @ (3 | 4)) => Some(channels),
+                Some(other) => return Err(format!("QOI has 3 or 4 channels, not {other}").into()),
+                None => None,
+            },
+            linear_rgb: match (args.flag("--linear"), args.flag("--srgb")) {
+                (true, true) => return Err("--linear and --srgb are mutually exclusive".into()),
+                (true, false) => Some(true),
+                (false, true) => Some(false),
+                (false, false) => None,
+            },
+            checksum: args.flag("--checksum"),
+        })
+    }
+}
+
+pub(crate) fn is_qoi_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("qoi"))
+}
+
+/// Loads a QOI image with hardqoi, or anything else with the image crate
+pub(crate) fn load_image(path: &Path) -> CliResult<LoadedImage> {
+    let data = std::fs::read(path).map_err(|why| format!("{}: {why}", path.display()))?;
+
+    let (image, linear_rgb, metadata) = if data.starts_with(&MAGIC_QOIF) {
+        // the decoder trusts its input, so check it first. Data after the end marker does no harm,
+        // but the decoder expects the marker to end its input, so it only gets the image itself.
+        let stream = match validate(&data) {
+            Ok(_) => &data[..],
+            Err(OpError::TrailingBytes(end)) => &data[..end],
+            Err(why) => return Err(format!("{}: {why}", path.display()).into()),
+        };
+        let header = QOIHeader::from(stream);
+        let image =
+            decode_to_dynamic_image(stream).map_err(|why| format!("{}: {why}", path.display()))?;
+        let metadata = Metadata::read(&data).map_err(|why| format!("{}: {why}", path.display()))?;
+        (image, Some(header.linear_rgb), metadata)
+    } else {
+        let image =
+            image::load_from_memory(&data).map_err(|why| format!("{}: {why}", path.display()))?;
+        let mut metadata = Metadata::new();
+        if let Some(profile) = read_icc_profile(&data) {
+            metadata.set(Chunk::IccProfile(profile));
+        }
+        (image, png_linear_rgb(&data), metadata)
+    };
+
+    Ok(LoadedImage {
+        image,
+        linear_rgb,
+        metadata,
+        file_size: data.len() as u64,
+    })
+}
+
+pub(crate) fn is_opaque(img: &DynamicImage) -> bool {
+    match img {
+        _ if !img.color().has_alpha() => true,
+        DynamicImage::ImageRgba8(buffer) => buffer.pixels().all(|pixel| pixel[3] == u8::MAX),
+        _ => img.pixels().all(|(_, _, pixel)| pixel[3] == u8::MAX),
+    }
+}
+
+/// Converts `input` to `output`, encoding QOI if the output ends in `.qoi` and decoding it otherwise.
+/// Errors start with the path of the file they're about.
+pub(crate) fn convert_file(
+    input: &Path,
+    output: &Path,
+    options: ConvertOptions,
+) -> CliResult<Conversion> {
+    let loaded = load_image(input)?;
+    let img = &loaded.image;
+    let (width, height) = img.dimensions();
+
+    let channels = if is_qoi_path(output) {
+        let mut header = QOIHeader::from(img);
+        header.has_alpha = match options.channels {
+            Some(channels) => channels == 4,
+            None => !is_opaque(img),
+        };
+        header.linear_rgb = options.linear_rgb.or(loaded.linear_rgb).unwrap_or(false);
+
+        let mut qoi_data =
+            encode_dynamic_image_with_header(img, header).map_err(|(found, expected)| {
+                format!(
+                    "{}: expected {expected} pixels, found {found} pixels instead",
+                    input.display()
+                )
+            })?;
+        if !loaded.metadata.is_empty() {
+            append_metadata(&mut qoi_data, &loaded.metadata);
+        }
+        if options.checksum {
+            // the trailer covers the pixels as they decode, so take them from the output itself
+            let mut pixels = Vec::with_capacity(header.image_size());
+            decode(&qoi_data, &mut pixels).map_err(|(found, expected)| {
+                format!(
+                    "{}: expected {expected} pixels, found {found} pixels instead",
+                    output.display()
+                )
+            })?;
+            append_trailer(&mut qoi_data, &pixels);
+        }
+        std::fs::write(output, qoi_data).map_err(|why| format!("{}: {why}", output.display()))?;
+        3 + header.has_alpha as u8
+    } else {
+        img.save(output)
+            .map_err(|why| format!("{}: {why}", output.display()))?;
+        img.color().channel_count()
+    };
+
+    if options.verify {
+        verify(img, &load_image(output)?.image, channels)
+            .map_err(|why| format!("{}: {why}", output.display()))?;
+    }
+
+    Ok(Conversion {
+        width,
+        height,
+        channels,
+        input_bytes: loaded.file_size,
+        output_bytes: std::fs::metadata(output)
+            .map_err(|why| format!("{}: {why}", output.display()))?
+            .len(),
+    })
+}
+
+/// Compares the pixels that survive a conversion to `channels` channels
+fn verify(expected: &DynamicImage, found: &DynamicImage, channels: u8) -> CliResult<()> {
+    if expected.dimensions() != found.dimensions() {
+        return Err(format!(
+            "verification failed, expected {:?} pixels but found {:?}",
+            expected.dimensions(),
+            found.dimensions()
+        )
+        .into());
+    }
+
+    let channels = channels.min(4) as usize;
+    let (expected, found) = (expected.to_rgba8(), found.to_rgba8());
+    let mismatches = expected
+        .enumerate_pixels()
+        .zip(found.pixels())
+        .filter(|((_, _, a), b)| a.0[..channels] != b.0[..channels]);
+
+    let mut count = 0usize;
+    let mut first = None;
+    for ((x, y, a), b) in mismatches {
+        count += 1;
+        first.get_or_insert((x, y, a.0, b.0));
+    }
+    match first {
+        None => Ok(()),
+        Some((x, y, a, b)) => Err(format!(
+            "verification failed, {count} pixels differ, the first at ({x}, {y}): expected {a:?}, found {b:?}"
+        )
+        .into()),
+    }
+}
+
+pub(crate) fn run(args: impl Iterator<Item = String>) -> CliResult<()> {
+    let args = Args::parse(args, &["--channels"])?;
+    args.only_allow(&["--channels", "--linear", "--srgb", "--verify", "--checksum"])?;
+    let input = Path::new(args.positional(0, "input")?);
+    let output = Path::new(args.positional(1, "output")?);
+    let options = ConvertOptions::from_args(&args)?;
+
+    let conversion = convert_file(input, output, options)?;
+    println!(
+        "{} -> {}: {}x{}, {} channels, {} -> {} bytes{}",
+        input.display(),
+        output.display(),
+        conversion.width,
+        conversion.height,
+        conversion.channels,
+        conversion.input_bytes,
+        conversion.output_bytes,
+        if options.verify { ", verified" } else { "" }
+    );
+    Ok(())
+}
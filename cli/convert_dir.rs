@@ -0,0 +1,234 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use image::ImageFormat;
+
+use crate::args::Args;
+use crate::convert::{convert_file, is_qoi_path, ConvertOptions};
+use crate::CliResult;
+
+pub(crate) const USAGE: &str = "\
+hardqoi convert-dir <source> <destination> [options]
+    Mirrors a directory tree, converting every image in it to QOI, or every QOI image to --to.
+    Files whose output is newer than they are get skipped, and a report follows at the end.
+    --to <extension>    the format to convert to, qoi by default
+    --jobs <n>          files to convert at once, one per CPU thread by default
+    --force             convert every file, even the ones that are up to date
+    --worst <n>         how many of the worst compressing files to report, 10 by default
+    also takes --channels, --linear, --srgb, --verify and --checksum, which work the same as for convert";
+
+/// One file to convert, with its output path already worked out
+struct Job {
+    input: PathBuf,
+    output: PathBuf,
+}
+
+enum Outcome {
+    Converted { input_bytes: u64, output_bytes: u64 },
+    UpToDate,
+    Failed(String),
+}
+
+/// Collects the files to convert from a source tree
+struct Walk<'a> {
+    extension: &'a str,
+    /// The destination tree, left out in case it's inside the source tree
+    destination: &'a Path,
+    jobs: Vec<Job>,
+    failures: Vec<String>,
+}
+
+impl Walk<'_> {
+    /// Whether `path` is something this run converts, going by its extension
+    fn is_source(&self, path: &Path) -> bool {
+        match self.extension.eq_ignore_ascii_case("qoi") {
+            true => !is_qoi_path(path) && ImageFormat::from_path(path).is_ok(),
+            false => is_qoi_path(path),
+        }
+    }
+
+    /// Walks `source` depth first, pairing every file to convert with its path under `destination`
+    fn visit(&mut self, source: &Path, destination: &Path) {
+        let entries = match std::fs::read_dir(source) {
+            Ok(entries) => entries,
+            Err(why) => return self.failures.push(format!("{}: {why}", source.display())),
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            let name = path.file_name().unwrap();
+            if path.is_dir() && path != self.destination {
+                self.visit(&path, &destination.join(name));
+            } else if path.is_file() && self.is_source(&path) {
+                self.jobs.push(Job {
+                    output: destination.join(name).with_extension(self.extension),
+                    input: path,
+                });
+            }
+        }
+    }
+}
+
+fn is_up_to_date(job: &Job) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|meta| meta.modified());
+    match (modified(&job.input), modified(&job.output)) {
+        (Ok(input), Ok(output)) => output >= input,
+        _ => false,
+    }
+}
+
+fn run_job(job: &Job, options: ConvertOptions, force: bool) -> Outcome {
+    if !force && is_up_to_date(job) {
+        return Outcome::UpToDate;
+    }
+    if let Some(parent) = job.output.parent() {
+        if let Err(why) = std::fs::create_dir_all(parent) {
+            return Outcome::Failed(format!("{}: {why}", parent.display()));
+        }
+    }
+    match convert_file(&job.input, &job.output, options) {
+        Ok(conversion) => Outcome::Converted {
+            input_bytes: conversion.input_bytes,
+            output_bytes: conversion.output_bytes,
+        },
+        Err(why) => Outcome::Failed(why.to_string()),
+    }
+}
+
+/// Runs every job on `workers` threads, which take the next job as soon as they finish one
+fn run_jobs(jobs: &[Job], options: ConvertOptions, force: bool, workers: usize) -> Vec<Outcome> {
+    let next_job = AtomicUsize::new(0);
+    let outcomes = Mutex::new(Vec::with_capacity(jobs.len()));
+
+    thread::scope(|scope| {
+        for _ in 0..workers.min(jobs.len()) {
+            scope.spawn(|| loop {
+                let index = next_job.fetch_add(1, Ordering::Relaxed);
+                let Some(job) = jobs.get(index) else {
+                    break;
+                };
+                let outcome = run_job(job, options, force);
+                outcomes.lock().unwrap().push((index, outcome));
+            });
+        }
+    });
+
+    let mut outcomes = outcomes.into_inner().unwrap();
+    outcomes.sort_by_key(|(index, _)| *index);
+    outcomes.into_iter().map(|(_, outcome)| outcome).collect()
+}
+
+fn percentage(part: u64, whole: u64) -> f64 {
+    100.0 * part as f64 / whole.max(1) as f64
+}
+
+fn report(
+    jobs: &[Job],
+    outcomes: &[Outcome],
+    mut failures: Vec<String>,
+    worst: usize,
+) -> CliResult<()> {
+    let mut converted = Vec::new();
+    let mut up_to_date = 0;
+    for (job, outcome) in jobs.iter().zip(outcomes) {
+        match outcome {
+            Outcome::Converted {
+                input_bytes,
+                output_bytes,
+            } => converted.push((job, *input_bytes, *output_bytes)),
+            Outcome::UpToDate => up_to_date += 1,
+            Outcome::Failed(why) => failures.push(why.clone()),
+        }
+    }
+
+    let input_total: u64 = converted.iter().map(|(_, input, _)| input).sum();
+    let output_total: u64 = converted.iter().map(|(_, _, output)| output).sum();
+    println!(
+        "{} converted, {up_to_date} up to date, {} failed",
+        converted.len(),
+        failures.len()
+    );
+    println!(
+        "{input_total} bytes in, {output_total} bytes out ({:.1}% of the input)",
+        percentage(output_total, input_total)
+    );
+
+    converted.sort_by(|(_, a_in, a_out), (_, b_in, b_out)| {
+        let ratio = |output: u64, input: u64| output as f64 / input.max(1) as f64;
+        ratio(*b_out, *b_in).total_cmp(&ratio(*a_out, *a_in))
+    });
+    if worst > 0 && !converted.is_empty() {
+        println!("\nworst compressing:");
+        for (job, input_bytes, output_bytes) in converted.iter().take(worst) {
+            println!(
+                "{:>7.1}%  {} ({input_bytes} -> {output_bytes} bytes)",
+                percentage(*output_bytes, *input_bytes),
+                job.input.display()
+            );
+        }
+    }
+
+    if failures.is_empty() {
+        return Ok(());
+    }
+    println!("\nfailures:");
+    for why in &failures {
+        println!("{why}");
+    }
+    Err(format!(
+        "{} of {} files failed to convert",
+        failures.len(),
+        jobs.len()
+    )
+    .into())
+}
+
+pub(crate) fn run(args: impl Iterator<Item = String>) -> CliResult<()> {
+    let args = Args::parse(args, &["--channels", "--to", "--jobs", "--worst"])?;
+    args.only_allow(&[
+        "--channels",
+        "--linear",
+        "--srgb",
+        "--verify",
+        "--checksum",
+        "--to",
+        "--jobs",
+        "--worst",
+        "--force",
+    ])?;
+    let source = Path::new(args.positional(0, "source")?);
+    let destination = Path::new(args.positional(1, "destination")?);
+    let options = ConvertOptions::from_args(&args)?;
+
+    let extension = args
+        .value::<String>("--to")?
+        .unwrap_or_else(|| "qoi".to_string());
+    let extension = extension.trim_start_matches('.');
+    if ImageFormat::from_extension(extension).is_none() {
+        return Err(format!("unknown image extension {extension}").into());
+    }
+    let workers = match args.value::<usize>("--jobs")? {
+        Some(0) => return Err("--jobs must be at least 1".into()),
+        Some(jobs) => jobs,
+        None => thread::available_parallelism().map_or(1, |threads| threads.get()),
+    };
+    let worst = args.value::<usize>("--worst")?.unwrap_or(10);
+    if !source.is_dir() {
+        return Err(format!("{} is not a directory", source.display()).into());
+    }
+
+    let mut walk = Walk {
+        extension,
+        destination,
+        jobs: Vec::new(),
+        failures: Vec::new(),
+    };
+    walk.visit(source, destination);
+    let outcomes = run_jobs(&walk.jobs, options, args.flag("--force"), workers);
+    report(&walk.jobs, &outcomes, walk.failures, worst)
+}
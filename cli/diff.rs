@@ -0,0 +1,139 @@
+use std::path::Path;
+
+use image::{GenericImageView, RgbaImage};
+
+use hardqoi::common::{rgb8_to_pixels, QOIHeader};
+use hardqoi::encode;
+
+use crate::args::Args;
+use crate::convert::load_image;
+use crate::CliResult;
+
+pub(crate) const USAGE: &str = "\
+hardqoi diff <first> <second> [options]
+    Compares two images pixel by pixel, each of them QOI or anything the image crate can read.
+    Reports the differing pixels, their bounding box, the largest change in each channel and the PSNR,
+    and exits with an error if any pixel differs.
+    --heatmap <file.qoi>    also write an image of where they differ, brighter red for bigger differences";
+
+/// How two images of the same size differ
+#[derive(Default)]
+struct Comparison {
+    differing_pixels: usize,
+    /// The smallest and largest x and y of a differing pixel
+    bounds: Option<(u32, u32, u32, u32)>,
+    max_delta: [u8; 4],
+    squared_error: u64,
+}
+
+impl Comparison {
+    /// Peak signal to noise ratio in decibels over all four channels, infinite for identical images
+    fn psnr(&self, pixel_count: usize) -> f64 {
+        let mean_squared_error = self.squared_error as f64 / (pixel_count * 4) as f64;
+        10.0 * (255.0 * 255.0 / mean_squared_error).log10()
+    }
+}
+
+fn channel_deltas(a: [u8; 4], b: [u8; 4]) -> [u8; 4] {
+    [0, 1, 2, 3].map(|channel| a[channel].abs_diff(b[channel]))
+}
+
+fn compare(first: &RgbaImage, second: &RgbaImage) -> Comparison {
+    let mut comparison = Comparison::default();
+    for ((x, y, a), b) in first.enumerate_pixels().zip(second.pixels()) {
+        let deltas = channel_deltas(a.0, b.0);
+        if deltas == [0; 4] {
+            continue;
+        }
+        comparison.differing_pixels += 1;
+        for (max, delta) in comparison.max_delta.iter_mut().zip(deltas) {
+            *max = (*max).max(delta);
+            comparison.squared_error += delta as u64 * delta as u64;
+        }
+        comparison.bounds = Some(match comparison.bounds {
+            None => (x, y, x, y),
+            Some((left, top, right, bottom)) => {
+                (left.min(x), top.min(y), right.max(x), bottom.max(y))
+            }
+        });
+    }
+    comparison
+}
+
+/// Red where the images differ, scaled by the largest channel difference so that even a difference of one shows,
+/// over a dimmed grayscale copy of the first image where they agree
+fn heatmap(first: &RgbaImage, second: &RgbaImage) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity(first.as_raw().len() / 4 * 3);
+    for (a, b) in first.pixels().zip(second.pixels()) {
+        let delta = channel_deltas(a.0, b.0).into_iter().max().unwrap();
+        let [r, g, bl, _] = a.0;
+        rgb.extend(match delta {
+            0 => {
+                let luma = (r as u32 * 299 + g as u32 * 587 + bl as u32 * 114) / 1000;
+                [(luma / 4) as u8; 3]
+            }
+            _ => [64 + (delta as u32 * 191 / 255) as u8, 0, 0],
+        });
+    }
+    rgb
+}
+
+pub(crate) fn run(args: impl Iterator<Item = String>) -> CliResult<()> {
+    let args = Args::parse(args, &["--heatmap"])?;
+    args.only_allow(&["--heatmap"])?;
+    let first_path = Path::new(args.positional(0, "first")?);
+    let second_path = Path::new(args.positional(1, "second")?);
+
+    let first = load_image(first_path)?.image;
+    let second = load_image(second_path)?.image;
+    if first.dimensions() != second.dimensions() {
+        return Err(format!(
+            "the images have different sizes, {}x{} and {}x{}",
+            first.width(),
+            first.height(),
+            second.width(),
+            second.height()
+        )
+        .into());
+    }
+    let (width, height) = first.dimensions();
+    let (first, second) = (first.into_rgba8(), second.into_rgba8());
+    let comparison = compare(&first, &second);
+
+    if let Some(heatmap_path) = args.value::<String>("--heatmap")? {
+        let header = QOIHeader {
+            width,
+            height,
+            has_alpha: false,
+            linear_rgb: false,
+        };
+        let mut qoi_data = Vec::new();
+        encode(
+            &rgb8_to_pixels(&heatmap(&first, &second)),
+            &mut qoi_data,
+            header,
+        )
+        .map_err(|(found, expected)| format!("expected {expected} pixels, found {found}"))?;
+        std::fs::write(&heatmap_path, qoi_data).map_err(|why| format!("{heatmap_path}: {why}"))?;
+    }
+
+    let Some((left, top, right, bottom)) = comparison.bounds else {
+        println!("The images are pixel-identical, {width}x{height}");
+        return Ok(());
+    };
+    let pixel_count = width as usize * height as usize;
+    let [r, g, b, a] = comparison.max_delta;
+    println!(
+        "{} of {pixel_count} pixels differ ({:.3}%)",
+        comparison.differing_pixels,
+        100.0 * comparison.differing_pixels as f64 / pixel_count as f64
+    );
+    println!(
+        "bounding box: ({left}, {top}) to ({right}, {bottom}), {}x{}",
+        right - left + 1,
+        bottom - top + 1
+    );
+    println!("largest difference: r {r}, g {g}, b {b}, a {a}");
+    println!("PSNR: {:.2} dB", comparison.psnr(pixel_count));
+    Err("the images differ".into())
+}
@@ -0,0 +1,199 @@
+use std::io::{ErrorKind, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use hardqoi::common::QOIHeader;
+use hardqoi::inspect::{first_divergence, Divergence, OpRecord, OpTrace, TraceEnd};
+use hardqoi::metadata::{Chunk, Metadata};
+
+use crate::args::Args;
+use crate::CliResult;
+
+pub(crate) const USAGE: &str = "\
+hardqoi inspect <file.qoi> [options]
+    Lists every op in a QOI stream with its byte offset, operands, the pixel it produces,
+    that pixel's coordinates and the index array slot it lands in, then any metadata chunks.
+    --op <kinds>             only list these comma separated kinds: rgb,rgba,index,diff,luma,run
+    --pixels <start>..<end>  only list ops producing pixels in this range of pixel indices
+    --diverge <other.qoi>    compare op by op with another file and show where they first differ";
+
+const OP_KINDS: [&str; 6] = ["rgb", "rgba", "index", "diff", "luma", "run"];
+
+fn describe_header(header: &QOIHeader) -> String {
+    format!(
+        "{}x{}, {} channels, {}",
+        header.width,
+        header.height,
+        3 + header.has_alpha as u8,
+        if header.linear_rgb { "linear" } else { "sRGB" }
+    )
+}
+
+fn describe_end(end: Option<TraceEnd>) -> &'static str {
+    match end {
+        Some(TraceEnd::Complete) => "complete, followed by the end marker",
+        Some(TraceEnd::MissingEndMarker) => "every pixel decoded, but the end marker is missing",
+        Some(TraceEnd::Truncated) => "truncated before the last pixel",
+        Some(TraceEnd::RunOverflow) => "a run continues past the last pixel",
+        None => "unfinished",
+    }
+}
+
+fn describe_chunk(chunk: &Chunk) -> String {
+    match chunk {
+        Chunk::IccProfile(profile) => format!("ICC profile, {} bytes", profile.len()),
+        Chunk::Orientation(orientation) => format!("EXIF orientation {orientation}"),
+        Chunk::Text { key, value } => format!("{key}: {value}"),
+        Chunk::Unknown { tag, data } => {
+            format!("unknown chunk {}, {} bytes", tag.escape_ascii(), data.len())
+        }
+    }
+}
+
+fn format_record(header: &QOIHeader, record: &OpRecord) -> String {
+    let (x, y) = header.coordinates(record.pixel_index);
+    let position = match record.op.pixel_count() {
+        1 => format!("({x}, {y})"),
+        n => format!("({x}, {y}) x{n}"),
+    };
+    let [r, g, b, a] = record.pixel.to_ne_bytes();
+    format!(
+        "{:>10}  {:<36}  {:<18}  slot {:>2}  #{r:02x}{g:02x}{b:02x}{a:02x}",
+        record.offset,
+        record.op.to_string(),
+        position,
+        record.slot
+    )
+}
+
+fn parse_pixel_range(range: &str) -> CliResult<Range<usize>> {
+    let (start, end) = range
+        .split_once("..")
+        .ok_or_else(|| format!("expected <start>..<end>, found {range}"))?;
+    let start = if start.is_empty() { 0 } else { start.parse()? };
+    let end = if end.is_empty() {
+        usize::MAX
+    } else {
+        end.parse()?
+    };
+    Ok(start..end)
+}
+
+fn open_trace<'a>(path: &Path, data: &'a [u8]) -> CliResult<OpTrace<'a>> {
+    OpTrace::new(data).ok_or_else(|| format!("{} is not a QOI image", path.display()).into())
+}
+
+fn list_ops(trace: &mut OpTrace, kinds: &[String], pixels: Range<usize>) -> std::io::Result<()> {
+    let mut out = std::io::stdout().lock();
+    writeln!(
+        out,
+        "{:>10}  {:<36}  {:<18}  {:<7}  pixel",
+        "offset", "op", "position", "index"
+    )?;
+    let header = trace.header();
+    for record in trace.by_ref() {
+        let kind = record
+            .op
+            .name()
+            .trim_start_matches("QOI_OP_")
+            .to_lowercase();
+        let overlaps = record.pixel_index < pixels.end && pixels.start < record.pixels().end;
+        if overlaps && (kinds.is_empty() || kinds.contains(&kind)) {
+            writeln!(out, "{}", format_record(&header, &record))?;
+        }
+    }
+    Ok(())
+}
+
+fn show_divergence(left: OpTrace, right: OpTrace) {
+    let (left_header, right_header) = (left.header(), right.header());
+    match first_divergence(left, right) {
+        None => println!("The op streams are identical"),
+        Some(Divergence::Header(..)) => println!(
+            "The headers differ: {} vs {}",
+            describe_header(&left_header),
+            describe_header(&right_header)
+        ),
+        Some(Divergence::End(left_end, right_end)) => println!(
+            "Every op agrees, but the first stream is {} and the second is {}",
+            describe_end(Some(left_end)),
+            describe_end(Some(right_end))
+        ),
+        Some(Divergence::Op {
+            op_number,
+            left: left_record,
+            right: right_record,
+        }) => {
+            println!("The streams first differ at op #{op_number}:");
+            for (name, record) in [("first ", left_record), ("second", right_record)] {
+                match record {
+                    Some(record) => println!("{name} {}", format_record(&left_header, &record)),
+                    None => println!("{name} (no more ops)"),
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn run(args: impl Iterator<Item = String>) -> CliResult<()> {
+    let args = Args::parse(args, &["--op", "--pixels", "--diverge"])?;
+    args.only_allow(&["--op", "--pixels", "--diverge"])?;
+    let path = Path::new(args.positional(0, "file.qoi")?);
+    let data = std::fs::read(path).map_err(|why| format!("{}: {why}", path.display()))?;
+    let mut trace = open_trace(path, &data)?;
+    println!(
+        "{}: {}, {} bytes",
+        path.display(),
+        describe_header(&trace.header()),
+        data.len()
+    );
+
+    if let Some(other_path) = args.value::<String>("--diverge")? {
+        let other_path = Path::new(&other_path);
+        let other_data =
+            std::fs::read(other_path).map_err(|why| format!("{}: {why}", other_path.display()))?;
+        let other_trace = open_trace(other_path, &other_data)?;
+        println!(
+            "{}: {}, {} bytes",
+            other_path.display(),
+            describe_header(&other_trace.header()),
+            other_data.len()
+        );
+        show_divergence(trace, other_trace);
+        return Ok(());
+    }
+
+    let kinds: Vec<String> = match args.value::<String>("--op")? {
+        Some(kinds) => kinds.split(',').map(|kind| kind.to_lowercase()).collect(),
+        None => Vec::new(),
+    };
+    if let Some(unknown) = kinds.iter().find(|kind| !OP_KINDS.contains(&kind.as_str())) {
+        return Err(format!("unknown op kind {unknown}, expected one of {OP_KINDS:?}").into());
+    }
+    let pixels = match args.value::<String>("--pixels")? {
+        Some(range) => parse_pixel_range(&range)?,
+        None => 0..usize::MAX,
+    };
+
+    match list_ops(&mut trace, &kinds, pixels) {
+        // the listing is often piped into head or less, which may stop reading early
+        Err(why) if why.kind() == ErrorKind::BrokenPipe => return Ok(()),
+        result => result?,
+    }
+    println!(
+        "{} of {} pixels decoded, stream ended at byte {}: {}",
+        trace.pixels_decoded(),
+        trace.header().image_size(),
+        trace.position(),
+        describe_end(trace.end())
+    );
+    match Metadata::read(&data) {
+        Ok(metadata) => {
+            for chunk in &metadata.chunks {
+                println!("metadata: {}", describe_chunk(chunk));
+            }
+        }
+        Err(why) => println!("metadata: {why}"),
+    }
+    Ok(())
+}
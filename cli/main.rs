@@ -0,0 +1,55 @@
+extern crate image;
+
+use std::error::Error;
+use std::process::ExitCode;
+
+mod args;
+mod bench;
+mod convert;
+mod convert_dir;
+mod diff;
+mod inspect;
+mod pipe;
+mod view;
+
+pub(crate) type CliResult<T> = Result<T, Box<dyn Error>>;
+
+fn usage() -> String {
+    [
+        "usage:",
+        convert::USAGE,
+        convert_dir::USAGE,
+        diff::USAGE,
+        inspect::USAGE,
+        view::USAGE,
+        pipe::USAGE,
+        bench::USAGE,
+    ]
+    .join("\n")
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let result = match args.next().as_deref() {
+        Some("convert") => convert::run(args),
+        Some("convert-dir") => convert_dir::run(args),
+        Some("diff") => diff::run(args),
+        Some("inspect") => inspect::run(args),
+        Some("view") => view::run(args),
+        Some("pipe") => pipe::run(args),
+        Some("bench") => bench::run(args),
+        Some("help" | "--help" | "-h") | None => {
+            println!("{}", usage());
+            return ExitCode::SUCCESS;
+        }
+        Some(other) => Err(format!("unknown subcommand {other}\n{}", usage()).into()),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(why) => {
+            eprintln!("hardqoi: {why}");
+            ExitCode::FAILURE
+        }
+    }
+}
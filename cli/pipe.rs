@@ -0,0 +1,429 @@
+use std::io::{BufWriter, ErrorKind, Read, Write};
+
+use hardqoi::common::{compact_rgba8_to_rgb8, QOIHeader, END_8, MAGIC_QOIF, RGBA};
+use hardqoi::integrity::{TRAILER_SIZE, TRAILER_TAG};
+use hardqoi::metadata::{FOOTER_SIZE, METADATA_TAG};
+use hardqoi::{
+    decode_to_bytes_with_options, decode_yuv, validate, DecodeOptions, Encoder, OpError,
+    PixelLayout, QoiOp, YuvFormat, YuvMatrix, YuvOptions, YuvRange,
+};
+
+use crate::args::Args;
+use crate::CliResult;
+
+pub(crate) const USAGE: &str = "\
+hardqoi pipe --width <w> --height <h> [options]
+    Reads fixed size raw frames from stdin, such as ffmpeg -f rawvideo output,
+    and writes one QOI image per frame to stdout.
+    --format <rgba|rgb|bgra|argb|abgr>  the layout of the raw pixels, rgba by default,
+             <nv12|i420|yuyv>           or of YUV frames from a camera or video capture
+    --matrix <601|709>                  the YUV matrix, 601 by default
+    --range <limited|full>              the YUV range, limited by default
+    --channels <3|4>                    channels to encode, 3 for rgb and YUV and 4 otherwise by default
+    --linear                            mark the images as linear RGB instead of sRGB
+    --output <pattern>                  write each image to its own file instead, replacing {} with the frame number
+    --decode                            go the other way, reading QOI images from stdin and writing raw frames,
+                                        in which case --width and --height are optional and checked if given";
+
+const HEADER_SIZE: usize = 14;
+
+#[derive(Clone, Copy, PartialEq)]
+enum RawFormat {
+    Rgb,
+    /// Four channels in any order
+    Packed(PixelLayout),
+    Yuv(YuvFormat, YuvOptions),
+}
+
+impl RawFormat {
+    fn parse(name: &str, yuv_options: YuvOptions) -> CliResult<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "rgb" => Ok(RawFormat::Rgb),
+            "rgba" => Ok(RawFormat::Packed(PixelLayout::Rgba)),
+            "bgra" => Ok(RawFormat::Packed(PixelLayout::Bgra)),
+            "argb" => Ok(RawFormat::Packed(PixelLayout::Argb)),
+            "abgr" => Ok(RawFormat::Packed(PixelLayout::Abgr)),
+            "nv12" => Ok(RawFormat::Yuv(YuvFormat::Nv12, yuv_options)),
+            "i420" => Ok(RawFormat::Yuv(YuvFormat::I420, yuv_options)),
+            "yuyv" => Ok(RawFormat::Yuv(YuvFormat::Yuyv, yuv_options)),
+            _ => Err(format!(
+                "unknown raw format {name}, expected rgba, rgb, bgra, argb, abgr, nv12, i420 or yuyv"
+            )
+            .into()),
+        }
+    }
+
+    fn frame_size(self, width: u32, height: u32) -> usize {
+        let pixel_count = width as usize * height as usize;
+        match self {
+            RawFormat::Rgb => pixel_count * 3,
+            RawFormat::Packed(_) => pixel_count * 4,
+            RawFormat::Yuv(format, _) => format.frame_size(width as usize, height as usize),
+        }
+    }
+
+    /// The channel order of the pixels `to_pixels` unpacks, which the encoder and decoder handle themselves
+    fn layout(self) -> PixelLayout {
+        match self {
+            RawFormat::Rgb | RawFormat::Yuv(..) => PixelLayout::Rgba,
+            RawFormat::Packed(layout) => layout,
+        }
+    }
+
+    /// Unpacks a raw frame into `pixels` in `layout()` order, reusing its allocation
+    fn to_pixels(self, frame: &[u8], pixels: &mut Vec<RGBA>) {
+        pixels.clear();
+        match self {
+            RawFormat::Rgb => pixels.extend(
+                frame
+                    .chunks_exact(3)
+                    .map(|raw| RGBA::from_ne_bytes([raw[0], raw[1], raw[2], 0xff])),
+            ),
+            RawFormat::Packed(_) => pixels.extend(
+                frame
+                    .chunks_exact(4)
+                    .map(|raw| RGBA::from_ne_bytes([raw[0], raw[1], raw[2], raw[3]])),
+            ),
+            RawFormat::Yuv(..) => unreachable!("YUV frames are converted as they're encoded"),
+        }
+    }
+}
+
+/// Where the frames go
+enum Sink<W: Write> {
+    Stream(W),
+    /// A file name pattern, with `{}` standing for the frame number
+    Files(String),
+}
+
+impl<W: Write> Sink<W> {
+    fn write_frame(&mut self, frame_number: usize, data: &[u8]) -> CliResult<()> {
+        match self {
+            Sink::Stream(writer) => writer.write_all(data)?,
+            Sink::Files(pattern) => {
+                let path = pattern.replace("{}", &format!("{frame_number:06}"));
+                std::fs::write(&path, data).map_err(|why| format!("{path}: {why}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> CliResult<()> {
+        if let Sink::Stream(writer) = self {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads until `buffer` is full, returning `false` if the input ended before the first byte
+fn read_frame(input: &mut impl Read, buffer: &mut [u8], frame_number: usize) -> CliResult<bool> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match input.read(&mut buffer[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => {
+                return Err(format!(
+                "the input ended partway through frame {frame_number}, after {filled} of {} bytes",
+                buffer.len()
+            )
+                .into())
+            }
+            Ok(read) => filled += read,
+            Err(why) if why.kind() == ErrorKind::Interrupted => (),
+            Err(why) => return Err(why.into()),
+        }
+    }
+    Ok(true)
+}
+
+fn encode_frames(
+    format: RawFormat,
+    header: QOIHeader,
+    input: &mut impl Read,
+    sink: &mut Sink<impl Write>,
+) -> CliResult<usize> {
+    let mut encoder = Encoder::new();
+    let mut frame = vec![0; format.frame_size(header.width, header.height)];
+    let mut pixels = Vec::with_capacity(header.image_size());
+    let mut qoi_data = Vec::new();
+
+    let mut frame_number = 0;
+    while read_frame(input, &mut frame, frame_number)? {
+        qoi_data.clear();
+        match format {
+            RawFormat::Yuv(yuv_format, options) => {
+                encoder.encode_yuv(&frame, &mut qoi_data, header, yuv_format, options)
+            }
+            _ => {
+                format.to_pixels(&frame, &mut pixels);
+                encoder.encode_with_layout(&pixels, &mut qoi_data, header, format.layout())
+            }
+        }
+        .map_err(|(found, expected)| format!("expected {expected} pixels, found {found}"))?;
+        sink.write_frame(frame_number, &qoi_data)?;
+        frame_number += 1;
+    }
+    Ok(frame_number)
+}
+
+/// Finds where the QOI image at the start of a growing buffer ends, along with any metadata chunks and
+/// integrity trailer after it, which run up to the next image or the end of the input.
+/// It reads ops with `QoiOp::parse`, and remembers how far it got so that more data can arrive between calls.
+/// The image is only sized up here, it's still validated before it's decoded.
+struct ImageScanner {
+    position: usize,
+    pixels: usize,
+    /// Whether the end marker has been passed, so that what follows is extensions or the next image
+    after_end: bool,
+}
+
+impl ImageScanner {
+    fn new() -> Self {
+        Self {
+            position: HEADER_SIZE,
+            pixels: 0,
+            after_end: false,
+        }
+    }
+
+    /// The length of the image, or `None` if `data` doesn't hold all of it yet.
+    /// `more_coming` says whether more data can still arrive, as without it what's left belongs to this image.
+    fn image_length(
+        &mut self,
+        data: &[u8],
+        image_size: usize,
+        more_coming: bool,
+    ) -> Result<Option<usize>, OpError> {
+        while self.pixels < image_size {
+            let Some(op) = QoiOp::parse(&data[self.position..]) else {
+                return Ok(None);
+            };
+            if op.pixel_count() > image_size - self.pixels {
+                return Err(OpError::RunOverflow(self.position));
+            }
+            self.position += op.size();
+            self.pixels += op.pixel_count();
+        }
+        if !self.after_end {
+            let Some(marker) = data.get(self.position..self.position + END_8.len()) else {
+                return Ok(None);
+            };
+            if marker != END_8 {
+                return Err(OpError::MissingEndMarker(self.position));
+            }
+            self.position += END_8.len();
+            self.after_end = true;
+        }
+
+        loop {
+            let rest = &data[self.position..];
+            if (rest.is_empty() && !more_coming) || rest.starts_with(&MAGIC_QOIF) {
+                return Ok(Some(self.position));
+            }
+            // a chunk, the footer after the chunks or the trailer, each of which starts with a tag and a length
+            let size = match rest.get(..8) {
+                Some(tag) if tag[..4] == TRAILER_TAG => TRAILER_SIZE,
+                Some(tag) if tag[..4] == METADATA_TAG => FOOTER_SIZE,
+                Some(header) => 8 + u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize,
+                None => usize::MAX,
+            };
+            match rest.len() >= size {
+                true => self.position += size,
+                // whatever is left is validated along with the image
+                false if !more_coming => return Ok(Some(data.len())),
+                false => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Reads more of `input` onto the end of `buffer`, returning `false` at the end of the input
+fn read_more(input: &mut impl Read, buffer: &mut Vec<u8>) -> CliResult<bool> {
+    let start = buffer.len();
+    buffer.resize(start + (1 << 16), 0);
+    loop {
+        match input.read(&mut buffer[start..]) {
+            Ok(read) => {
+                buffer.truncate(start + read);
+                return Ok(read > 0);
+            }
+            Err(why) if why.kind() == ErrorKind::Interrupted => (),
+            Err(why) => return Err(why.into()),
+        }
+    }
+}
+
+fn decode_frames(
+    format: RawFormat,
+    size: (Option<u32>, Option<u32>),
+    input: &mut impl Read,
+    sink: &mut Sink<impl Write>,
+) -> CliResult<usize> {
+    let mut buffer = Vec::new();
+    let mut frame = Vec::new();
+    let mut frame_number = 0;
+
+    loop {
+        while buffer.len() < HEADER_SIZE {
+            if !read_more(input, &mut buffer)? {
+                return match buffer.is_empty() {
+                    true => Ok(frame_number),
+                    false => {
+                        Err(format!("the input ended partway through image {frame_number}").into())
+                    }
+                };
+            }
+        }
+        if buffer[..4] != MAGIC_QOIF {
+            return Err(format!("image {frame_number} doesn't start with a QOI header").into());
+        }
+        let header = QOIHeader::from(buffer.as_slice());
+        let expected = (
+            size.0.unwrap_or(header.width),
+            size.1.unwrap_or(header.height),
+        );
+        if (header.width, header.height) != expected {
+            return Err(format!(
+                "image {frame_number} is {}x{}, but the frames are {}x{}",
+                header.width, header.height, expected.0, expected.1
+            )
+            .into());
+        }
+
+        let mut scanner = ImageScanner::new();
+        let mut more_coming = true;
+        let length = loop {
+            let length = scanner
+                .image_length(&buffer, header.image_size(), more_coming)
+                .map_err(|why| format!("image {frame_number}: {why}"))?;
+            if let Some(length) = length {
+                break length;
+            }
+            if !more_coming {
+                return Err(format!("the input ended partway through image {frame_number}").into());
+            }
+            more_coming = read_more(input, &mut buffer)?;
+        };
+
+        // the decoder trusts its input, so the whole image is checked first
+        let image = &buffer[..length];
+        validate(image).map_err(|why| format!("image {frame_number}: {why}"))?;
+        frame.clear();
+        match format {
+            RawFormat::Yuv(yuv_format, options) => {
+                decode_yuv(image, &mut frame, yuv_format, options)
+            }
+            _ => {
+                let options = DecodeOptions {
+                    layout: format.layout(),
+                    ..DecodeOptions::default()
+                };
+                decode_to_bytes_with_options(image, &mut frame, options)
+            }
+        }
+        .map_err(|(found, expected)| {
+            format!("image {frame_number}: expected {expected} pixels, found {found}")
+        })?;
+        if format == RawFormat::Rgb {
+            compact_rgba8_to_rgb8(&mut frame);
+        }
+        sink.write_frame(frame_number, &frame)?;
+
+        buffer.drain(..length);
+        frame_number += 1;
+    }
+}
+
+pub(crate) fn run(args: impl Iterator<Item = String>) -> CliResult<()> {
+    let args = Args::parse(
+        args,
+        &[
+            "--width",
+            "--height",
+            "--format",
+            "--matrix",
+            "--range",
+            "--channels",
+            "--output",
+        ],
+    )?;
+    args.only_allow(&[
+        "--width",
+        "--height",
+        "--format",
+        "--matrix",
+        "--range",
+        "--channels",
+        "--output",
+        "--linear",
+        "--decode",
+    ])?;
+    let yuv_options = YuvOptions {
+        matrix: match args.value::<String>("--matrix")?.as_deref() {
+            None | Some("601") => YuvMatrix::Bt601,
+            Some("709") => YuvMatrix::Bt709,
+            Some(other) => {
+                return Err(format!("unknown YUV matrix {other}, expected 601 or 709").into())
+            }
+        },
+        range: match args.value::<String>("--range")?.as_deref() {
+            None | Some("limited") => YuvRange::Limited,
+            Some("full") => YuvRange::Full,
+            Some(other) => {
+                return Err(format!("unknown YUV range {other}, expected limited or full").into())
+            }
+        },
+    };
+    let format = RawFormat::parse(
+        &args.value::<String>("--format")?.unwrap_or("rgba".into()),
+        yuv_options,
+    )?;
+    let (width, height) = (
+        args.value::<u32>("--width")?,
+        args.value::<u32>("--height")?,
+    );
+
+    let mut input = std::io::stdin().lock();
+    let mut sink = match args.value::<String>("--output")? {
+        Some(pattern) if pattern.contains("{}") => Sink::Files(pattern),
+        Some(pattern) => {
+            return Err(format!("--output {pattern} needs a {{}} for the frame number").into())
+        }
+        None => Sink::Stream(BufWriter::new(std::io::stdout().lock())),
+    };
+
+    let frames = if args.flag("--decode") {
+        decode_frames(format, (width, height), &mut input, &mut sink)
+    } else {
+        let (Some(width), Some(height)) = (width, height) else {
+            return Err("--width and --height are needed to split the input into frames".into());
+        };
+        let has_alpha = match args.value::<u8>("--channels")? {
+            Some(channels @ (3 | 4)) => channels == 4,
+            Some(other) => return Err(format!("QOI has 3 or 4 channels, not {other}").into()),
+            None => matches!(format, RawFormat::Packed(_)),
+        };
+        let header = QOIHeader {
+            width,
+            height,
+            has_alpha,
+            linear_rgb: args.flag("--linear"),
+        };
+        encode_frames(format, header, &mut input, &mut sink)
+    };
+
+    let frames = match frames {
+        // whatever reads stdout stopped early, which is its call to make
+        Err(why) if is_broken_pipe(why.as_ref()) => return Ok(()),
+        result => result?,
+    };
+    sink.flush()?;
+    eprintln!("hardqoi: {frames} frames");
+    Ok(())
+}
+
+fn is_broken_pipe(why: &(dyn std::error::Error + 'static)) -> bool {
+    why.downcast_ref::<std::io::Error>()
+        .is_some_and(|why| why.kind() == ErrorKind::BrokenPipe)
+}
@@ -0,0 +1,177 @@
+use std::fmt::Write;
+use std::ops::Range;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use hardqoi::common::{QOIHeader, RGBA};
+use hardqoi::{decode, validate, OpError};
+
+use crate::args::Args;
+use crate::CliResult;
+
+pub(crate) const USAGE: &str = "\
+hardqoi view <file.qoi> [options]
+    Previews an image in the terminal with 24-bit color half blocks, shrunk to fit,
+    with transparency shown over a checkerboard, and the header underneath.
+    --width <columns>   the width to fit into, instead of the terminal's
+    --height <rows>     the height to fit into, instead of the terminal's";
+
+/// Lines under the picture, kept free so the header doesn't scroll it away
+const FOOTER_LINES: usize = 2;
+const CHECKER_SIZE: usize = 4;
+const CHECKER_LIGHT: u8 = 0x99;
+const CHECKER_DARK: u8 = 0x66;
+
+/// The terminal size in columns and rows, from `$COLUMNS`/`$LINES` or `stty`, or 80x24 as a last resort
+fn terminal_size() -> (usize, usize) {
+    let from_env = |name| std::env::var(name).ok()?.parse().ok();
+    if let (Some(columns), Some(rows)) = (from_env("COLUMNS"), from_env("LINES")) {
+        return (columns, rows);
+    }
+
+    let from_stty = || {
+        let tty = std::fs::File::open("/dev/tty").ok()?;
+        let output = Command::new("stty")
+            .arg("size")
+            .stdin(tty)
+            .stderr(Stdio::null())
+            .output()
+            .ok()?;
+        let output = String::from_utf8(output.stdout).ok()?;
+        let (rows, columns) = output.trim().split_once(' ')?;
+        Some((columns.parse().ok()?, rows.parse().ok()?))
+    };
+    from_stty().unwrap_or((80, 24))
+}
+
+/// The source pixels that output pixel `index` covers along one axis, at least one of them
+fn source_span(index: usize, output_length: usize, source_length: usize) -> Range<usize> {
+    let start = index * source_length / output_length;
+    let end = (index + 1) * source_length / output_length;
+    start..end.max(start + 1)
+}
+
+/// Shrinks the image to fit in `max_width` by `max_height` pixels, keeping its aspect ratio.
+/// Each output pixel averages the block of source pixels it covers, weighted by alpha,
+/// and is then composited over a checkerboard.
+fn downscale(
+    header: &QOIHeader,
+    pixels: &[RGBA],
+    max_width: usize,
+    max_height: usize,
+) -> (usize, usize, Vec<[u8; 3]>) {
+    let (width, height) = (header.width as usize, header.height as usize);
+    if width == 0 || height == 0 {
+        return (0, 0, Vec::new());
+    }
+    let scale = f64::max(
+        1.0,
+        f64::max(
+            width as f64 / max_width as f64,
+            height as f64 / max_height as f64,
+        ),
+    );
+    let out_width = ((width as f64 / scale) as usize).clamp(1, width);
+    let out_height = ((height as f64 / scale) as usize).clamp(1, height);
+
+    let mut output = Vec::with_capacity(out_width * out_height);
+    for out_y in 0..out_height {
+        let rows = source_span(out_y, out_height, height);
+        for out_x in 0..out_width {
+            let columns = source_span(out_x, out_width, width);
+
+            // premultiplied sums, so transparent pixels don't bleed their color into the average
+            let mut sums = [0u64; 4];
+            for y in rows.clone() {
+                for &pixel in &pixels[y * width + columns.start..y * width + columns.end] {
+                    let [r, g, b, a] = pixel.to_ne_bytes();
+                    let alpha = a as u64;
+                    sums[0] += r as u64 * alpha;
+                    sums[1] += g as u64 * alpha;
+                    sums[2] += b as u64 * alpha;
+                    sums[3] += alpha;
+                }
+            }
+            let count = (rows.len() * columns.len()) as u64;
+            let checker = match (out_x / CHECKER_SIZE + out_y / CHECKER_SIZE) % 2 {
+                0 => CHECKER_LIGHT,
+                _ => CHECKER_DARK,
+            } as u64;
+            // color = premultiplied average + checker * (1 - average alpha), all out of 255
+            let background = checker * (255 * count - sums[3]);
+            let composite = |sum: u64| ((sum + background) / (255 * count)) as u8;
+            output.push([composite(sums[0]), composite(sums[1]), composite(sums[2])]);
+        }
+    }
+    (out_width, out_height, output)
+}
+
+/// Draws two rows of pixels per line of text, using the upper half block
+/// with the top pixel as the foreground and the bottom one as the background
+fn render(width: usize, height: usize, pixels: &[[u8; 3]]) -> String {
+    let mut text = String::new();
+    for y in (0..height).step_by(2) {
+        for x in 0..width {
+            let [r, g, b] = pixels[y * width + x];
+            write!(text, "\x1b[38;2;{r};{g};{b}m").unwrap();
+            // an odd height leaves the last bottom half showing the terminal's own background
+            match pixels.get((y + 1) * width + x) {
+                Some([r, g, b]) => write!(text, "\x1b[48;2;{r};{g};{b}m").unwrap(),
+                None => text.push_str("\x1b[49m"),
+            }
+            text.push('\u{2580}');
+        }
+        text.push_str("\x1b[0m\n");
+    }
+    text
+}
+
+pub(crate) fn run(args: impl Iterator<Item = String>) -> CliResult<()> {
+    let args = Args::parse(args, &["--width", "--height"])?;
+    args.only_allow(&["--width", "--height"])?;
+    let path = Path::new(args.positional(0, "file.qoi")?);
+
+    let data = std::fs::read(path).map_err(|why| format!("{}: {why}", path.display()))?;
+    // the decoder trusts its input, so check it first, and only hand it the image itself
+    let stream = match validate(&data) {
+        Ok(_) => &data[..],
+        Err(OpError::TrailingBytes(end)) => &data[..end],
+        Err(why) => return Err(format!("{}: {why}", path.display()).into()),
+    };
+    let header = QOIHeader::from(stream);
+    let mut pixels = Vec::with_capacity(header.image_size());
+    decode(stream, &mut pixels).map_err(|(found, expected)| {
+        format!(
+            "{}: expected {expected} pixels, found {found} pixels instead",
+            path.display()
+        )
+    })?;
+
+    let (columns, rows) = match (
+        args.value::<usize>("--width")?,
+        args.value::<usize>("--height")?,
+    ) {
+        (Some(columns), Some(rows)) => (columns, rows),
+        (columns, rows) => {
+            let (terminal_columns, terminal_rows) = terminal_size();
+            let rows = rows.unwrap_or(terminal_rows.saturating_sub(FOOTER_LINES));
+            (columns.unwrap_or(terminal_columns), rows)
+        }
+    };
+    if columns == 0 || rows == 0 {
+        return Err("there's no room to draw the image".into());
+    }
+
+    let (width, height, preview) = downscale(&header, &pixels, columns, rows * 2);
+    print!("{}", render(width, height, &preview));
+    println!(
+        "{}: {}x{}, {} channels, {}, {} bytes, shown at {width}x{height}",
+        path.display(),
+        header.width,
+        header.height,
+        3 + header.has_alpha as u8,
+        if header.linear_rgb { "linear" } else { "sRGB" },
+        data.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,59 @@
+/*
+ * hardqoi C bindings, declaring the same API as the reference qoi.h
+ * (https://github.com/phoboslab/qoi). Link against libqoi from the hardqoi-ffi
+ * crate instead of defining QOI_IMPLEMENTATION.
+ *
+ * Buffers returned by qoi_encode, qoi_decode and qoi_read are allocated with
+ * malloc() and must be released with free().
+ *
+ * Regenerate from ffi/src/lib.rs with `cbindgen --config ffi/cbindgen.toml -o ffi/include/qoi.h ffi`
+ * whenever the exported functions change.
+ */
+
+#ifndef QOI_H
+#define QOI_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define QOI_SRGB 0
+#define QOI_LINEAR 1
+
+typedef struct {
+  unsigned int width;
+  unsigned int height;
+  unsigned char channels;
+  unsigned char colorspace;
+} qoi_desc;
+
+/*
+ * Encodes raw RGB or RGBA pixels described by `desc` into a newly allocated QOI image,
+ * storing its size in `out_len`. Returns NULL on failure.
+ */
+void *qoi_encode(const void *data, const qoi_desc *desc, int *out_len);
+
+/*
+ * Decodes `size` bytes of QOI data into newly allocated raw pixels, filling in `desc` from the header.
+ * `channels` chooses 3 or 4 output channels, or 0 to use the channel count in the header.
+ * Returns NULL on failure.
+ */
+void *qoi_decode(const void *data, int size, qoi_desc *desc, int channels);
+
+/*
+ * Encodes raw pixels and writes them to the file at `filename`.
+ * Returns the number of bytes written, or 0 on failure.
+ */
+int qoi_write(const char *filename, const void *data, const qoi_desc *desc);
+
+/*
+ * Reads and decodes the QOI file at `filename` into newly allocated raw pixels, as with `qoi_decode`.
+ * Returns NULL on failure.
+ */
+void *qoi_read(const char *filename, qoi_desc *desc, int channels);
+
+#ifdef __cplusplus
+} /* extern "C" */
+#endif
+
+#endif /* QOI_H */
@@ -0,0 +1,246 @@
+//! A C ABI matching the reference `qoi.h`, so that C and C++ programs can swap in hardqoi by relinking.
+//! Buffers handed back to C are allocated with `malloc` and are released with `free`, as with `QOI_FREE`.
+#![allow(non_camel_case_types)]
+
+use std::ffi::{c_char, c_int, c_uchar, c_uint, c_void, CStr};
+use std::panic::{catch_unwind, UnwindSafe};
+use std::ptr::null_mut;
+use std::slice;
+
+use hardqoi::common::{
+    compact_rgba8_to_rgb8, rgb8_to_pixels, rgba8_to_pixels, QOIHeader, MAGIC_QOIF,
+};
+use hardqoi::{decode_to_bytes, encode, validate, OpError};
+
+pub const QOI_SRGB: c_uchar = 0;
+pub const QOI_LINEAR: c_uchar = 1;
+
+const QOI_HEADER_SIZE: usize = 14;
+const QOI_PADDING_SIZE: usize = 8;
+const QOI_PIXELS_MAX: c_uint = 400_000_000;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct qoi_desc {
+    pub width: c_uint,
+    pub height: c_uint,
+    pub channels: c_uchar,
+    pub colorspace: c_uchar,
+}
+
+impl qoi_desc {
+    fn is_valid(&self) -> bool {
+        self.width != 0
+            && self.height != 0
+            && (3..=4).contains(&self.channels)
+            && self.colorspace <= QOI_LINEAR
+            && self.height < QOI_PIXELS_MAX / self.width
+    }
+}
+
+impl From<&qoi_desc> for QOIHeader {
+    fn from(desc: &qoi_desc) -> Self {
+        Self {
+            width: desc.width,
+            height: desc.height,
+            has_alpha: desc.channels == 4,
+            linear_rgb: desc.colorspace == QOI_LINEAR,
+        }
+    }
+}
+
+extern "C" {
+    fn malloc(size: usize) -> *mut c_void;
+}
+
+/// Copies `bytes` into a buffer owned by the C allocator
+fn into_malloc(bytes: &[u8]) -> *mut c_void {
+    unsafe {
+        let buffer = malloc(bytes.len());
+        if !buffer.is_null() {
+            buffer.copy_from_nonoverlapping(bytes.as_ptr() as *const c_void, bytes.len());
+        }
+        buffer
+    }
+}
+
+/// Panics must not unwind into C, so they become the same failure as any other error
+fn catch<T>(f: impl FnOnce() -> Option<T> + UnwindSafe) -> Option<T> {
+    catch_unwind(f).ok().flatten()
+}
+
+fn encode_to_vec(pixels: &[u8], desc: &qoi_desc) -> Option<Vec<u8>> {
+    if !desc.is_valid() {
+        return None;
+    }
+    let header = QOIHeader::from(desc);
+    let channels = desc.channels as usize;
+    let pixels = &pixels[..header.image_size() * channels];
+    let pixels = if channels == 4 {
+        rgba8_to_pixels(pixels)
+    } else {
+        rgb8_to_pixels(pixels)
+    };
+
+    let mut qoi_data = Vec::with_capacity(pixels.len() / 4);
+    encode(&pixels, &mut qoi_data, header).ok()?;
+    Some(qoi_data)
+}
+
+fn decode_to_vec(qoi_data: &[u8], channels: c_int) -> Option<(qoi_desc, Vec<u8>)> {
+    if !(channels == 0 || channels == 3 || channels == 4)
+        || qoi_data.len() < QOI_HEADER_SIZE + QOI_PADDING_SIZE
+        || qoi_data[0..4] != MAGIC_QOIF
+    {
+        return None;
+    }
+    let desc = qoi_desc {
+        width: u32::from_be_bytes(qoi_data[4..8].try_into().unwrap()),
+        height: u32::from_be_bytes(qoi_data[8..12].try_into().unwrap()),
+        channels: qoi_data[12],
+        colorspace: qoi_data[13],
+    };
+    if !desc.is_valid() {
+        return None;
+    }
+    // the decoder trusts its input, but like the reference one, it doesn't mind data after the end,
+    // as long as it only sees the data up to the end marker
+    let qoi_data = match validate(qoi_data) {
+        Ok(_) => qoi_data,
+        Err(OpError::TrailingBytes(end)) => &qoi_data[..end],
+        Err(_) => return None,
+    };
+
+    let mut pixels = Vec::new();
+    decode_to_bytes(qoi_data, &mut pixels).ok()?;
+
+    let channels = if channels == 0 {
+        desc.channels as c_int
+    } else {
+        channels
+    };
+    if channels == 3 {
+        compact_rgba8_to_rgb8(&mut pixels);
+    }
+    Some((desc, pixels))
+}
+
+/// Encodes raw RGB or RGBA pixels described by `desc` into a newly allocated QOI image,
+/// storing its size in `out_len`. Returns NULL on failure.
+///
+/// # Safety
+/// `data` must point to `width * height * channels` bytes, and `desc` and `out_len` must be valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn qoi_encode(
+    data: *const c_void,
+    desc: *const qoi_desc,
+    out_len: *mut c_int,
+) -> *mut c_void {
+    if data.is_null() || desc.is_null() || out_len.is_null() {
+        return null_mut();
+    }
+    let desc = *desc;
+    let encoded = catch(|| {
+        if !desc.is_valid() {
+            return None;
+        }
+        let byte_count = desc.width as usize * desc.height as usize * desc.channels as usize;
+        let pixels = slice::from_raw_parts(data as *const u8, byte_count);
+        encode_to_vec(pixels, &desc)
+    });
+
+    match encoded {
+        Some(qoi_data) => {
+            let buffer = into_malloc(&qoi_data);
+            if !buffer.is_null() {
+                *out_len = qoi_data.len() as c_int;
+            }
+            buffer
+        }
+        None => null_mut(),
+    }
+}
+
+/// Decodes `size` bytes of QOI data into newly allocated raw pixels, filling in `desc` from the header.
+/// `channels` chooses 3 or 4 output channels, or 0 to use the channel count in the header.
+/// Returns NULL on failure.
+///
+/// # Safety
+/// `data` must point to `size` readable bytes and `desc` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn qoi_decode(
+    data: *const c_void,
+    size: c_int,
+    desc: *mut qoi_desc,
+    channels: c_int,
+) -> *mut c_void {
+    if data.is_null() || desc.is_null() || size < 0 {
+        return null_mut();
+    }
+    let qoi_data = slice::from_raw_parts(data as *const u8, size as usize);
+    match catch(|| decode_to_vec(qoi_data, channels)) {
+        Some((decoded_desc, pixels)) => {
+            *desc = decoded_desc;
+            into_malloc(&pixels)
+        }
+        None => null_mut(),
+    }
+}
+
+/// Encodes raw pixels and writes them to the file at `filename`.
+/// Returns the number of bytes written, or 0 on failure.
+///
+/// # Safety
+/// `filename` must be a NUL terminated string, and `data` and `desc` must be valid as for `qoi_encode`.
+#[no_mangle]
+pub unsafe extern "C" fn qoi_write(
+    filename: *const c_char,
+    data: *const c_void,
+    desc: *const qoi_desc,
+) -> c_int {
+    if filename.is_null() || data.is_null() || desc.is_null() {
+        return 0;
+    }
+    let desc = *desc;
+    let written = catch(|| {
+        if !desc.is_valid() {
+            return None;
+        }
+        let path = CStr::from_ptr(filename).to_str().ok()?;
+        let byte_count = desc.width as usize * desc.height as usize * desc.channels as usize;
+        let pixels = slice::from_raw_parts(data as *const u8, byte_count);
+
+        let qoi_data = encode_to_vec(pixels, &desc)?;
+        std::fs::write(path, &qoi_data).ok()?;
+        Some(qoi_data.len() as c_int)
+    });
+    written.unwrap_or(0)
+}
+
+/// Reads and decodes the QOI file at `filename` into newly allocated raw pixels, as with `qoi_decode`.
+/// Returns NULL on failure.
+///
+/// # Safety
+/// `filename` must be a NUL terminated string and `desc` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn qoi_read(
+    filename: *const c_char,
+    desc: *mut qoi_desc,
+    channels: c_int,
+) -> *mut c_void {
+    if filename.is_null() || desc.is_null() {
+        return null_mut();
+    }
+    let decoded = catch(|| {
+        let path = CStr::from_ptr(filename).to_str().ok()?;
+        let qoi_data = std::fs::read(path).ok()?;
+        decode_to_vec(&qoi_data, channels)
+    });
+    match decoded {
+        Some((decoded_desc, pixels)) => {
+            *desc = decoded_desc;
+            into_malloc(&pixels)
+        }
+        None => null_mut(),
+    }
+}
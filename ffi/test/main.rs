@@ -0,0 +1,74 @@
+extern crate image;
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use hardqoi::encode_dynamic_image;
+
+fn manifest_dir() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Cargo builds the static library into the same `deps` directory as this test binary,
+/// and only copies it up into the profile directory on `cargo build`
+fn static_library() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    let deps_dir = exe.parent().unwrap();
+    [deps_dir, deps_dir.parent().unwrap()]
+        .iter()
+        .map(|dir| dir.join("libqoi.a"))
+        .find(|library| library.exists())
+        .expect("libqoi.a has not been built")
+}
+
+fn compile_roundtrip(out_dir: &Path) -> PathBuf {
+    let program = out_dir.join("roundtrip");
+    let status = Command::new("cc")
+        .arg("-Wall")
+        .arg("-I")
+        .arg(manifest_dir().join("include"))
+        .arg(manifest_dir().join("test/roundtrip.c"))
+        .arg(static_library())
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&program)
+        .status()
+        .expect("Unable to run the C compiler");
+    assert!(status.success(), "Compiling roundtrip.c failed");
+    program
+}
+
+#[test]
+fn test_c_roundtrip() {
+    let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("hardqoi-ffi");
+    std::fs::create_dir_all(&out_dir).unwrap();
+    let program = compile_roundtrip(&out_dir);
+
+    let image_dir = manifest_dir().join("../test");
+    for entry in std::fs::read_dir(image_dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "png") {
+            continue;
+        }
+        let name = path.file_stem().unwrap().to_str().unwrap();
+
+        let img = image::open(&path).unwrap();
+        for (suffix, img) in [
+            ("rgba", img.to_rgba8().into()),
+            ("rgb", img.to_rgb8().into()),
+        ] {
+            let qoi_path = out_dir.join(format!("{name}-{suffix}.qoi"));
+            std::fs::write(&qoi_path, encode_dynamic_image(&img).unwrap()).unwrap();
+
+            let output = Command::new(&program)
+                .arg(&qoi_path)
+                .arg(out_dir.join(format!("{name}-{suffix}-rewritten.qoi")))
+                .output()
+                .unwrap();
+            assert!(
+                output.status.success(),
+                "{}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    }
+}
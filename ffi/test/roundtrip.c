@@ -0,0 +1,101 @@
+/*
+ * Round-trips a QOI image through every function in qoi.h:
+ *   roundtrip <input.qoi> <scratch.qoi>
+ * The input must have been written by hardqoi, so that re-encoding it reproduces it byte for byte.
+ */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "qoi.h"
+
+#define CHECK(condition, message)                                     \
+    do {                                                              \
+        if (!(condition)) {                                           \
+            fprintf(stderr, "%s: %s\n", argv[1], message);            \
+            return 1;                                                 \
+        }                                                             \
+    } while (0)
+
+static void *read_file(const char *path, int *size) {
+    FILE *f = fopen(path, "rb");
+    if (!f) {
+        return NULL;
+    }
+    fseek(f, 0, SEEK_END);
+    *size = (int)ftell(f);
+    fseek(f, 0, SEEK_SET);
+    void *data = malloc(*size);
+    if (fread(data, 1, *size, f) != (size_t)*size) {
+        free(data);
+        data = NULL;
+    }
+    fclose(f);
+    return data;
+}
+
+static int same_desc(const qoi_desc *a, const qoi_desc *b) {
+    return a->width == b->width && a->height == b->height && a->channels == b->channels &&
+           a->colorspace == b->colorspace;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 3) {
+        fprintf(stderr, "usage: %s <input.qoi> <scratch.qoi>\n", argv[0]);
+        return 2;
+    }
+
+    int file_size;
+    void *file_data = read_file(argv[1], &file_size);
+    CHECK(file_data != NULL, "could not read input");
+
+    qoi_desc desc;
+    void *pixels = qoi_read(argv[1], &desc, 0);
+    CHECK(pixels != NULL, "qoi_read failed");
+    CHECK(desc.channels == 3 || desc.channels == 4, "unexpected channel count");
+    size_t pixel_count = (size_t)desc.width * desc.height;
+    size_t raw_size = pixel_count * desc.channels;
+
+    int encoded_size;
+    void *encoded = qoi_encode(pixels, &desc, &encoded_size);
+    CHECK(encoded != NULL, "qoi_encode failed");
+    CHECK(encoded_size == file_size && memcmp(encoded, file_data, file_size) == 0,
+          "re-encoding does not reproduce the input");
+
+    qoi_desc decoded_desc;
+    void *decoded = qoi_decode(encoded, encoded_size, &decoded_desc, 0);
+    CHECK(decoded != NULL, "qoi_decode failed");
+    CHECK(same_desc(&decoded_desc, &desc), "qoi_decode changed the header");
+    CHECK(memcmp(decoded, pixels, raw_size) == 0, "qoi_decode does not match qoi_read");
+
+    /* ask for the other channel count and check the pixels survive the conversion */
+    int other_channels = desc.channels == 3 ? 4 : 3;
+    unsigned char *converted = qoi_decode(encoded, encoded_size, &decoded_desc, other_channels);
+    CHECK(converted != NULL, "qoi_decode with explicit channels failed");
+    CHECK(decoded_desc.channels == desc.channels, "desc.channels must describe the file, not the output");
+    for (size_t i = 0; i < pixel_count; i++) {
+        const unsigned char *expected = (const unsigned char *)pixels + i * desc.channels;
+        const unsigned char *found = converted + i * other_channels;
+        CHECK(memcmp(expected, found, 3) == 0, "RGB differs after channel conversion");
+        if (other_channels == 4) {
+            CHECK(found[3] == 255, "alpha is not opaque after expanding RGB");
+        }
+    }
+
+    CHECK(qoi_decode(encoded, encoded_size, &decoded_desc, 2) == NULL, "channels = 2 must be rejected");
+    CHECK(qoi_decode(encoded, 10, &decoded_desc, 0) == NULL, "truncated header must be rejected");
+
+    int written = qoi_write(argv[2], pixels, &desc);
+    CHECK(written == file_size, "qoi_write wrote an unexpected number of bytes");
+    qoi_desc reread_desc;
+    void *reread = qoi_read(argv[2], &reread_desc, 0);
+    CHECK(reread != NULL && memcmp(reread, pixels, raw_size) == 0, "qoi_write output does not read back");
+
+    free(reread);
+    free(converted);
+    free(decoded);
+    free(encoded);
+    free(pixels);
+    free(file_data);
+    return 0;
+}
@@ -0,0 +1,69 @@
+//! Conversions between straight alpha, which is what QOI stores, and what compositors and thumbnails want.
+//! All of them round exactly, so that `premultiply(unpremultiply(pixel)) == pixel` for any premultiplied pixel.
+
+use crate::common::RGBA;
+
+/// What the decoder does with alpha as it stores pixels
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AlphaMode {
+    /// Leaves pixels as they are in the image
+    #[default]
+    Straight,
+    /// Multiplies the color channels by alpha
+    Premultiplied,
+    /// Blends pixels over an opaque background color, leaving them all opaque.
+    /// `compact_rgba8_to_rgb8` then makes RGB out of them, as `decode_flattened_rgb` does.
+    Flatten(RGBA),
+}
+
+impl AlphaMode {
+    /// Converts a straight alpha pixel
+    #[inline(always)]
+    pub(crate) fn convert(self, pixel: RGBA) -> RGBA {
+        match self {
+            AlphaMode::Straight => pixel,
+            AlphaMode::Premultiplied => premultiply(pixel),
+            AlphaMode::Flatten(background) => flatten(pixel, background),
+        }
+    }
+}
+
+/// `x / 255` rounded to the nearest integer, for `x` up to `255 * 255`, which never lands on a half
+const fn div255(x: u32) -> u32 {
+    (x + 128 + ((x + 128) >> 8)) >> 8
+}
+
+pub fn premultiply(pixel: RGBA) -> RGBA {
+    let [r, g, b, a] = pixel.to_ne_bytes();
+    let scale = |channel: u8| div255(channel as u32 * a as u32) as u8;
+    RGBA::from_ne_bytes([scale(r), scale(g), scale(b), a])
+}
+
+/// The straight alpha pixel closest to a premultiplied one. Fully transparent pixels become transparent black,
+/// and channels greater than alpha, which premultiplied pixels can't have, come out as 255.
+pub fn unpremultiply(pixel: RGBA) -> RGBA {
+    let [r, g, b, a] = pixel.to_ne_bytes();
+    if a == 0 {
+        return 0;
+    }
+    let a = a as u32;
+    // round(channel * 255 / a), halves rounding up
+    let unscale = |channel: u8| {
+        let straight = (channel as u32 * 510 + a) / (2 * a);
+        if straight > 255 {
+            255
+        } else {
+            straight as u8
+        }
+    };
+    RGBA::from_ne_bytes([unscale(r), unscale(g), unscale(b), a as u8])
+}
+
+/// Blends `pixel` over the opaque color `background`
+pub fn flatten(pixel: RGBA, background: RGBA) -> RGBA {
+    let [r, g, b, a] = pixel.to_ne_bytes();
+    let [br, bg, bb, _] = background.to_ne_bytes();
+    let (a, inverse) = (a as u32, 255 - a as u32);
+    let blend = |channel: u8, under: u8| div255(channel as u32 * a + under as u32 * inverse) as u8;
+    RGBA::from_ne_bytes([blend(r, br), blend(g, bg), blend(b, bb), 255])
+}
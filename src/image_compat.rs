@@ -0,0 +1,343 @@
+use alloc::format;
+use alloc::vec::Vec;
+use std::io::{Cursor, Read, Write};
+
+use bytemuck::cast_slice;
+use image::error::{DecodingError, EncodingError};
+use image::{
+    ColorType, DynamicImage, ImageBuffer, ImageDecoder, ImageEncoder, ImageError, ImageFormat,
+    ImageResult,
+};
+
+use crate::common::{
+    compact_rgba8_to_rgb8, pixels_to_rgb8, rgb8_to_pixels, rgba8_to_pixels, QOIHeader, MAGIC_QOIF,
+    RGBA,
+};
+use crate::layout::NarrowFormat;
+use crate::metadata::{append_metadata, Chunk, Metadata};
+use crate::ops::{validate, OpError};
+use crate::{decode, decode_to_bytes, encode, encode_narrow};
+
+fn decoding_error(message: impl Into<alloc::string::String>) -> ImageError {
+    ImageError::Decoding(DecodingError::new(ImageFormat::Qoi.into(), message.into()))
+}
+
+fn encoding_error(message: impl Into<alloc::string::String>) -> ImageError {
+    ImageError::Encoding(EncodingError::new(ImageFormat::Qoi.into(), message.into()))
+}
+
+/// Encodes any `DynamicImage` as QOI, with three or four channels depending on
+/// whether its color type has an alpha channel.
+/// Images that are already RGBA8 are encoded in place without converting them first.
+pub fn encode_dynamic_image(img: &DynamicImage) -> Result<Vec<u8>, (usize, usize)> {
+    encode_dynamic_image_with_header(img, QOIHeader::from(img))
+}
+
+/// Encodes a `DynamicImage` as QOI using the channel count and colorspace from `header`.
+/// When `header` has no alpha channel, any alpha in the image is discarded.
+pub fn encode_dynamic_image_with_header(
+    img: &DynamicImage,
+    header: QOIHeader,
+) -> Result<Vec<u8>, (usize, usize)> {
+    // gray images are widened as they're encoded instead of in a copy
+    let narrow = match img {
+        DynamicImage::ImageLuma8(buffer) => Some((buffer.as_raw(), NarrowFormat::L8)),
+        DynamicImage::ImageLumaA8(buffer) if header.has_alpha => {
+            Some((buffer.as_raw(), NarrowFormat::La8))
+        }
+        _ => None,
+    };
+    if let Some((bytes, format)) = narrow {
+        let pixel_count = bytes.len() / format.bytes_per_pixel();
+        if pixel_count != header.image_size() {
+            return Err((pixel_count, header.image_size()));
+        }
+        let mut qoi_data = Vec::with_capacity(pixel_count / 4);
+        encode_narrow(bytes, &mut qoi_data, header, format)?;
+        return Ok(qoi_data);
+    }
+
+    let converted: Vec<RGBA>;
+    let pixels: &[RGBA] = match img {
+        DynamicImage::ImageRgba8(buffer) if header.has_alpha => {
+            match bytemuck::try_cast_slice(buffer.as_raw()) {
+                Ok(pixels) => pixels,
+                Err(_) => {
+                    converted = rgba8_to_pixels(buffer.as_raw());
+                    &converted
+                }
+            }
+        }
+        DynamicImage::ImageRgb8(buffer) => {
+            converted = rgb8_to_pixels(buffer.as_raw());
+            &converted
+        }
+        _ if header.has_alpha => {
+            converted = rgba8_to_pixels(img.to_rgba8().as_raw());
+            &converted
+        }
+        _ => {
+            converted = rgb8_to_pixels(img.to_rgb8().as_raw());
+            &converted
+        }
+    };
+    if pixels.len() != header.image_size() {
+        return Err((pixels.len(), header.image_size()));
+    }
+
+    let mut qoi_data = Vec::with_capacity(pixels.len() / 4);
+    encode(pixels, &mut qoi_data, header)?;
+    Ok(qoi_data)
+}
+
+/// Decodes a QOI image straight into the storage of an `ImageRgba8` or `ImageRgb8`,
+/// chosen by the channel count in its header. The whole stream is validated first, so malformed input is an error.
+pub fn decode_to_dynamic_image(input: &[u8]) -> Result<DynamicImage, OpError> {
+    let header = validate(input)?;
+    let mut bytes = Vec::new();
+    decode_to_bytes(input, &mut bytes).expect("the stream was validated");
+
+    if header.has_alpha {
+        let buffer = ImageBuffer::from_raw(header.width, header.height, bytes).unwrap();
+        Ok(DynamicImage::ImageRgba8(buffer))
+    } else {
+        compact_rgba8_to_rgb8(&mut bytes);
+        let buffer = ImageBuffer::from_raw(header.width, header.height, bytes).unwrap();
+        Ok(DynamicImage::ImageRgb8(buffer))
+    }
+}
+
+/// Reads the colorspace out of a PNG file's `sRGB` and `gAMA` chunks.
+/// Returns `Some(true)` for a gamma of 1.0, `Some(false)` for sRGB or any other gamma,
+/// and `None` if the file doesn't say, or isn't a PNG at all.
+pub fn png_linear_rgb(png_data: &[u8]) -> Option<bool> {
+    const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+    const LINEAR_GAMMA: u32 = 100_000;
+    if png_data.get(0..8)? != PNG_SIGNATURE {
+        return None;
+    }
+
+    let mut gamma = None;
+    let mut position = 8;
+    // chunks are a big endian length, a type, the data and a CRC
+    while let Some(chunk_header) = png_data.get(position..position + 8) {
+        let length = u32::from_be_bytes(chunk_header[0..4].try_into().unwrap()) as usize;
+        let data = png_data.get(position + 8..position + 8 + length)?;
+        match &chunk_header[4..8] {
+            b"sRGB" => return Some(false),
+            b"gAMA" if length == 4 => gamma = Some(u32::from_be_bytes(data.try_into().unwrap())),
+            // colorspace chunks must come before the image data
+            b"IDAT" | b"IEND" => break,
+            _ => (),
+        }
+        position += 12 + length;
+    }
+
+    gamma.map(|gamma| gamma == LINEAR_GAMMA)
+}
+
+/// Reads the ICC profile out of any image the `image` crate can decode, or a QOI image's metadata,
+/// so that it can be carried over when converting to QOI.
+/// Returns `None` if there isn't one, or the format can't hold one.
+pub fn read_icc_profile(data: &[u8]) -> Option<Vec<u8>> {
+    use image::codecs::{jpeg::JpegDecoder, png::PngDecoder, tiff::TiffDecoder, webp::WebPDecoder};
+
+    if data.starts_with(&MAGIC_QOIF) {
+        return Metadata::read(data).ok()?.icc_profile().map(<[u8]>::to_vec);
+    }
+    let reader = Cursor::new(data);
+    match image::guess_format(data).ok()? {
+        ImageFormat::Png => PngDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::Jpeg => JpegDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::WebP => WebPDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::Tiff => TiffDecoder::new(reader).ok()?.icc_profile(),
+        _ => None,
+    }
+}
+
+/// QOI decoder for the `image` crate, backed by hardqoi's SIMD decoding
+pub struct QoiDecoder<R> {
+    header: QOIHeader,
+    data: Vec<u8>,
+    _reader: core::marker::PhantomData<R>,
+}
+
+impl<R: Read> QoiDecoder<R> {
+    /// Reads the entire QOI stream from `reader` and checks all of it, so that a truncated or malformed
+    /// stream is turned away here rather than failing to decode later
+    pub fn new(mut reader: R) -> ImageResult<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let header = validate(&data).map_err(|error| decoding_error(format!("{error}")))?;
+
+        Ok(Self {
+            header,
+            data,
+            _reader: core::marker::PhantomData,
+        })
+    }
+
+    pub fn header(&self) -> QOIHeader {
+        self.header
+    }
+
+    fn decode_pixels(&self) -> ImageResult<Vec<RGBA>> {
+        let mut pixels = Vec::with_capacity(self.header.image_size());
+        match decode(&self.data, &mut pixels) {
+            Ok(()) => Ok(pixels),
+            Err((found, expected)) => Err(decoding_error(format!(
+                "Expected {expected} pixels, found {found} pixels instead"
+            ))),
+        }
+    }
+}
+
+impl<'a, R: Read + 'a> ImageDecoder<'a> for QoiDecoder<R> {
+    type Reader = Cursor<Vec<u8>>;
+
+    fn dimensions(&self) -> (u32, u32) {
+        (self.header.width, self.header.height)
+    }
+
+    fn color_type(&self) -> ColorType {
+        if self.header.has_alpha {
+            ColorType::Rgba8
+        } else {
+            ColorType::Rgb8
+        }
+    }
+
+    fn icc_profile(&mut self) -> Option<Vec<u8>> {
+        Metadata::read(&self.data)
+            .ok()?
+            .icc_profile()
+            .map(<[u8]>::to_vec)
+    }
+
+    fn into_reader(self) -> ImageResult<Self::Reader> {
+        let mut buffer = alloc::vec![0u8; self.total_bytes() as usize];
+        self.read_image(&mut buffer)?;
+        Ok(Cursor::new(buffer))
+    }
+
+    fn read_image(self, buf: &mut [u8]) -> ImageResult<()> {
+        assert_eq!(buf.len() as u64, self.total_bytes());
+        let pixels = self.decode_pixels()?;
+
+        if self.header.has_alpha {
+            buf.copy_from_slice(cast_slice::<RGBA, u8>(&pixels));
+        } else {
+            pixels_to_rgb8(&pixels, buf);
+        }
+        Ok(())
+    }
+}
+
+/// QOI encoder for the `image` crate, backed by hardqoi's SIMD encoding
+pub struct QoiEncoder<W> {
+    writer: W,
+    linear_rgb: bool,
+    metadata: Metadata,
+}
+
+impl<W: Write> QoiEncoder<W> {
+    /// Creates an encoder that writes an sRGB tagged QOI image to `writer`
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            linear_rgb: false,
+            metadata: Metadata::new(),
+        }
+    }
+
+    /// Tags the output as having linear RGB channels instead of sRGB
+    pub fn with_linear_rgb(mut self, linear_rgb: bool) -> Self {
+        self.linear_rgb = linear_rgb;
+        self
+    }
+
+    /// Embeds an ICC profile, such as the one `read_icc_profile` found in the source image
+    pub fn with_icc_profile(mut self, profile: Vec<u8>) -> Self {
+        self.metadata.set(Chunk::IccProfile(profile));
+        self
+    }
+
+    /// Writes `metadata` after the image, replacing anything `with_icc_profile` set
+    pub fn with_metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+
+impl<W: Write> ImageEncoder for QoiEncoder<W> {
+    fn write_image(
+        mut self,
+        buf: &[u8],
+        width: u32,
+        height: u32,
+        color_type: ColorType,
+    ) -> ImageResult<()> {
+        // the narrow formats are widened as they're encoded, RGBA8 is used in place if it's aligned,
+        // and everything else is converted in a copy
+        let converted: Vec<RGBA>;
+        let (pixels, narrow): (&[RGBA], _) = match color_type {
+            ColorType::Rgba8 => match bytemuck::try_cast_slice(buf) {
+                Ok(pixels) => (pixels, None),
+                Err(_) => {
+                    converted = rgba8_to_pixels(buf);
+                    (&converted, None)
+                }
+            },
+            ColorType::Rgb8 => {
+                converted = rgb8_to_pixels(buf);
+                (&converted, None)
+            }
+            ColorType::L8 => (&[], Some(NarrowFormat::L8)),
+            ColorType::La8 => (&[], Some(NarrowFormat::La8)),
+            ColorType::L16 => (&[], Some(NarrowFormat::L16)),
+            _ => {
+                return Err(encoding_error(format!(
+                    "Unsupported color type {color_type:?}. Supported are Rgba8, Rgb8, L8, La8 and L16."
+                )))
+            }
+        };
+        let pixel_count = match narrow {
+            Some(format) => buf.len() / format.bytes_per_pixel(),
+            None => pixels.len(),
+        };
+
+        let header = QOIHeader {
+            width,
+            height,
+            has_alpha: color_type.has_alpha(),
+            linear_rgb: self.linear_rgb,
+        };
+        if pixel_count != header.image_size() {
+            return Err(encoding_error(format!(
+                "Expected {} pixels for a {width}x{height} image, found {pixel_count}",
+                header.image_size(),
+            )));
+        }
+
+        let mut qoi_data = Vec::with_capacity(pixel_count / 4);
+        let result = match narrow {
+            Some(format) => {
+                let bytes = &buf[..pixel_count * format.bytes_per_pixel()];
+                encode_narrow(bytes, &mut qoi_data, header, format)
+            }
+            None => encode(pixels, &mut qoi_data, header),
+        };
+        if let Err((found, expected)) = result {
+            return Err(encoding_error(format!(
+                "Expected {expected} pixels, found {found} pixels instead"
+            )));
+        }
+
+        if !self.metadata.is_empty() {
+            append_metadata(&mut qoi_data, &self.metadata);
+        }
+        self.writer.write_all(&qoi_data)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
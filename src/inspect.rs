@@ -0,0 +1,174 @@
+use crate::common::{HashIndexedArray, QOIHeader, END_8, HASH, MAGIC_QOIF, RGBA};
+use crate::metadata::strip_extensions;
+use crate::ops::{index_position, QoiOp};
+
+const HEADER_SIZE: usize = 14;
+
+/// One op in a QOI stream, along with the decoder state right after it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpRecord {
+    /// Byte offset of the op from the start of the file
+    pub offset: usize,
+    pub op: QoiOp,
+    /// Index of the first pixel the op produces, counting across rows
+    pub pixel_index: usize,
+    /// The pixel the op produces, repeated for runs
+    pub pixel: RGBA,
+    /// The index array slot that now holds `pixel`
+    pub slot: HASH,
+}
+
+impl OpRecord {
+    /// The pixels this op covers, as a range of pixel indices
+    pub fn pixels(&self) -> core::ops::Range<usize> {
+        self.pixel_index..self.pixel_index + self.op.pixel_count()
+    }
+}
+
+/// How an op stream finished, once `OpTrace` has run out of ops
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceEnd {
+    /// Every pixel was produced and the end marker follows directly, possibly with metadata or an integrity trailer after it
+    Complete,
+    /// Every pixel was produced, but the end marker is missing or not where it should be
+    MissingEndMarker,
+    /// The data ran out partway through the image
+    Truncated,
+    /// A run continued past the last pixel of the image
+    RunOverflow,
+}
+
+/// Walks a QOI stream op by op, tracking the state a decoder would have after each one.
+/// Unlike `decode`, it doesn't trust the stream, so it's suitable for looking at broken files.
+pub struct OpTrace<'a> {
+    header: QOIHeader,
+    data: &'a [u8],
+    position: usize,
+    pixel_index: usize,
+    previous_pixel: RGBA,
+    index: HashIndexedArray,
+    end: Option<TraceEnd>,
+}
+
+impl<'a> OpTrace<'a> {
+    /// Returns `None` if `data` doesn't start with a QOI header
+    pub fn new(data: &'a [u8]) -> Option<Self> {
+        if data.len() < HEADER_SIZE || data[0..4] != MAGIC_QOIF {
+            return None;
+        }
+        Some(Self {
+            header: QOIHeader::from(data),
+            data,
+            position: HEADER_SIZE,
+            pixel_index: 0,
+            previous_pixel: 0xff000000,
+            index: [0; 64],
+            end: None,
+        })
+    }
+
+    pub fn header(&self) -> QOIHeader {
+        self.header
+    }
+
+    /// Byte offset of the next op
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// The number of pixels produced so far
+    pub fn pixels_decoded(&self) -> usize {
+        self.pixel_index
+    }
+
+    /// How the stream finished, or `None` while there are still ops to read
+    pub fn end(&self) -> Option<TraceEnd> {
+        self.end
+    }
+
+    fn finish(&mut self, end: TraceEnd) -> Option<OpRecord> {
+        self.end = Some(end);
+        None
+    }
+}
+
+impl Iterator for OpTrace<'_> {
+    type Item = OpRecord;
+
+    fn next(&mut self) -> Option<OpRecord> {
+        if self.end.is_some() {
+            return None;
+        }
+        if self.pixel_index >= self.header.image_size() {
+            let stream = strip_extensions(self.data);
+            return if stream.get(self.position..) == Some(&END_8) {
+                self.finish(TraceEnd::Complete)
+            } else {
+                self.finish(TraceEnd::MissingEndMarker)
+            };
+        }
+
+        let op = match QoiOp::parse(&self.data[self.position..]) {
+            Some(op) => op,
+            None => return self.finish(TraceEnd::Truncated),
+        };
+        let pixel = op.apply(self.previous_pixel, &self.index);
+        let record = OpRecord {
+            offset: self.position,
+            op,
+            pixel_index: self.pixel_index,
+            pixel,
+            slot: index_position(pixel),
+        };
+
+        self.index[record.slot as usize] = record.pixel;
+        self.previous_pixel = record.pixel;
+        self.position += op.size();
+        self.pixel_index += op.pixel_count();
+
+        if self.pixel_index > self.header.image_size() {
+            self.end = Some(TraceEnd::RunOverflow);
+        }
+        Some(record)
+    }
+}
+
+/// Where two QOI streams first stop agreeing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Divergence {
+    /// The headers differ, so the op streams aren't comparable
+    Header(QOIHeader, QOIHeader),
+    /// Every op agrees, but the streams finish differently
+    End(TraceEnd, TraceEnd),
+    /// The ops at the same position in both streams differ, or only one of the streams has an op there.
+    /// The op number counts from zero.
+    Op {
+        op_number: usize,
+        left: Option<OpRecord>,
+        right: Option<OpRecord>,
+    },
+}
+
+/// Compares two QOI streams op by op, returning `None` if they agree all the way to the end
+pub fn first_divergence(mut left: OpTrace, mut right: OpTrace) -> Option<Divergence> {
+    let (left_header, right_header) = (left.header(), right.header());
+    if left_header != right_header {
+        return Some(Divergence::Header(left_header, right_header));
+    }
+
+    let mut op_number = 0;
+    loop {
+        match (left.next(), right.next()) {
+            (None, None) if left.end() == right.end() => return None,
+            (None, None) => return Some(Divergence::End(left.end()?, right.end()?)),
+            (Some(l), Some(r)) if l.op == r.op && l.pixel == r.pixel => op_number += 1,
+            (l, r) => {
+                return Some(Divergence::Op {
+                    op_number,
+                    left: l,
+                    right: r,
+                })
+            }
+        }
+    }
+}
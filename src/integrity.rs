@@ -0,0 +1,118 @@
+//! An optional trailer after the end marker with checksums of the image, since QOI itself has none.
+//! Other decoders skip it the same way as the [metadata chunks](crate::metadata).
+//!
+//! The trailer is 12 bytes: the tag `qcrc`, then the CRC-32C of the decoded pixels as RGBA8,
+//! then the CRC-32C of the rest of the file before the trailer, both big endian like the header.
+//! The second one lets `validate` check a file without decoding it, and `decode_checked` checks the first one,
+//! which catches a decoder going wrong. Any metadata chunks come before the trailer, and are covered by the second one.
+
+use alloc::vec::Vec;
+
+use crate::common::{END_8, RGBA};
+use crate::decode;
+use crate::metadata::split_metadata;
+use crate::ops::{validate, OpError};
+
+pub const TRAILER_TAG: [u8; 4] = *b"qcrc";
+pub const TRAILER_SIZE: usize = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Trailer {
+    pub pixels_crc: u32,
+    pub stream_crc: u32,
+}
+
+impl Trailer {
+    pub fn to_bytes(&self) -> [u8; TRAILER_SIZE] {
+        let mut bytes = [0; TRAILER_SIZE];
+        bytes[0..4].copy_from_slice(&TRAILER_TAG);
+        bytes[4..8].copy_from_slice(&self.pixels_crc.to_be_bytes());
+        bytes[8..12].copy_from_slice(&self.stream_crc.to_be_bytes());
+        bytes
+    }
+
+    /// Reads a trailer that makes up all of `bytes`, the data after the end marker
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != TRAILER_SIZE || bytes[0..4] != TRAILER_TAG {
+            return None;
+        }
+        Some(Self {
+            pixels_crc: u32::from_be_bytes(bytes[4..8].try_into().unwrap()),
+            stream_crc: u32::from_be_bytes(bytes[8..12].try_into().unwrap()),
+        })
+    }
+}
+
+/// The CRC-32C of `data`, using the SSE4.2 instruction when the crate is built with it and the CPU has it
+pub fn crc32c(data: &[u8]) -> u32 {
+    crate::arch_switch::implementation::crc32c::crc32c(data)
+}
+
+/// The CRC-32C of pixels as RGBA8, which is how the trailer covers them whatever the channel count
+pub fn pixels_crc(pixels: &[RGBA]) -> u32 {
+    crc32c(bytemuck::cast_slice(pixels))
+}
+
+/// Appends a trailer to `qoi_data`, which must hold exactly one image, just as `encode` wrote it,
+/// and any metadata for it. `pixels` are the ones it was encoded from.
+pub fn append_trailer(qoi_data: &mut Vec<u8>, pixels: &[RGBA]) {
+    debug_assert!(split_metadata(qoi_data).0.ends_with(&END_8));
+    let trailer = Trailer {
+        pixels_crc: pixels_crc(pixels),
+        stream_crc: crc32c(qoi_data),
+    };
+    qoi_data.extend(trailer.to_bytes());
+}
+
+/// The last 12 bytes of a file and everything before them, if they're shaped like a trailer,
+/// starting with its tag and following the end marker or metadata chunks
+fn tagged_trailer(qoi_data: &[u8]) -> Option<(&[u8], Trailer)> {
+    let before_length = qoi_data.len().checked_sub(TRAILER_SIZE)?;
+    let (before, trailer) = qoi_data.split_at(before_length);
+    let trailer = Trailer::parse(trailer)?;
+    split_metadata(before)
+        .0
+        .ends_with(&END_8)
+        .then_some((before, trailer))
+}
+
+/// Splits a file into everything before its trailer, and the trailer if it has one.
+/// A plain stream can end in ops that look like a trailer, so it's only taken for one
+/// when the checksum of the stream in it matches what comes before.
+pub fn split_trailer(qoi_data: &[u8]) -> (&[u8], Option<Trailer>) {
+    match tagged_trailer(qoi_data) {
+        Some((before, trailer)) if crc32c(before) == trailer.stream_crc => (before, Some(trailer)),
+        _ => (qoi_data, None),
+    }
+}
+
+/// Decodes like `decode`, but checks the whole stream first and the pixels afterwards against the trailer.
+/// Returns whether there was a trailer to check against, and leaves `output` alone if the stream is broken.
+/// The other decode functions don't check the pixels, since most of them convert them as they're stored,
+/// while the trailer covers them as they were encoded.
+pub fn decode_checked(input: &[u8], output: &mut Vec<RGBA>) -> Result<bool, OpError> {
+    validate(input)?;
+    let start = output.len();
+    decode(input, output).expect("the stream was validated");
+
+    let (before, trailer) = split_trailer(input);
+    let Some(trailer) = trailer else {
+        return Ok(false);
+    };
+    if pixels_crc(&output[start..]) != trailer.pixels_crc {
+        output.truncate(start);
+        return Err(OpError::ChecksumMismatch(before.len()));
+    }
+    Ok(true)
+}
+
+/// What's wrong with the data after the stream that ends at `stream_end`, which `split_trailer` didn't take
+/// for a trailer. When it's shaped like one, right after the stream and any metadata, its checksum is what's wrong.
+pub(crate) fn trailing_error(qoi_data: &[u8], stream_end: usize) -> OpError {
+    match tagged_trailer(qoi_data) {
+        Some((before, _)) if split_metadata(before).0.len() == stream_end => {
+            OpError::ChecksumMismatch(before.len())
+        }
+        _ => OpError::TrailingBytes(stream_end),
+    }
+}
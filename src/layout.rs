@@ -0,0 +1,272 @@
+//! The pixel formats the decoder can write and the encoder can read besides QOI's own RGBA,
+//! converted as pixels are stored or hashed rather than in a pass over the whole image.
+
+use alloc::vec::Vec;
+
+use crate::alpha::AlphaMode;
+use crate::arch_switch::implementation::swizzle::swizzle;
+use crate::common::RGBA;
+
+const OPAQUE_BLACK_RGBA: RGBA = RGBA::from_ne_bytes([0, 0, 0, 0xff]);
+const OPAQUE_BLACK_ARGB: RGBA = RGBA::from_ne_bytes([0xff, 0, 0, 0]);
+
+/// The order of the channels in each pixel's bytes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PixelLayout {
+    /// QOI's own order
+    #[default]
+    Rgba,
+    /// For Cairo, Skia and Windows framebuffers on little-endian machines
+    Bgra,
+    Argb,
+    Abgr,
+}
+
+impl PixelLayout {
+    /// For each byte of a pixel in this layout, the byte of the RGBA pixel it comes from
+    pub(crate) const fn order(self) -> [u8; 4] {
+        match self {
+            PixelLayout::Rgba => [0, 1, 2, 3],
+            PixelLayout::Bgra => [2, 1, 0, 3],
+            PixelLayout::Argb => [3, 0, 1, 2],
+            PixelLayout::Abgr => [3, 2, 1, 0],
+        }
+    }
+
+    /// Rearranges an RGBA pixel into this layout
+    pub fn from_rgba(self, pixel: RGBA) -> RGBA {
+        let bytes = pixel.to_ne_bytes();
+        RGBA::from_ne_bytes(self.order().map(|index| bytes[index as usize]))
+    }
+
+    /// Rearranges a pixel in this layout into RGBA
+    pub fn to_rgba(self, pixel: RGBA) -> RGBA {
+        let bytes = pixel.to_ne_bytes();
+        let mut rgba = [0; 4];
+        for (byte, index) in bytes.into_iter().zip(self.order()) {
+            rgba[index as usize] = byte;
+        }
+        RGBA::from_ne_bytes(rgba)
+    }
+
+    /// The pixel before the first one, which QOI takes to be opaque black
+    pub(crate) const fn opaque_black(self) -> &'static RGBA {
+        match self {
+            PixelLayout::Rgba | PixelLayout::Bgra => &OPAQUE_BLACK_RGBA,
+            PixelLayout::Argb | PixelLayout::Abgr => &OPAQUE_BLACK_ARGB,
+        }
+    }
+
+    /// Rearranges RGBA pixels into this layout in place, with SIMD where the CPU has it
+    pub fn swizzle_from_rgba(self, pixels: &mut [RGBA]) {
+        if self != PixelLayout::Rgba {
+            swizzle(pixels, self.order());
+        }
+    }
+}
+
+/// Pixels with fewer channels than QOI's, which the encoder widens to RGBA a chunk at a time as it reads them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NarrowFormat {
+    /// A byte of gray
+    L8,
+    /// A byte of gray, then a byte of alpha
+    La8,
+    /// Native-endian 16-bit gray, of which only the top byte is kept
+    L16,
+    /// Just alpha, over black the way OpenGL's `GL_ALPHA` textures have it
+    A8,
+}
+
+impl NarrowFormat {
+    pub const fn bytes_per_pixel(self) -> usize {
+        match self {
+            NarrowFormat::L8 | NarrowFormat::A8 => 1,
+            NarrowFormat::La8 | NarrowFormat::L16 => 2,
+        }
+    }
+
+    /// Widens the `bytes_per_pixel` bytes of one pixel to RGBA
+    pub fn to_rgba(self, bytes: &[u8]) -> RGBA {
+        let [luma, alpha] = match self {
+            NarrowFormat::L8 => [bytes[0], 0xff],
+            NarrowFormat::La8 => [bytes[0], bytes[1]],
+            NarrowFormat::L16 => [(u16::from_ne_bytes([bytes[0], bytes[1]]) >> 8) as u8, 0xff],
+            NarrowFormat::A8 => [0, bytes[0]],
+        };
+        RGBA::from_ne_bytes([luma, luma, luma, alpha])
+    }
+
+    /// Appends the pixels in `input` to `output` widened to RGBA, picking the format once rather than for each pixel
+    pub(crate) fn widen(self, input: &[u8], output: &mut Vec<RGBA>) {
+        let gray = |luma: u8, alpha: u8| RGBA::from_ne_bytes([luma, luma, luma, alpha]);
+        match self {
+            NarrowFormat::L8 => output.extend(input.iter().map(|&luma| gray(luma, 0xff))),
+            NarrowFormat::La8 => {
+                output.extend(input.chunks_exact(2).map(|bytes| gray(bytes[0], bytes[1])))
+            }
+            NarrowFormat::L16 => output.extend(
+                input
+                    .chunks_exact(2)
+                    .map(|bytes| gray((u16::from_ne_bytes([bytes[0], bytes[1]]) >> 8) as u8, 0xff)),
+            ),
+            NarrowFormat::A8 => output.extend(input.iter().map(|&alpha| gray(0, alpha))),
+        }
+    }
+}
+
+/// A single channel to decode as an 8-bit mask
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Alpha,
+    /// Rec. 601 luma, ignoring alpha
+    Luma,
+}
+
+impl Channel {
+    pub fn extract(self, pixel: RGBA) -> u8 {
+        let [r, g, b, a] = pixel.to_ne_bytes();
+        match self {
+            Channel::Alpha => a,
+            Channel::Luma => ((r as u32 * 77 + g as u32 * 150 + b as u32 * 29 + 128) >> 8) as u8,
+        }
+    }
+}
+
+/// How `decode_with_options` stores pixels, alpha conversion coming before the swizzle.
+/// The decoder converts each pixel as it stores it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DecodeOptions {
+    pub alpha: AlphaMode,
+    pub layout: PixelLayout,
+}
+
+/// Where the decoder hands its pixels when it decodes into a window rather than the whole image
+pub(crate) trait StoreStep {
+    /// Called on each pixel once, in order, a window's worth at a time
+    fn finish(&mut self, pixels: &[RGBA]);
+}
+
+/// Where a `RowStore` has got to in its image
+pub(crate) struct Rows {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    /// How many pixels have been placed so far
+    pub(crate) position: usize,
+}
+
+impl Rows {
+    pub(crate) fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            position: 0,
+        }
+    }
+}
+
+/// A store step that places pixels a row at a time, since a window of them can start and end anywhere
+pub(crate) trait RowStore {
+    fn rows(&mut self) -> &mut Rows;
+
+    /// Places `pixels`, which start at `(x, y)` and don't go past the end of their row
+    fn place_row(&mut self, pixels: &[RGBA], x: usize, y: usize);
+}
+
+impl<T: RowStore> StoreStep for T {
+    fn finish(&mut self, mut pixels: &[RGBA]) {
+        let Rows { width, height, .. } = *self.rows();
+        while !pixels.is_empty() {
+            let position = self.rows().position;
+            // a malformed image can hold more pixels than its header says, which have nowhere to go
+            if width == 0 || position / width >= height {
+                return;
+            }
+            let (x, y) = (position % width, position / width);
+            let (row_part, rest) = pixels.split_at(pixels.len().min(width - x));
+            self.place_row(row_part, x, y);
+            self.rows().position += row_part.len();
+            pixels = rest;
+        }
+    }
+}
+
+/// Copies pixels out to rows `pitch` bytes apart in `output`
+pub(crate) struct PitchedStore<'a> {
+    pub(crate) output: &'a mut [u8],
+    pub(crate) pitch: usize,
+    pub(crate) rows: Rows,
+}
+
+impl RowStore for PitchedStore<'_> {
+    fn rows(&mut self) -> &mut Rows {
+        &mut self.rows
+    }
+
+    fn place_row(&mut self, pixels: &[RGBA], x: usize, y: usize) {
+        let start = y * self.pitch + x * core::mem::size_of::<RGBA>();
+        let bytes: &[u8] = bytemuck::cast_slice(pixels);
+        self.output[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+}
+
+/// Copies one channel of each pixel out to `output`, leaving the pixels alone
+pub(crate) struct ChannelStore<'a> {
+    pub(crate) channel: Channel,
+    pub(crate) output: &'a mut Vec<u8>,
+}
+
+impl StoreStep for ChannelStore<'_> {
+    fn finish(&mut self, pixels: &[RGBA]) {
+        let channel = self.channel;
+        self.output
+            .extend(pixels.iter().map(|&pixel| channel.extract(pixel)));
+    }
+}
+
+/// Writes a byte of gray per pixel for as long as every pixel is opaque gray,
+/// then at the first one that isn't, widens what it's written so far to RGBA and carries on in RGBA
+pub(crate) struct GrayStore<'a> {
+    pub(crate) output: &'a mut Vec<u8>,
+    /// Where this image starts in `output`
+    pub(crate) start: usize,
+    pub(crate) gray: bool,
+}
+
+impl GrayStore<'_> {
+    fn widen(&mut self) {
+        let pixel_count = self.output.len() - self.start;
+        self.output.resize(self.start + pixel_count * 4, 0);
+        let pixels = &mut self.output[self.start..];
+        // backwards, so that no gray byte is overwritten before it's been read
+        for index in (0..pixel_count).rev() {
+            let luma = pixels[index];
+            pixels[index * 4..index * 4 + 4].copy_from_slice(&[luma, luma, luma, 0xff]);
+        }
+        self.gray = false;
+    }
+}
+
+impl StoreStep for GrayStore<'_> {
+    fn finish(&mut self, pixels: &[RGBA]) {
+        if self.gray {
+            let gray_count = pixels
+                .iter()
+                .take_while(|&&pixel| {
+                    let [r, g, b, a] = pixel.to_ne_bytes();
+                    r == g && g == b && a == 0xff
+                })
+                .count();
+            let (gray, rest) = pixels.split_at(gray_count);
+            self.output
+                .extend(gray.iter().map(|&pixel| pixel.to_ne_bytes()[0]));
+            if rest.is_empty() {
+                return;
+            }
+            self.widen();
+            self.output.extend_from_slice(bytemuck::cast_slice(rest));
+        } else {
+            self.output.extend_from_slice(bytemuck::cast_slice(pixels));
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use alloc::vec::Vec;
+
+use crate::common::{QOIHeader, RGBA};
+use crate::decode;
+use crate::inspect::OpTrace;
+use crate::ops::{validate, OpError};
+
+/// Whatever `decode_lenient` could get out of a QOI file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartialImage {
+    pub header: QOIHeader,
+    /// Every pixel of the image, with the ones the stream didn't provide set to the fill color
+    pub pixels: Vec<RGBA>,
+    /// How many pixels, from the start, came from the stream
+    pub pixels_decoded: usize,
+    /// What was wrong with the stream, which holds the byte offset where things went wrong
+    pub error: Option<OpError>,
+}
+
+impl PartialImage {
+    /// Whether every pixel came from the stream, which can be the case even with an error
+    /// such as a missing end marker
+    pub fn is_complete(&self) -> bool {
+        self.pixels_decoded == self.header.image_size()
+    }
+}
+
+/// Decodes as much of a truncated or corrupt QOI file as possible, for salvaging partial downloads and captures.
+/// Pixels up to the first error are kept, a run that goes past the end is cut short,
+/// and the rest of the image is filled with `fill`.
+/// Sound files take the same fast path as `decode`, broken ones a slower checked one.
+/// Fails only when there's no usable header.
+pub fn decode_lenient(input: &[u8], fill: RGBA) -> Result<PartialImage, OpError> {
+    let error = match validate(input) {
+        Ok(_) => None,
+        Err(error @ (OpError::NotQoi | OpError::InvalidHeader)) => return Err(error),
+        Err(error) => Some(error),
+    };
+    let header = QOIHeader::from(input);
+    let image_size = header.image_size();
+    let mut pixels = Vec::with_capacity(image_size);
+
+    // where the stream ends, for sound streams, since the fast decoder expects the end marker
+    // or the extensions after it to end its input
+    let stream_end = match error {
+        None => Some(input.len()),
+        Some(OpError::TrailingBytes(end) | OpError::ChecksumMismatch(end)) => Some(end),
+        Some(_) => None,
+    };
+    if let Some(end) = stream_end {
+        decode(&input[..end], &mut pixels).expect("the stream was validated");
+    } else {
+        // the fast decoder trusts its input, so broken streams go through the checked walk instead
+        for record in OpTrace::new(input).expect("the header was validated") {
+            let count = record.op.pixel_count().min(image_size - pixels.len());
+            pixels.resize(pixels.len() + count, record.pixel);
+        }
+    }
+
+    let pixels_decoded = pixels.len();
+    pixels.resize(image_size, fill);
+    Ok(PartialImage {
+        header,
+        pixels,
+        pixels_decoded,
+        error,
+    })
+}
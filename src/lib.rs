@@ -3,18 +3,58 @@
 
 extern crate alloc;
 extern crate bytemuck;
+#[cfg(feature = "image_compat")]
+extern crate std;
 
-pub use arch_switch::implementation::{decode::decode, encode::encode};
+pub use arch_switch::implementation::{
+    decode::{
+        decode, decode_channel, decode_flattened_rgb, decode_gray, decode_oriented, decode_pitched,
+        decode_scaled, decode_to_bytes, decode_to_bytes_with_alpha, decode_to_bytes_with_options,
+        decode_with_alpha, decode_with_backend, decode_with_options, decode_yuv,
+    },
+    encode::{
+        encode, encode_narrow, encode_oriented, encode_premultiplied, encode_strided,
+        encode_with_backend, encode_with_layout, encode_with_stats, encode_yuv, Encoder,
+    },
+    Backend,
+};
 use common::*;
+#[cfg(feature = "image_compat")]
+pub use image_compat::{
+    decode_to_dynamic_image, encode_dynamic_image, encode_dynamic_image_with_header,
+    png_linear_rgb, read_icc_profile, QoiDecoder, QoiEncoder,
+};
+pub use layout::{Channel, DecodeOptions, NarrowFormat, PixelLayout};
+pub use lenient::{decode_lenient, PartialImage};
+pub use ops::{ops, validate, OpError, QoiOp};
+pub use orientation::Orientation;
+pub use scale::ScaleFactor;
+pub use stats::{EncodeStats, OpTally};
+pub use writer::{QoiWriter, WriteError};
+pub use yuv::{YuvFormat, YuvMatrix, YuvOptions, YuvRange};
 
+pub use alpha::AlphaMode;
+
+pub mod alpha;
 #[path = "./arch_switch.rs"]
 mod arch_switch;
+#[cfg(feature = "image_compat")]
+pub mod image_compat;
+pub mod inspect;
+pub mod integrity;
+pub mod layout;
+pub mod lenient;
+pub mod metadata;
+pub mod ops;
+pub mod orientation;
+pub mod scale;
+pub mod stats;
+pub mod writer;
+pub mod yuv;
 
 pub(crate) trait Hashing {
-    fn update(&mut self, pixel_feed: &[RGBA]);
     unsafe fn fetch(&self, hash: HASH) -> &RGBA;
     unsafe fn fetch_mut(&mut self, hash: HASH) -> &mut RGBA;
-    fn swap(&mut self, pixel: &RGBA) -> (RGBA, HASH);
 }
 
 pub mod common {
@@ -37,7 +77,7 @@ pub mod common {
     pub type SBPX = u8; // byte subpixel
     pub type HashIndexedArray = [RGBA; 64];
 
-    #[derive(Clone, Copy)]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     pub struct QOIHeader {
         pub width: u32,
         pub height: u32,
@@ -53,7 +93,8 @@ pub mod common {
                 width,
                 height,
                 has_alpha: img.color().has_alpha(),
-                linear_rgb: false, // TODO detect or parameterize this somehow
+                // DynamicImage doesn't keep the colorspace, read it from the file with png_linear_rgb
+                linear_rgb: false,
             }
         }
     }
@@ -88,5 +129,43 @@ pub mod common {
         pub fn image_size(&self) -> usize {
             self.width as usize * self.height as usize
         }
+
+        /// The (x, y) position of the pixel at `pixel_index`, counting across rows
+        pub fn coordinates(&self, pixel_index: usize) -> (u32, u32) {
+            let width = self.width.max(1) as usize;
+            ((pixel_index % width) as u32, (pixel_index / width) as u32)
+        }
+    }
+
+    /// Expands tightly packed RGB8 bytes into opaque pixels ready for `encode`
+    pub fn rgb8_to_pixels(bytes: &[u8]) -> Vec<RGBA> {
+        bytes
+            .chunks_exact(3)
+            .map(|rgb| RGBA::from_ne_bytes([rgb[0], rgb[1], rgb[2], 0xff]))
+            .collect()
+    }
+
+    /// Reinterprets RGBA8 bytes as pixels, regardless of the alignment of `bytes`
+    pub fn rgba8_to_pixels(bytes: &[u8]) -> Vec<RGBA> {
+        bytes
+            .chunks_exact(4)
+            .map(|rgba| RGBA::from_ne_bytes(rgba.try_into().unwrap()))
+            .collect()
+    }
+
+    /// Drops the alpha channel of each pixel, writing packed RGB8 into `output`
+    pub fn pixels_to_rgb8(pixels: &[RGBA], output: &mut [u8]) {
+        for (rgb, pixel) in output.chunks_exact_mut(3).zip(pixels) {
+            rgb.copy_from_slice(&pixel.to_ne_bytes()[..3]);
+        }
+    }
+
+    /// Squeezes decoded RGBA8 bytes down to RGB8 within the same allocation
+    pub fn compact_rgba8_to_rgb8(bytes: &mut Vec<u8>) {
+        let pixel_count = bytes.len() / 4;
+        for i in 0..pixel_count {
+            bytes.copy_within(i * 4..i * 4 + 3, i * 3);
+        }
+        bytes.truncate(pixel_count * 3);
     }
 }
@@ -0,0 +1,255 @@
+//! Optional metadata chunks after the end marker, for what the QOI header has no room for:
+//! ICC profiles, the EXIF orientation and UTF-8 key/value text.
+//! Decoders that follow the specification stop at the end marker, so they read such files as usual.
+//!
+//! Each chunk is a 4 byte tag, a big endian 4 byte length and that many bytes of data.
+//! The chunks are followed by a footer of the tag `qmet` and their total length,
+//! so that they can be found from the end of the file without walking the ops.
+//! If the file also has an integrity trailer, it comes after the footer and covers the chunks too.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::common::END_8;
+use crate::integrity::split_trailer;
+
+pub const METADATA_TAG: [u8; 4] = *b"qmet";
+pub const FOOTER_SIZE: usize = 8;
+const CHUNK_HEADER_SIZE: usize = 8;
+
+pub const ICC_PROFILE_TAG: [u8; 4] = *b"iccp";
+pub const ORIENTATION_TAG: [u8; 4] = *b"exor";
+pub const TEXT_TAG: [u8; 4] = *b"text";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Chunk {
+    /// An ICC color profile, as it would be embedded in a PNG or JPEG
+    IccProfile(Vec<u8>),
+    /// The EXIF orientation, from 1 for upright to 8
+    Orientation(u8),
+    /// A UTF-8 key and value, stored as the key, a zero byte and the value
+    Text { key: String, value: String },
+    /// A chunk this version doesn't know, kept so that it survives being read and written again
+    Unknown { tag: [u8; 4], data: Vec<u8> },
+}
+
+/// Why the metadata of a file couldn't be read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetadataError {
+    /// The orientation chunk holds something other than a single byte from 1 to 8
+    InvalidOrientation,
+    /// The text chunk at this offset isn't a UTF-8 key and value separated by a zero byte
+    InvalidText(usize),
+}
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetadataError::InvalidOrientation => write!(f, "the orientation isn't from 1 to 8"),
+            MetadataError::InvalidText(offset) => {
+                write!(
+                    f,
+                    "the text chunk at byte {offset} isn't a UTF-8 key and value"
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "image_compat")]
+impl std::error::Error for MetadataError {}
+
+impl Chunk {
+    pub fn tag(&self) -> [u8; 4] {
+        match self {
+            Chunk::IccProfile(_) => ICC_PROFILE_TAG,
+            Chunk::Orientation(_) => ORIENTATION_TAG,
+            Chunk::Text { .. } => TEXT_TAG,
+            Chunk::Unknown { tag, .. } => *tag,
+        }
+    }
+
+    fn write_to(&self, output: &mut Vec<u8>) {
+        let text;
+        let data: &[u8] = match self {
+            Chunk::IccProfile(profile) => profile,
+            Chunk::Orientation(orientation) => core::slice::from_ref(orientation),
+            Chunk::Text { key, value } => {
+                text = [key.as_bytes(), &[0], value.as_bytes()].concat();
+                &text
+            }
+            Chunk::Unknown { data, .. } => data,
+        };
+        output.extend(self.tag());
+        output.extend((data.len() as u32).to_be_bytes());
+        output.extend(data);
+    }
+
+    /// `offset` is where the chunk starts in the file, for errors
+    fn parse(tag: [u8; 4], data: &[u8], offset: usize) -> Result<Self, MetadataError> {
+        Ok(match tag {
+            ICC_PROFILE_TAG => Chunk::IccProfile(data.to_vec()),
+            ORIENTATION_TAG => match data {
+                [orientation @ 1..=8] => Chunk::Orientation(*orientation),
+                _ => return Err(MetadataError::InvalidOrientation),
+            },
+            TEXT_TAG => {
+                let invalid = || MetadataError::InvalidText(offset);
+                let separator = data
+                    .iter()
+                    .position(|&byte| byte == 0)
+                    .ok_or_else(invalid)?;
+                let text = |bytes: &[u8]| {
+                    core::str::from_utf8(bytes)
+                        .map(String::from)
+                        .map_err(|_| invalid())
+                };
+                Chunk::Text {
+                    key: text(&data[..separator])?,
+                    value: text(&data[separator + 1..])?,
+                }
+            }
+            _ => Chunk::Unknown {
+                tag,
+                data: data.to_vec(),
+            },
+        })
+    }
+}
+
+/// The metadata chunks of an image, in the order they're written
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    pub chunks: Vec<Chunk>,
+}
+
+impl Metadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Reads the metadata chunks of a QOI file, which are empty if it has none
+    pub fn read(qoi_data: &[u8]) -> Result<Self, MetadataError> {
+        let (stream, chunks) = split_metadata(split_trailer(qoi_data).0);
+        let mut metadata = Metadata::new();
+        let mut offset = stream.len();
+        for (tag, data) in ChunkIter(chunks.unwrap_or(&[])) {
+            metadata.chunks.push(Chunk::parse(tag, data, offset)?);
+            offset += CHUNK_HEADER_SIZE + data.len();
+        }
+        Ok(metadata)
+    }
+
+    /// The chunks and the footer, as they go after the end marker
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for chunk in &self.chunks {
+            chunk.write_to(&mut bytes);
+        }
+        let length = bytes.len() as u32;
+        bytes.extend(METADATA_TAG);
+        bytes.extend(length.to_be_bytes());
+        bytes
+    }
+
+    pub fn icc_profile(&self) -> Option<&[u8]> {
+        self.chunks.iter().find_map(|chunk| match chunk {
+            Chunk::IccProfile(profile) => Some(profile.as_slice()),
+            _ => None,
+        })
+    }
+
+    pub fn orientation(&self) -> Option<u8> {
+        self.chunks.iter().find_map(|chunk| match chunk {
+            Chunk::Orientation(orientation) => Some(*orientation),
+            _ => None,
+        })
+    }
+
+    pub fn text(&self, key: &str) -> Option<&str> {
+        self.chunks.iter().find_map(|chunk| match chunk {
+            Chunk::Text { key: k, value } if k == key => Some(value.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Adds `chunk`, replacing the one it stands in for: the one with the same tag,
+    /// or for text, the one with the same key
+    pub fn set(&mut self, chunk: Chunk) {
+        let replaces = |existing: &Chunk| match (existing, &chunk) {
+            (Chunk::Text { key: a, .. }, Chunk::Text { key: b, .. }) => a == b,
+            (Chunk::Text { .. }, _) | (_, Chunk::Text { .. }) => false,
+            (existing, chunk) => existing.tag() == chunk.tag(),
+        };
+        match self.chunks.iter_mut().find(|existing| replaces(existing)) {
+            Some(existing) => *existing = chunk,
+            None => self.chunks.push(chunk),
+        }
+    }
+}
+
+/// Appends `metadata` to `qoi_data`, which must hold exactly one image without metadata,
+/// as `encode` wrote it. Add it before any integrity trailer, so that the trailer covers it.
+pub fn append_metadata(qoi_data: &mut Vec<u8>, metadata: &Metadata) {
+    assert!(
+        split_trailer(qoi_data).1.is_none(),
+        "metadata goes before the integrity trailer"
+    );
+    debug_assert!(qoi_data.ends_with(&END_8));
+    qoi_data.extend(metadata.to_bytes());
+}
+
+/// Tags and data of a run of chunks, stopping at the first one that doesn't fit
+struct ChunkIter<'a>(&'a [u8]);
+
+impl<'a> Iterator for ChunkIter<'a> {
+    type Item = ([u8; 4], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.0.get(..CHUNK_HEADER_SIZE)?;
+        let tag = header[0..4].try_into().unwrap();
+        let length = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+        let data = self
+            .0
+            .get(CHUNK_HEADER_SIZE..CHUNK_HEADER_SIZE.checked_add(length)?)?;
+        self.0 = &self.0[CHUNK_HEADER_SIZE + length..];
+        Some((tag, data))
+    }
+}
+
+/// Splits what comes before any integrity trailer into the QOI stream, up to and including the end marker,
+/// and the metadata chunks if there are any. The chunks have to fill their space exactly
+/// and the stream has to end with the end marker, or the data isn't taken to have metadata.
+pub(crate) fn split_metadata(data: &[u8]) -> (&[u8], Option<&[u8]>) {
+    let Some(footer_start) = data.len().checked_sub(FOOTER_SIZE) else {
+        return (data, None);
+    };
+    let footer = &data[footer_start..];
+    if footer[0..4] != METADATA_TAG {
+        return (data, None);
+    }
+    let length = u32::from_be_bytes(footer[4..8].try_into().unwrap()) as usize;
+    let Some(chunks_start) = footer_start.checked_sub(length) else {
+        return (data, None);
+    };
+    let (stream, chunks) = (&data[..chunks_start], &data[chunks_start..footer_start]);
+
+    let mut rest = chunks;
+    for (_, chunk_data) in ChunkIter(chunks) {
+        rest = &rest[CHUNK_HEADER_SIZE + chunk_data.len()..];
+    }
+    match rest.is_empty() && stream.ends_with(&END_8) {
+        true => (stream, Some(chunks)),
+        false => (data, None),
+    }
+}
+
+/// The QOI stream of a file, up to and including the end marker, without any metadata or integrity trailer after it
+pub fn strip_extensions(qoi_data: &[u8]) -> &[u8] {
+    split_metadata(split_trailer(qoi_data).0).0
+}
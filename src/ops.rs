@@ -0,0 +1,339 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::common::{
+    QOIHeader, END_8, HASH, MAGIC_QOIF, QOI_OP_DIFF, QOI_OP_INDEX, QOI_OP_LUMA, QOI_OP_RGB,
+    QOI_OP_RGBA, QOI_OP_RUN, RGBA,
+};
+use crate::integrity::trailing_error;
+use crate::metadata::strip_extensions;
+
+const HEADER_SIZE: usize = 14;
+
+const QOI_OP_INDEX_MAX: u8 = QOI_OP_DIFF - 1;
+const QOI_OP_DIFF_MAX: u8 = QOI_OP_LUMA - 1;
+const QOI_OP_LUMA_MAX: u8 = QOI_OP_RUN - 1;
+const QOI_OP_RUN_MAX: u8 = QOI_OP_RGB - 1;
+
+/// A single QOI op with its operands unpacked and unbiased
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QoiOp {
+    Rgb {
+        r: u8,
+        g: u8,
+        b: u8,
+    },
+    Rgba {
+        r: u8,
+        g: u8,
+        b: u8,
+        a: u8,
+    },
+    Index(HASH),
+    Diff {
+        dr: i8,
+        dg: i8,
+        db: i8,
+    },
+    Luma {
+        dg: i8,
+        dr_dg: i8,
+        db_dg: i8,
+    },
+    /// The number of repeats of the previous pixel, from 1 to 62
+    Run(u8),
+}
+
+impl QoiOp {
+    /// Reads the op at the start of `bytes`, or `None` if `bytes` ends partway through it
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let tag = *bytes.first()?;
+        let op = match tag {
+            QOI_OP_RGB => {
+                let [r, g, b] = bytes.get(1..4)?.try_into().unwrap();
+                QoiOp::Rgb { r, g, b }
+            }
+            QOI_OP_RGBA => {
+                let [r, g, b, a] = bytes.get(1..5)?.try_into().unwrap();
+                QoiOp::Rgba { r, g, b, a }
+            }
+            QOI_OP_INDEX..=QOI_OP_INDEX_MAX => QoiOp::Index(tag),
+            QOI_OP_DIFF..=QOI_OP_DIFF_MAX => QoiOp::Diff {
+                dr: ((tag >> 4) & 0b11) as i8 - 2,
+                dg: ((tag >> 2) & 0b11) as i8 - 2,
+                db: (tag & 0b11) as i8 - 2,
+            },
+            QOI_OP_LUMA..=QOI_OP_LUMA_MAX => {
+                let second = *bytes.get(1)?;
+                QoiOp::Luma {
+                    dg: (tag & 0b111111) as i8 - 32,
+                    dr_dg: (second >> 4) as i8 - 8,
+                    db_dg: (second & 0b1111) as i8 - 8,
+                }
+            }
+            QOI_OP_RUN..=QOI_OP_RUN_MAX => QoiOp::Run((tag & 0b111111) + 1),
+        };
+        Some(op)
+    }
+
+    /// Whether every operand is in the range the op can encode, which `parse` always guarantees
+    pub const fn is_encodable(&self) -> bool {
+        match *self {
+            QoiOp::Rgb { .. } | QoiOp::Rgba { .. } => true,
+            QoiOp::Index(slot) => slot < 64,
+            QoiOp::Diff { dr, dg, db } => {
+                matches!(dr, -2..=1) && matches!(dg, -2..=1) && matches!(db, -2..=1)
+            }
+            QoiOp::Luma { dg, dr_dg, db_dg } => {
+                matches!(dg, -32..=31) && matches!(dr_dg, -8..=7) && matches!(db_dg, -8..=7)
+            }
+            QoiOp::Run(length) => matches!(length, 1..=62),
+        }
+    }
+
+    /// Appends the op's bytes to `output`, the inverse of `parse`.
+    /// Panics if the op isn't encodable.
+    pub fn write_to(&self, output: &mut Vec<u8>) {
+        assert!(self.is_encodable(), "{self} is out of range");
+        match *self {
+            QoiOp::Rgb { r, g, b } => output.extend([QOI_OP_RGB, r, g, b]),
+            QoiOp::Rgba { r, g, b, a } => output.extend([QOI_OP_RGBA, r, g, b, a]),
+            QoiOp::Index(slot) => output.push(QOI_OP_INDEX | slot),
+            QoiOp::Diff { dr, dg, db } => output
+                .push(QOI_OP_DIFF | ((dr + 2) as u8) << 4 | ((dg + 2) as u8) << 2 | (db + 2) as u8),
+            QoiOp::Luma { dg, dr_dg, db_dg } => output.extend([
+                QOI_OP_LUMA | (dg + 32) as u8,
+                ((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8,
+            ]),
+            QoiOp::Run(length) => output.push(QOI_OP_RUN | (length - 1)),
+        }
+    }
+
+    /// The number of bytes this op takes up in the stream
+    pub const fn size(&self) -> usize {
+        match self {
+            QoiOp::Rgb { .. } => 4,
+            QoiOp::Rgba { .. } => 5,
+            QoiOp::Luma { .. } => 2,
+            QoiOp::Index(_) | QoiOp::Diff { .. } | QoiOp::Run(_) => 1,
+        }
+    }
+
+    /// The number of pixels this op produces
+    pub const fn pixel_count(&self) -> usize {
+        match self {
+            QoiOp::Run(length) => *length as usize,
+            _ => 1,
+        }
+    }
+
+    /// The name of the op as it appears in the QOI specification
+    pub const fn name(&self) -> &'static str {
+        match self {
+            QoiOp::Rgb { .. } => "QOI_OP_RGB",
+            QoiOp::Rgba { .. } => "QOI_OP_RGBA",
+            QoiOp::Index(_) => "QOI_OP_INDEX",
+            QoiOp::Diff { .. } => "QOI_OP_DIFF",
+            QoiOp::Luma { .. } => "QOI_OP_LUMA",
+            QoiOp::Run(_) => "QOI_OP_RUN",
+        }
+    }
+
+    /// Applies the op to the previous pixel, looking up index ops in `index`
+    pub fn apply(&self, previous: RGBA, index: &[RGBA; 64]) -> RGBA {
+        let [pr, pg, pb, pa] = previous.to_ne_bytes();
+        let pixel = match *self {
+            QoiOp::Rgb { r, g, b } => [r, g, b, pa],
+            QoiOp::Rgba { r, g, b, a } => [r, g, b, a],
+            QoiOp::Index(slot) => return index[slot as usize],
+            QoiOp::Diff { dr, dg, db } => [
+                pr.wrapping_add(dr as u8),
+                pg.wrapping_add(dg as u8),
+                pb.wrapping_add(db as u8),
+                pa,
+            ],
+            QoiOp::Luma { dg, dr_dg, db_dg } => [
+                pr.wrapping_add(dg.wrapping_add(dr_dg) as u8),
+                pg.wrapping_add(dg as u8),
+                pb.wrapping_add(dg.wrapping_add(db_dg) as u8),
+                pa,
+            ],
+            QoiOp::Run(_) => return previous,
+        };
+        RGBA::from_ne_bytes(pixel)
+    }
+}
+
+impl fmt::Display for QoiOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:<12}", self.name())?;
+        match self {
+            QoiOp::Rgb { r, g, b } => write!(f, " r={r} g={g} b={b}"),
+            QoiOp::Rgba { r, g, b, a } => write!(f, " r={r} g={g} b={b} a={a}"),
+            QoiOp::Index(slot) => write!(f, " slot={slot}"),
+            QoiOp::Diff { dr, dg, db } => write!(f, " dr={dr} dg={dg} db={db}"),
+            QoiOp::Luma { dg, dr_dg, db_dg } => {
+                write!(f, " dg={dg} dr-dg={dr_dg} db-dg={db_dg}")
+            }
+            QoiOp::Run(length) => write!(f, " length={length}"),
+        }
+    }
+}
+
+/// The index array position of a pixel, computed portably the same way as the specification
+pub const fn index_position(pixel: RGBA) -> HASH {
+    let [r, g, b, a] = pixel.to_ne_bytes();
+    let sum = r as u32 * 3 + g as u32 * 5 + b as u32 * 7 + a as u32 * 11;
+    (sum % 64) as HASH
+}
+
+/// Why an op stream couldn't be read all the way to its end marker.
+/// Each carries the byte offset, from the start of the file, where things went wrong.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpError {
+    /// The data doesn't start with a QOI header
+    NotQoi,
+    /// The header has a channel count other than 3 or 4, or a colorspace other than 0 or 1
+    InvalidHeader,
+    /// The data ends partway through the op at this offset, or before it when the image is still missing pixels
+    Truncated(usize),
+    /// The run at this offset continues past the last pixel of the image
+    RunOverflow(usize),
+    /// Every pixel is there, but the end marker doesn't follow at this offset
+    MissingEndMarker(usize),
+    /// The end marker is there, but more data follows it from this offset,
+    /// and it isn't metadata chunks or an integrity trailer
+    TrailingBytes(usize),
+    /// The integrity trailer at this offset doesn't match the file
+    ChecksumMismatch(usize),
+}
+
+impl fmt::Display for OpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpError::NotQoi => write!(f, "the data doesn't start with a QOI header"),
+            OpError::InvalidHeader => {
+                write!(f, "the header has an invalid channel count or colorspace")
+            }
+            OpError::Truncated(offset) => {
+                write!(f, "the data ends partway through the op at byte {offset}")
+            }
+            OpError::RunOverflow(offset) => {
+                write!(f, "the run at byte {offset} goes past the end of the image")
+            }
+            OpError::MissingEndMarker(offset) => {
+                write!(f, "the end marker is missing at byte {offset}")
+            }
+            OpError::ChecksumMismatch(offset) => {
+                write!(
+                    f,
+                    "the checksum in the trailer at byte {offset} doesn't match"
+                )
+            }
+            OpError::TrailingBytes(offset) => write!(
+                f,
+                "unexpected data after the end marker, from byte {offset}"
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "image_compat")]
+impl std::error::Error for OpError {}
+
+/// Iterator over the ops of a QOI file, made by [`ops`]
+pub struct Ops<'a> {
+    data: &'a [u8],
+    position: usize,
+    pixels_left: usize,
+    /// A problem with the header, reported in place of the first op
+    header_error: Option<OpError>,
+    done: bool,
+}
+
+fn check_header(data: &[u8]) -> Result<QOIHeader, OpError> {
+    if data.len() < HEADER_SIZE || data[0..4] != MAGIC_QOIF {
+        return Err(OpError::NotQoi);
+    }
+    if !matches!(data[12], 3 | 4) || data[13] > 1 {
+        return Err(OpError::InvalidHeader);
+    }
+    Ok(QOIHeader::from(data))
+}
+
+/// Reads a QOI file op by op, giving the byte offset of each op along with it.
+/// The stream is checked as it goes, so the iterator stops after the first error,
+/// and after the last op only if the end marker follows it and ends the data,
+/// give or take metadata chunks and an integrity trailer.
+pub fn ops(data: &[u8]) -> Ops<'_> {
+    let header = check_header(data);
+    Ops {
+        data,
+        position: HEADER_SIZE,
+        pixels_left: header.map_or(0, |header| header.image_size()),
+        header_error: header.err(),
+        done: false,
+    }
+}
+
+/// Checks a whole QOI file without decoding it, returning its header if it's sound.
+/// It walks every op to make sure they produce exactly the pixels the header promises,
+/// followed by the end marker and nothing else. Nothing is allocated, so it's a cheap
+/// way to turn away malformed files before committing memory to a decode.
+/// If the file has an integrity trailer, the stream is checked against it too.
+pub fn validate(data: &[u8]) -> Result<QOIHeader, OpError> {
+    let header = check_header(data)?;
+    let mut ops = ops(data);
+    for op in ops.by_ref() {
+        if let Err(OpError::TrailingBytes(end)) = op {
+            return Err(trailing_error(data, end));
+        }
+        op?;
+    }
+    Ok(header)
+}
+
+impl Ops<'_> {
+    fn fail(&mut self, error: OpError) -> Option<Result<(usize, QoiOp), OpError>> {
+        self.done = true;
+        Some(Err(error))
+    }
+}
+
+impl Iterator for Ops<'_> {
+    type Item = Result<(usize, QoiOp), OpError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if let Some(error) = self.header_error {
+            return self.fail(error);
+        }
+        if self.pixels_left == 0 {
+            self.done = true;
+            let end = self.position + END_8.len();
+            return match self.data.get(self.position..end) {
+                Some(marker) if marker != END_8 => {
+                    Some(Err(OpError::MissingEndMarker(self.position)))
+                }
+                Some(_) if strip_extensions(self.data).len() != end => {
+                    Some(Err(OpError::TrailingBytes(end)))
+                }
+                Some(_) => None,
+                None => Some(Err(OpError::MissingEndMarker(self.position))),
+            };
+        }
+
+        let offset = self.position;
+        let Some(op) = QoiOp::parse(&self.data[offset..]) else {
+            return self.fail(OpError::Truncated(offset));
+        };
+        if op.pixel_count() > self.pixels_left {
+            return self.fail(OpError::RunOverflow(offset));
+        }
+        self.position += op.size();
+        self.pixels_left -= op.pixel_count();
+        Some(Ok((offset, op)))
+    }
+}
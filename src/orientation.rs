@@ -0,0 +1,178 @@
+//! Flips and rotations done as pixels are decoded or encoded,
+//! for bottom-up textures such as OpenGL's and for applying EXIF orientation.
+
+use alloc::vec::Vec;
+use core::mem::MaybeUninit;
+use core::ops::Range;
+
+use crate::common::RGBA;
+use crate::layout::{RowStore, Rows};
+
+/// A flip, rotation or both, in the order of the EXIF orientations they undo
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Orientation {
+    #[default]
+    Identity,
+    FlipHorizontal,
+    Rotate180,
+    /// Upside down, which turns top-down rows into OpenGL's bottom-up ones
+    FlipVertical,
+    /// Mirrored along the diagonal from the top left
+    Transpose,
+    /// A quarter turn clockwise
+    Rotate90,
+    /// Mirrored along the diagonal from the top right
+    Transverse,
+    /// Three quarter turns clockwise
+    Rotate270,
+}
+
+impl Orientation {
+    /// What to do to an image with this EXIF orientation, from 1 to 8, to show it the right way up
+    pub const fn from_exif(orientation: u8) -> Option<Orientation> {
+        Some(match orientation {
+            1 => Orientation::Identity,
+            2 => Orientation::FlipHorizontal,
+            3 => Orientation::Rotate180,
+            4 => Orientation::FlipVertical,
+            5 => Orientation::Transpose,
+            6 => Orientation::Rotate90,
+            7 => Orientation::Transverse,
+            8 => Orientation::Rotate270,
+            _ => return None,
+        })
+    }
+
+    pub const fn swaps_dimensions(self) -> bool {
+        matches!(
+            self,
+            Orientation::Transpose
+                | Orientation::Rotate90
+                | Orientation::Transverse
+                | Orientation::Rotate270
+        )
+    }
+
+    /// The width and height of a `width` by `height` image once it's been through this
+    pub const fn oriented_size(self, width: u32, height: u32) -> (u32, u32) {
+        match self.swaps_dimensions() {
+            true => (height, width),
+            false => (width, height),
+        }
+    }
+
+    /// Where the pixel at `(x, y)` of a `width` by `height` image ends up
+    pub const fn map(self, x: usize, y: usize, width: usize, height: usize) -> (usize, usize) {
+        let (flipped_x, flipped_y) = (width - 1 - x, height - 1 - y);
+        match self {
+            Orientation::Identity => (x, y),
+            Orientation::FlipHorizontal => (flipped_x, y),
+            Orientation::Rotate180 => (flipped_x, flipped_y),
+            Orientation::FlipVertical => (x, flipped_y),
+            Orientation::Transpose => (y, x),
+            Orientation::Rotate90 => (flipped_y, x),
+            Orientation::Transverse => (flipped_y, flipped_x),
+            Orientation::Rotate270 => (y, flipped_x),
+        }
+    }
+
+    /// Writes `pixels`, which start at `(x, y)` and don't go past the end of their row,
+    /// to where they belong in `output`, which holds the whole transformed image
+    pub(crate) fn place(
+        self,
+        pixels: &[RGBA],
+        (x, y): (usize, usize),
+        (width, height): (usize, usize),
+        output: &mut [MaybeUninit<RGBA>],
+    ) {
+        let rows = |row: usize| row * width..(row + 1) * width;
+        match self {
+            // flips keep rows together, so whole stretches of them can be copied
+            Orientation::Identity | Orientation::FlipVertical => {
+                let row = &mut output[rows(self.map(x, y, width, height).1)];
+                let destination = &mut row[x..x + pixels.len()];
+                for (slot, &pixel) in destination.iter_mut().zip(pixels) {
+                    slot.write(pixel);
+                }
+            }
+            Orientation::FlipHorizontal | Orientation::Rotate180 => {
+                let row = &mut output[rows(self.map(x, y, width, height).1)];
+                let destination = &mut row[width - x - pixels.len()..width - x];
+                for (slot, &pixel) in destination.iter_mut().rev().zip(pixels) {
+                    slot.write(pixel);
+                }
+            }
+            _ => {
+                // the transformed image is `height` pixels wide
+                for (offset, &pixel) in pixels.iter().enumerate() {
+                    let (to_x, to_y) = self.map(x + offset, y, width, height);
+                    output[to_y * height + to_x].write(pixel);
+                }
+            }
+        }
+    }
+
+    /// Appends rows `rows` of the transformed `width` by `height` image to `output`, reading them out of `pixels`.
+    /// A row of a rotation or transpose is a column of `pixels`, so a band of several of them is gathered
+    /// in one pass down the image, reading a short stretch of each row rather than one pixel.
+    pub(crate) fn gather(
+        self,
+        pixels: &[RGBA],
+        (width, height): (usize, usize),
+        rows: Range<usize>,
+        output: &mut Vec<RGBA>,
+    ) {
+        let start = output.len();
+        let (oriented_width, _) = self.oriented_size(width as u32, height as u32);
+        output.resize(start + rows.len() * oriented_width as usize, 0);
+        let output = &mut output[start..];
+        // the columns of `pixels` or the rows of it that the transformed rows come from
+        let sources = match self {
+            Orientation::Identity
+            | Orientation::FlipHorizontal
+            | Orientation::Transpose
+            | Orientation::Rotate90 => rows.clone(),
+            _ => match self.swaps_dimensions() {
+                true => width - rows.end..width - rows.start,
+                false => height - rows.end..height - rows.start,
+            },
+        };
+        if self.swaps_dimensions() {
+            for (y, row) in pixels.chunks_exact(width).enumerate() {
+                for (x, &pixel) in sources.clone().zip(&row[sources.clone()]) {
+                    let (to_x, to_y) = self.map(x, y, width, height);
+                    output[(to_y - rows.start) * height + to_x] = pixel;
+                }
+            }
+        } else {
+            for y in sources {
+                let (_, to_y) = self.map(0, y, width, height);
+                let row = &pixels[y * width..(y + 1) * width];
+                let destination = &mut output[(to_y - rows.start) * width..][..width];
+                destination.copy_from_slice(row);
+                if matches!(self, Orientation::FlipHorizontal | Orientation::Rotate180) {
+                    destination.reverse();
+                }
+            }
+        }
+    }
+}
+
+/// Writes each pixel straight to where it belongs in the transformed image
+pub(crate) struct OrientedStore<'a> {
+    pub(crate) orientation: Orientation,
+    pub(crate) output: &'a mut [MaybeUninit<RGBA>],
+    /// The size of the image before it's transformed
+    pub(crate) rows: Rows,
+}
+
+impl RowStore for OrientedStore<'_> {
+    fn rows(&mut self) -> &mut Rows {
+        &mut self.rows
+    }
+
+    fn place_row(&mut self, pixels: &[RGBA], x: usize, y: usize) {
+        let size = (self.rows.width, self.rows.height);
+        self.orientation.place(pixels, (x, y), size, self.output);
+    }
+}
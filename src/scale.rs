@@ -0,0 +1,86 @@
+//! Shrinking images by a power of two as they're decoded, for thumbnails of images too big to hold in full.
+
+use alloc::vec::Vec;
+
+use crate::common::RGBA;
+use crate::layout::{RowStore, Rows};
+
+/// How much `decode_scaled` shrinks each side of the image by
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScaleFactor {
+    Half,
+    Quarter,
+    Eighth,
+}
+
+impl ScaleFactor {
+    /// How many pixels across each output pixel covers
+    pub const fn divisor(self) -> usize {
+        match self {
+            ScaleFactor::Half => 2,
+            ScaleFactor::Quarter => 4,
+            ScaleFactor::Eighth => 8,
+        }
+    }
+
+    /// The size of the shrunk image. Sides that don't divide evenly round up,
+    /// with the last row and column averaging the fewer pixels they cover.
+    pub const fn scaled_size(self, width: u32, height: u32) -> (u32, u32) {
+        let divisor = self.divisor() as u32;
+        (width.div_ceil(divisor), height.div_ceil(divisor))
+    }
+}
+
+/// Averages each box of pixels weighted by alpha, so that transparent pixels don't bleed their color into it
+fn average([r, g, b, a]: [u32; 4], count: u32) -> RGBA {
+    if a == 0 {
+        return 0;
+    }
+    let color = |sum: u32| ((sum + a / 2) / a) as u8;
+    let alpha = ((a + count / 2) / count) as u8;
+    RGBA::from_ne_bytes([color(r), color(g), color(b), alpha])
+}
+
+/// Sums a band of `divisor` rows at a time, one total per output column, and appends their averages
+/// as a row of output once the band is complete. At most 64 pixels add up to less than 64 * 255 * 255, which fits.
+pub(crate) struct ScaledStore<'a> {
+    pub(crate) output: &'a mut Vec<RGBA>,
+    pub(crate) sums: Vec<[u32; 4]>,
+    pub(crate) divisor: usize,
+    /// The size of the image before it's shrunk
+    pub(crate) rows: Rows,
+}
+
+impl ScaledStore<'_> {
+    fn finish_band(&mut self, y: usize) {
+        let band_height = y % self.divisor + 1;
+        for (column, sums) in self.sums.iter_mut().enumerate() {
+            let band_width = (self.rows.width - column * self.divisor).min(self.divisor);
+            self.output
+                .push(average(*sums, (band_width * band_height) as u32));
+            *sums = [0; 4];
+        }
+    }
+}
+
+impl RowStore for ScaledStore<'_> {
+    fn rows(&mut self) -> &mut Rows {
+        &mut self.rows
+    }
+
+    fn place_row(&mut self, pixels: &[RGBA], x: usize, y: usize) {
+        for (offset, &pixel) in pixels.iter().enumerate() {
+            let [r, g, b, a] = pixel.to_ne_bytes().map(u32::from);
+            let sums = &mut self.sums[(x + offset) / self.divisor];
+            sums[0] += r * a;
+            sums[1] += g * a;
+            sums[2] += b * a;
+            sums[3] += a;
+        }
+        if x + pixels.len() == self.rows.width
+            && (y % self.divisor == self.divisor - 1 || y == self.rows.height - 1)
+        {
+            self.finish_band(y);
+        }
+    }
+}
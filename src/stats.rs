@@ -0,0 +1,107 @@
+//! Statistics about an encoded image: how many of each op it is made of and how well it compressed.
+
+use crate::inspect::OpTrace;
+use crate::ops::QoiOp;
+use crate::Backend;
+
+/// How often one kind of op was used, and how many bytes it took up
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OpTally {
+    pub count: usize,
+    pub bytes: usize,
+}
+
+/// What the encoder did with an image, from `Encoder::encode_with_stats`.
+/// It's worked out from the encoded stream afterwards, so plain `encode` doesn't pay for any of it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncodeStats {
+    pub backend: Backend,
+    pub rgb: OpTally,
+    pub rgba: OpTally,
+    pub index: OpTally,
+    pub diff: OpTally,
+    pub luma: OpTally,
+    pub run: OpTally,
+    /// `run_lengths[n - 1]` counts the runs of `n` pixels
+    pub run_lengths: [usize; 62],
+    /// `index_hits[slot]` counts the index ops that used `slot`
+    pub index_hits: [usize; 64],
+    /// The number of pixels whose alpha differs from the pixel before them
+    pub alpha_changes: usize,
+    /// The size of the image as raw 8 bit pixels, with as many channels as the header says
+    pub input_bytes: usize,
+    /// The size of the encoded image, including the header and the end marker
+    pub output_bytes: usize,
+}
+
+impl EncodeStats {
+    /// Tallies the ops of a stream the encoder just wrote, so it's trusted to be complete
+    pub(crate) fn from_stream(qoi_data: &[u8], backend: Backend) -> Self {
+        let mut trace = OpTrace::new(qoi_data).expect("the encoder wrote a header");
+        let header = trace.header();
+        let mut stats = Self {
+            backend,
+            rgb: OpTally::default(),
+            rgba: OpTally::default(),
+            index: OpTally::default(),
+            diff: OpTally::default(),
+            luma: OpTally::default(),
+            run: OpTally::default(),
+            run_lengths: [0; 62],
+            index_hits: [0; 64],
+            alpha_changes: 0,
+            input_bytes: header.image_size() * (3 + header.has_alpha as usize),
+            output_bytes: qoi_data.len(),
+        };
+
+        let mut previous_alpha = 0xff;
+        for record in trace.by_ref() {
+            let tally = stats.tally_mut(&record.op);
+            tally.count += 1;
+            tally.bytes += record.op.size();
+            match record.op {
+                QoiOp::Run(length) => stats.run_lengths[length as usize - 1] += 1,
+                QoiOp::Index(slot) => stats.index_hits[slot as usize] += 1,
+                _ => (),
+            }
+            let alpha = record.pixel.to_ne_bytes()[3];
+            stats.alpha_changes += (alpha != previous_alpha) as usize;
+            previous_alpha = alpha;
+        }
+        stats
+    }
+
+    fn tally_mut(&mut self, op: &QoiOp) -> &mut OpTally {
+        match op {
+            QoiOp::Rgb { .. } => &mut self.rgb,
+            QoiOp::Rgba { .. } => &mut self.rgba,
+            QoiOp::Index(_) => &mut self.index,
+            QoiOp::Diff { .. } => &mut self.diff,
+            QoiOp::Luma { .. } => &mut self.luma,
+            QoiOp::Run(_) => &mut self.run,
+        }
+    }
+
+    /// The total over every kind of op
+    pub fn ops(&self) -> OpTally {
+        [
+            self.rgb, self.rgba, self.index, self.diff, self.luma, self.run,
+        ]
+        .into_iter()
+        .fold(OpTally::default(), |total, tally| OpTally {
+            count: total.count + tally.count,
+            bytes: total.bytes + tally.bytes,
+        })
+    }
+
+    /// The encoded size as a fraction of the raw size, smaller is better
+    pub fn compression_ratio(&self) -> f64 {
+        self.output_bytes as f64 / self.input_bytes.max(1) as f64
+    }
+
+    /// The fraction of the op bytes that went to `tally`, such as `stats.byte_share(stats.rgb)`.
+    /// A large share of RGB and RGBA ops means the image doesn't suit QOI.
+    pub fn byte_share(&self, tally: OpTally) -> f64 {
+        tally.bytes as f64 / self.ops().bytes.max(1) as f64
+    }
+}
@@ -0,0 +1,140 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::common::{HashIndexedArray, QOIHeader, END_8, RGBA};
+use crate::ops::{index_position, QoiOp};
+
+/// Why `QoiWriter` refused an op or couldn't finish the image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteError {
+    /// An operand is outside the range the op can encode
+    OutOfRange(QoiOp),
+    /// The op would produce more pixels than are left in the image
+    TooManyPixels { written: usize, expected: usize },
+    /// The image was finished before every pixel was written
+    MissingPixels { written: usize, expected: usize },
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::OutOfRange(op) => write!(f, "{} has an operand out of range", op.name()),
+            WriteError::TooManyPixels { written, expected } => write!(
+                f,
+                "the op goes past the end of the image, with {written} of {expected} pixels written"
+            ),
+            WriteError::MissingPixels { written, expected } => {
+                write!(f, "only {written} of {expected} pixels were written")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "image_compat")]
+impl std::error::Error for WriteError {}
+
+/// Writes a QOI stream one op at a time, leaving every choice of op to the caller.
+/// It checks each op and keeps the state a decoder would have, so that the caller can see
+/// what an op is going to produce. Meant for test vectors and for trying out other encoders,
+/// where `encode` would pick the ops itself.
+pub struct QoiWriter<'a> {
+    header: QOIHeader,
+    output: &'a mut Vec<u8>,
+    previous_pixel: RGBA,
+    index: HashIndexedArray,
+    pixels_written: usize,
+}
+
+impl<'a> QoiWriter<'a> {
+    /// Starts an image by appending its header to `output`
+    pub fn new(output: &'a mut Vec<u8>, header: QOIHeader) -> Self {
+        output.extend(header.to_bytes());
+        Self {
+            header,
+            output,
+            previous_pixel: 0xff000000,
+            index: [0; 64],
+            pixels_written: 0,
+        }
+    }
+
+    pub fn header(&self) -> QOIHeader {
+        self.header
+    }
+
+    /// The last pixel written, which diff, luma and run ops are relative to
+    pub fn previous_pixel(&self) -> RGBA {
+        self.previous_pixel
+    }
+
+    /// The index array as a decoder would have it at this point
+    pub fn index_array(&self) -> &HashIndexedArray {
+        &self.index
+    }
+
+    pub fn pixels_written(&self) -> usize {
+        self.pixels_written
+    }
+
+    /// Writes `op`, returning the pixel it produces
+    pub fn write(&mut self, op: QoiOp) -> Result<RGBA, WriteError> {
+        if !op.is_encodable() {
+            return Err(WriteError::OutOfRange(op));
+        }
+        let expected = self.header.image_size();
+        if self.pixels_written + op.pixel_count() > expected {
+            return Err(WriteError::TooManyPixels {
+                written: self.pixels_written,
+                expected,
+            });
+        }
+
+        op.write_to(self.output);
+        let pixel = op.apply(self.previous_pixel, &self.index);
+        self.index[index_position(pixel) as usize] = pixel;
+        self.previous_pixel = pixel;
+        self.pixels_written += op.pixel_count();
+        Ok(pixel)
+    }
+
+    pub fn rgb(&mut self, r: u8, g: u8, b: u8) -> Result<RGBA, WriteError> {
+        self.write(QoiOp::Rgb { r, g, b })
+    }
+
+    pub fn rgba(&mut self, r: u8, g: u8, b: u8, a: u8) -> Result<RGBA, WriteError> {
+        self.write(QoiOp::Rgba { r, g, b, a })
+    }
+
+    /// Repeats the pixel in index array slot `slot`, from 0 to 63
+    pub fn index(&mut self, slot: u8) -> Result<RGBA, WriteError> {
+        self.write(QoiOp::Index(slot))
+    }
+
+    /// Each difference from the previous pixel is from -2 to 1
+    pub fn diff(&mut self, dr: i8, dg: i8, db: i8) -> Result<RGBA, WriteError> {
+        self.write(QoiOp::Diff { dr, dg, db })
+    }
+
+    /// `dg` is from -32 to 31, and the red and blue differences relative to it are from -8 to 7
+    pub fn luma(&mut self, dg: i8, dr_dg: i8, db_dg: i8) -> Result<RGBA, WriteError> {
+        self.write(QoiOp::Luma { dg, dr_dg, db_dg })
+    }
+
+    /// Repeats the previous pixel from 1 to 62 times
+    pub fn run(&mut self, length: u8) -> Result<RGBA, WriteError> {
+        self.write(QoiOp::Run(length))
+    }
+
+    /// Appends the end marker, once every pixel of the image has been written
+    pub fn finish(self) -> Result<(), WriteError> {
+        let expected = self.header.image_size();
+        if self.pixels_written != expected {
+            return Err(WriteError::MissingPixels {
+                written: self.pixels_written,
+                expected,
+            });
+        }
+        self.output.extend(END_8);
+        Ok(())
+    }
+}
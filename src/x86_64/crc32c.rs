@@ -0,0 +1,104 @@
+#[cfg(target_feature = "sse4.2")]
+use core::arch::asm;
+
+#[cfg(target_feature = "sse4.2")]
+use lazy_static::lazy_static;
+
+/// CRC-32C, the Castagnoli polynomial that SSE4.2 has an instruction for, bit reversed
+const POLYNOMIAL: u32 = 0x82f63b78;
+
+/// Slicing by 8 tables, `TABLES[k][byte]` being the CRC of `byte` followed by `k` zero bytes
+const TABLES: [[u32; 256]; 8] = {
+    let mut tables = [[0; 256]; 8];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut crc = byte as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = (crc >> 1) ^ (POLYNOMIAL & (crc & 1).wrapping_neg());
+            bit += 1;
+        }
+        tables[0][byte] = crc;
+        byte += 1;
+    }
+    let mut k = 1;
+    while k < 8 {
+        let mut byte = 0;
+        while byte < 256 {
+            let previous = tables[k - 1][byte];
+            tables[k][byte] = (previous >> 8) ^ tables[0][(previous & 0xff) as usize];
+            byte += 1;
+        }
+        k += 1;
+    }
+    tables
+};
+
+#[cfg(target_feature = "sse4.2")]
+lazy_static! {
+    static ref HAS_CRC32_INSTRUCTION: bool = raw_cpuid::CpuId::new()
+        .get_feature_info()
+        .is_some_and(|features| features.has_sse42());
+}
+
+fn update_scalar(mut crc: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(8);
+    for chunk in chunks.by_ref() {
+        let low = crc ^ u32::from_le_bytes(chunk[0..4].try_into().unwrap());
+        let high = u32::from_le_bytes(chunk[4..8].try_into().unwrap());
+        crc = TABLES[7][(low & 0xff) as usize]
+            ^ TABLES[6][(low >> 8 & 0xff) as usize]
+            ^ TABLES[5][(low >> 16 & 0xff) as usize]
+            ^ TABLES[4][(low >> 24) as usize]
+            ^ TABLES[3][(high & 0xff) as usize]
+            ^ TABLES[2][(high >> 8 & 0xff) as usize]
+            ^ TABLES[1][(high >> 16 & 0xff) as usize]
+            ^ TABLES[0][(high >> 24) as usize];
+    }
+    for &byte in chunks.remainder() {
+        crc = (crc >> 8) ^ TABLES[0][((crc ^ byte as u32) & 0xff) as usize];
+    }
+    crc
+}
+
+#[cfg(target_feature = "sse4.2")]
+fn update_sse42(mut crc: u32, data: &[u8]) -> u32 {
+    let (words, rest) = data.split_at(data.len() / 8 * 8);
+    if !words.is_empty() {
+        unsafe {
+            asm!(
+            "2:",
+            "crc32      {crc:r},    qword ptr [{data_ptr}]",
+            "add        {data_ptr}, 8",
+            "sub        {words},    1",
+            "jnz        2b",
+
+            crc         = inout(reg)    crc,
+            data_ptr    = inout(reg)    words.as_ptr() => _,
+            words       = inout(reg)    words.len() / 8 => _,
+            options(readonly, nostack)
+            )
+        };
+    }
+    for &byte in rest {
+        unsafe {
+            asm!(
+            "crc32      {crc:e},    {byte}",
+
+            crc         = inout(reg)    crc,
+            byte        = in(reg_byte)  byte,
+            options(pure, nomem, nostack)
+            )
+        };
+    }
+    crc
+}
+
+/// The CRC-32C of `data`, with the SSE4.2 instruction when it was compiled in and the CPU has it
+pub(crate) fn crc32c(data: &[u8]) -> u32 {
+    #[cfg(target_feature = "sse4.2")]
+    if *HAS_CRC32_INSTRUCTION {
+        return !update_sse42(!0, data);
+    }
+    !update_scalar(!0, data)
+}
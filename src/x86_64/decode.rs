@@ -1,49 +1,152 @@
 use alloc::vec::Vec;
 use core::arch::asm;
+use core::mem::MaybeUninit;
+use core::slice;
 
+use crate::alpha::AlphaMode;
 use crate::common::{
-    QOIHeader, END_8, QOI_OP_DIFF, QOI_OP_INDEX, QOI_OP_LUMA, QOI_OP_RGB, QOI_OP_RGBA, QOI_OP_RUN,
-    RGBA,
+    compact_rgba8_to_rgb8, QOIHeader, END_8, QOI_OP_DIFF, QOI_OP_INDEX, QOI_OP_LUMA, QOI_OP_RGB,
+    QOI_OP_RGBA, QOI_OP_RUN, RGBA,
 };
+use crate::layout::{
+    Channel, ChannelStore, DecodeOptions, GrayStore, PitchedStore, PixelLayout, Rows, StoreStep,
+};
+use crate::metadata::strip_extensions;
+use crate::orientation::{Orientation, OrientedStore};
+use crate::scale::{ScaleFactor, ScaledStore};
+use crate::yuv::{YuvFormat, YuvOptions, YuvStore};
 
-use super::hashing::Hashing;
+use super::hashing::{hash_in_order, hash_rgba, update_with, Hashing};
+use super::special::{Backend, VectorizedHashing, HASH_MULTIPLIERS_RGBA};
+use super::HASH_RGBA_MANY;
 
-const RGBA_CHA_CHA: u128 = 0x80808080_0d0c0b0a_08070605_03020100_u128;
 const DIFF_MUL_DUP: u32 = 0x01004010_u32;
 const DIFF_MASK: u32 = 0x03030303_u32;
 
+/// The vectorized stores write a few pixels past the last valid one,
+/// so output buffers are reserved with this many spare pixels at the end
+pub(crate) const OUTPUT_SLACK: usize = 4;
+
+/// How many pixels the decoder keeps at once when it hands them all to a store step instead of the caller
+const WINDOW_PIXELS: usize = 1 << 16;
+
+/// The shuffles that rearrange what QOI holds in RGBA order into the order pixels are decoded in
+struct DecodeOrder {
+    /// For two or three RGBA ops in a row, each shuffled out from behind its op byte
+    three_rgba: u128,
+    /// For one pixel or its deltas, from RGBA
+    one_rgba: u32,
+    /// For an RGB op with its op byte in front, leaving alpha zero for the previous pixel's to go in
+    rgb: u32,
+    /// Where alpha is
+    alpha_mask: u32,
+    /// QOI's hash weights rearranged to match, to hash pixels in this order with
+    multipliers: u32,
+}
+
+impl DecodeOrder {
+    fn new(layout: PixelLayout) -> Self {
+        let order = layout.order();
+        let mut three_rgba = [0x80u8; 16];
+        for (index, byte) in three_rgba[..12].iter_mut().enumerate() {
+            *byte = (index / 4 * 5) as u8 + order[index % 4];
+        }
+        Self {
+            three_rgba: u128::from_ne_bytes(three_rgba),
+            one_rgba: u32::from_ne_bytes(order),
+            rgb: u32::from_ne_bytes(order.map(|index| match index {
+                3 => 0x80,
+                _ => index + 1,
+            })),
+            alpha_mask: layout.from_rgba(0xff000000),
+            multipliers: layout.from_rgba(HASH_MULTIPLIERS_RGBA),
+        }
+    }
+}
+
 // ed is the encoding duration
-pub(crate) struct DecodeContext<'ed> {
-    input_buffer: &'ed Vec<u8>,
-    pub(crate) output_buffer: &'ed mut Vec<RGBA>,
+/// Without alpha to convert, the decoder works in the channel order of `options.layout` all along and stores pixels
+/// as they come. `CONVERTING` is whether alpha is converted too, in which case the decoder works in RGBA, can't read
+/// pixels back from the output, and so keeps the hash index array up to date as it goes rather than lazily.
+pub(crate) struct DecodeContext<'ed, const CONVERTING: bool> {
+    input_buffer: &'ed [u8],
+    output_buffer: &'ed mut [MaybeUninit<RGBA>],
+    output_len: usize,
     last_hash_update: usize,
+    /// Where the output goes once it's full, when it's a window to recycle rather than the whole image
+    store: Option<&'ed mut dyn StoreStep>,
+    /// When `output_len` goes past this, the output is a window to recycle rather than the whole image
+    window_limit: usize,
+    /// Pixels that were in the window before it was last recycled
+    recycled: usize,
     hash_index_array: [RGBA; 64],
     input_position: usize,
-    pub(crate) previous_pixel: *const RGBA,
+    /// The last pixel decoded, before any conversion
+    pub(crate) previous_pixel: RGBA,
+    hashing: &'ed dyn VectorizedHashing,
+    options: DecodeOptions,
+    order: DecodeOrder,
 }
 
-impl<'ed> DecodeContext<'ed> {
-    pub fn new(input_buffer: &'ed Vec<u8>, output_buffer: &'ed mut Vec<RGBA>) -> Self {
+impl<'ed, const CONVERTING: bool> DecodeContext<'ed, CONVERTING> {
+    pub fn new(
+        input_buffer: &'ed [u8],
+        output_buffer: &'ed mut [MaybeUninit<RGBA>],
+        hashing: &'ed dyn VectorizedHashing,
+        options: DecodeOptions,
+    ) -> Self {
+        let order = match CONVERTING {
+            true => PixelLayout::Rgba,
+            false => options.layout,
+        };
         Self {
             input_buffer,
             output_buffer,
+            output_len: 0,
 
             last_hash_update: 0,
+            store: None,
+            window_limit: usize::MAX,
+            recycled: 0,
             hash_index_array: [0u32; 64],
             input_position: 14,
-            previous_pixel: &0xff000000u32,
+            previous_pixel: *order.opaque_black(),
+            hashing,
+            options,
+            order: DecodeOrder::new(order),
         }
     }
 
     unsafe fn get_output_ptr(&mut self) -> *mut RGBA {
-        self.output_buffer
-            .as_mut_ptr()
-            .add(self.output_buffer.len())
+        self.output_buffer.as_mut_ptr().add(self.output_len) as *mut RGBA
     }
 
     unsafe fn register_more_output(&mut self, additional: usize) {
-        self.output_buffer
-            .set_len(self.output_buffer.len() + additional);
+        self.output_len += additional;
+    }
+
+    /// How `pixel` is stored, when it's already in the order the decoder works in
+    #[inline(always)]
+    fn convert(&self, pixel: RGBA) -> RGBA {
+        match CONVERTING {
+            true => self
+                .options
+                .layout
+                .from_rgba(self.options.alpha.convert(pixel)),
+            false => pixel,
+        }
+    }
+
+    /// Stores the next pixel, converting it on the way if need be
+    #[inline(always)]
+    unsafe fn push(&mut self, pixel: RGBA) {
+        self.previous_pixel = pixel;
+        if CONVERTING {
+            *self.hash_index_array.fetch_mut(hash_rgba(&pixel)) = pixel;
+        }
+        let stored = self.convert(pixel);
+        self.get_output_ptr().write(stored);
+        self.register_more_output(1);
     }
 
     /// An easily inlinable function that expands the OP_DIFF byte into an array
@@ -58,12 +161,42 @@ impl<'ed> DecodeContext<'ed> {
         QOI_OP_RUN <= byte && byte < QOI_OP_RGB
     }
 
-    fn update_hia(&mut self) {
-        let untouched_pixels = &self.output_buffer[self.last_hash_update..];
-        self.hash_index_array.update(untouched_pixels);
+    unsafe fn update_hia(&mut self) {
+        if CONVERTING {
+            return;
+        }
+        // everything before output_len has been written, so it can be viewed as initialized
+        let untouched_pixels = slice::from_raw_parts(
+            self.output_buffer.as_ptr().add(self.last_hash_update) as *const RGBA,
+            self.output_len - self.last_hash_update,
+        );
+        update_with(
+            &mut self.hash_index_array,
+            untouched_pixels,
+            self.hashing,
+            self.order.multipliers,
+        );
+    }
+
+    /// Hands everything in the window to the store, if there is one
+    unsafe fn finish_window(&mut self) {
+        if let Some(store) = &mut self.store {
+            let pixels =
+                slice::from_raw_parts(self.output_buffer.as_ptr() as *const RGBA, self.output_len);
+            store.finish(pixels);
+        }
+    }
+
+    /// Makes room in a window by finishing all of it and starting over from the front.
+    /// Every pixel in it is hashed by then, so the decoder never reads them again.
+    unsafe fn recycle(&mut self) {
+        self.update_hia();
+        self.finish_window();
+        self.recycled += self.output_len;
+        self.output_len = 0;
+        self.last_hash_update = 0;
     }
 
-    #[inline(always)]
     pub(crate) fn pos(&self) -> usize {
         self.input_position
     }
@@ -83,35 +216,29 @@ impl<'ed> DecodeContext<'ed> {
         self.input_buffer[self.input_position + offset]
     }
 
-    #[inline(always)]
-    pub(crate) unsafe fn update_previous_ptr(&mut self) {
-        self.previous_pixel = self.get_output_ptr() as *const u32;
-    }
-
     pub(crate) unsafe fn load_some_rgba(&mut self) {
         self.input_position += 1;
-        // look ahead to see if there are multiple
+        // look ahead to see if there are multiple, which only helps when they can be stored as they are
 
-        if self.get_byte_with_offset(4) == QOI_OP_RGBA {
+        if !CONVERTING && self.get_byte_with_offset(4) == QOI_OP_RGBA {
             // whether there are two or three, it still helps to move them together
             let theres_three_actually = self.get_byte_with_offset(9) == QOI_OP_RGBA;
             let n_added = 2 + theres_three_actually as usize;
 
             self.load_three_rgba();
             self.register_more_output(n_added);
-            self.previous_pixel = (self.get_output_ptr() as *const RGBA).sub(1);
+            self.previous_pixel = *self.get_output_ptr().sub(1);
 
             self.input_position += (5 * n_added) - 1;
         } else {
-            self.load_one_rgba();
-            self.update_previous_ptr();
-            self.register_more_output(1);
+            let pixel = self.load_one_rgba();
+            self.push(pixel);
             self.input_position += 4;
         }
     }
 
     /// This function takes a string of two pr three encoded RGBA OPs and uses a shuffle to remove
-    /// the OP byte from each of them and write them directly to the output pixel buffer.
+    /// the OP byte from each of them, rearrange their channels and write them directly to the output pixel buffer.
     /// As a minor side effect, leftovers are also written to the end of the buffer but will be
     /// overwritten by the next write to it because the pointer only gets incremented based on the
     /// number of valid RGBAs added.
@@ -127,7 +254,7 @@ impl<'ed> DecodeContext<'ed> {
 
         in_ptr      = in(reg)       self.get_byte_ref(),
         output_ptr  = in(reg)       self.get_output_ptr(),
-        shuffle_ptr = in(reg)       &RGBA_CHA_CHA,
+        shuffle_ptr = in(reg)       &self.order.three_rgba,
 
         staging     = out(xmm_reg)  _,
         shuffler    = out(xmm_reg)  _,
@@ -137,64 +264,89 @@ impl<'ed> DecodeContext<'ed> {
     }
 
     #[inline(always)]
-    unsafe fn load_one_rgba(&mut self) {
-        self.get_output_ptr()
-            .write_unaligned((self.get_byte_ref() as *const u8 as *const RGBA).read_unaligned());
+    unsafe fn load_one_rgba(&self) -> RGBA {
+        let pixel: RGBA;
+        asm!(
+        "movd       {pixel_xmm},    [{rgba}]",
+        "movd       {shuffler},     {shuffle:e}",
+        "pshufb     {pixel_xmm},    {shuffler}",
+        "movd       {pixel:e},      {pixel_xmm}",
+
+        rgba        = in(reg)       self.get_byte_ref(),
+        shuffle     = in(reg)       self.order.one_rgba,
+        pixel       = lateout(reg)  pixel,
+
+        pixel_xmm   = out(xmm_reg)  _,
+        shuffler    = out(xmm_reg)  _,
+
+        options(nostack, pure, readonly, preserves_flags)
+        );
+        pixel
     }
 
     pub(crate) unsafe fn load_one_rgb(&mut self) {
+        let pixel: RGBA;
         asm!(
-        // get the red, green, and blue, with the op also in the lowest byte
-        "mov       {staging:e},      [{orgb}]",
-        // overwrite the op with the alpha of the previous pixel, such that the staging is now argb
-        "mov       {staging:l},     [{prev}+3]",
-        // move the a so that it is rgba
-        "ror        {staging:e}, 8",
-        // put the resulting pixel in to the output buffer
-        "mov        [{output}],     {staging:e}",
+        // get the red, green, and blue, with the op in the lowest byte
+        "movd       {pixel_xmm},    [{orgb}]",
+        // shuffle the op out and the channels into order, leaving alpha zero
+        "movd       {shuffler},     {shuffle:e}",
+        "pshufb     {pixel_xmm},    {shuffler}",
+        "movd       {pixel:e},      {pixel_xmm}",
+        // and put the alpha of the previous pixel in
+        "or         {pixel:e},      {alpha:e}",
 
         orgb        = in(reg)       self.get_byte_ref(),
-        prev        = in(reg)       self.previous_pixel,
-        output      = in(reg)       self.get_output_ptr(),
-        staging     = out(reg)  _,
+        shuffle     = in(reg)       self.order.rgb,
+        alpha       = in(reg)       self.previous_pixel & self.order.alpha_mask,
+        pixel       = out(reg)      pixel,
 
-        options(nostack, preserves_flags)
+        pixel_xmm   = out(xmm_reg)  _,
+        shuffler    = out(xmm_reg)  _,
+
+        options(nostack, pure, readonly)
         );
-        self.update_previous_ptr();
-        self.register_more_output(1);
+        self.push(pixel);
         self.input_position += 4;
     }
 
     pub(crate) unsafe fn load_diff(&mut self) {
         let diff = Self::op_diff_expand222(self.get_byte());
+        let pixel: RGBA;
         asm!(
-        "movd       {pixel_xmm},    [{prev}]",
+        "movd       {pixel_xmm},    {prev:e}",
         "movd       {diff_xmm},     {diff:e}",
         "movd       {bias_xmm},     {bias:e}",
 
+        "movd       {shuffler},     {shuffle:e}",
+
+        // unbias the deltas and put them in the same order as the pixel
+        "psubb      {diff_xmm},     {bias_xmm}",
+        "pshufb     {diff_xmm},     {shuffler}",
         "paddb      {pixel_xmm},    {diff_xmm}",
-        "psubb      {pixel_xmm},    {bias_xmm}",
 
-        "movd       [{output}],     {pixel_xmm}",
+        "movd       {pixel:e},      {pixel_xmm}",
 
         prev        = in(reg)       self.previous_pixel,
         diff        = in(reg)       diff,
         bias        = in(reg)       0x00020202_u32,
-        output      = in(reg)       self.get_output_ptr(),
+        shuffle     = in(reg)       self.order.one_rgba,
+        pixel       = lateout(reg)  pixel,
 
         pixel_xmm   = out(xmm_reg)  _,
         diff_xmm    = out(xmm_reg)  _,
         bias_xmm    = out(xmm_reg)  _,
+        shuffler    = out(xmm_reg)  _,
 
-        options(nostack, preserves_flags)
+        options(nostack, pure, nomem, preserves_flags)
         );
 
-        self.update_previous_ptr();
-        self.register_more_output(1);
+        self.push(pixel);
         self.input_position += 1;
     }
 
     pub(crate) unsafe fn load_one_luma(&mut self) {
+        let pixel: RGBA;
         asm!(
         " # LLVM-MCA-BEGIN luma",
 
@@ -218,26 +370,31 @@ impl<'ed> DecodeContext<'ed> {
         // (dr - dg + 8) + (dg - 8) = (dr), (8) + (dg - 8) = (dg), (db - dg + 8) + (dg - 8) = (db)
         // so in summary we now have unbiased, raw deltas
         "paddb  {deltas},   {greens}",
-        "movd   {pixel},    [{previous_ptr}]",
-        "paddb  {pixel},    {deltas}",
-        "movd   [{out}],    {pixel}",
+        // then put them in the same order as the pixel
+        "movd   {shuffler},     {shuffle:e}",
+        "pshufb {deltas},       {shuffler}",
+        "movd   {pixel_xmm},    {previous:e}",
+        "paddb  {pixel_xmm},    {deltas}",
+        "movd   {pixel:e},      {pixel_xmm}",
         " # LLVM-MCA-END luma",
 
         op_ptr = in(reg) self.get_byte_ref(),
-        previous_ptr = in(reg) self.previous_pixel,
-        out = in(reg) self.get_output_ptr(),
+        previous = in(reg) self.previous_pixel,
+        shuffle = in(reg) self.order.one_rgba,
+        pixel = lateout(reg) pixel,
 
         staging = out(reg_abcd) _,
         green = out(reg) _,
 
         greens = lateout(xmm_reg) _,
         deltas = out(xmm_reg) _,
-        pixel = out(xmm_reg) _,
+        pixel_xmm = out(xmm_reg) _,
+        shuffler = out(xmm_reg) _,
 
+        options(nostack, pure, readonly)
         );
 
-        self.update_previous_ptr();
-        self.register_more_output(1);
+        self.push(pixel);
         self.input_position += 2;
     }
 
@@ -275,30 +432,39 @@ impl<'ed> DecodeContext<'ed> {
     }
 
     #[inline(always)]
-    unsafe fn store_run(&mut self, length: usize) {
+    unsafe fn store_run(&mut self, length: usize, pixel: RGBA) {
         asm!(
         "cld",
         "rep stosd",
         in("rcx") length + 1,
         in("rdi") self.get_output_ptr(),
-        in("eax") *self.previous_pixel,
+        in("eax") pixel,
         )
     }
 
     pub(crate) unsafe fn load_run(&mut self) {
         self.update_hia();
-        let run_length = self.scan_run_length();
-        self.store_run(run_length);
+        let mut run_length = self.scan_run_length();
+        let pixel = self.convert(self.previous_pixel);
+        // a run can be longer than a whole window, in which case it's stored a window's worth at a time
+        while self.output_len + run_length > self.window_limit {
+            let room = self.window_limit - self.output_len;
+            self.store_run(room, pixel);
+            self.register_more_output(room);
+            run_length -= room;
+            self.recycle();
+        }
+        self.store_run(run_length, pixel);
         self.register_more_output(run_length);
-        self.last_hash_update = self.output_buffer.len();
+        self.last_hash_update = self.output_len;
     }
 
     pub(crate) unsafe fn load_index(&mut self) {
         self.update_hia();
-        self.last_hash_update = self.output_buffer.len();
-        self.update_previous_ptr();
-        self.output_buffer
-            .push(*self.hash_index_array.fetch(self.get_byte()));
+        let pixel = *self.hash_index_array.fetch(self.get_byte());
+        self.push(pixel);
+        // the pixel is in the hash index array already
+        self.last_hash_update = self.output_len;
         self.input_position += 1;
     }
 }
@@ -308,25 +474,334 @@ const QOI_OP_DIFF_MAX: u8 = QOI_OP_LUMA - 1;
 const QOI_OP_LUMA_MAX: u8 = QOI_OP_RUN - 1;
 const QOI_OP_RUN_MAX: u8 = QOI_OP_RGB - 1;
 
+/// Decodes `input` into RGBA pixels appended to `output`. Errors with the number of pixels decoded and the number
+/// the header says there are if they differ. Like every decode function, it trusts its input, so `validate`
+/// anything that might be malformed first. It doesn't check the pixels against an integrity trailer,
+/// `integrity::decode_checked` does.
 #[inline(never)]
-pub fn decode(input: &Vec<u8>, output: &mut Vec<RGBA>) -> Result<(), (usize, usize)> {
-    let header = QOIHeader::from(input.as_slice());
+pub fn decode(input: &[u8], output: &mut Vec<RGBA>) -> Result<(), (usize, usize)> {
+    decode_with_backend(input, output, Backend::detected())
+}
+
+/// Same as `decode`, but hashing with a specific backend instead of the fastest one.
+/// Panics if the backend isn't available, check with `Backend::is_available` first.
+pub fn decode_with_backend(
+    input: &[u8],
+    output: &mut Vec<RGBA>,
+    backend: Backend,
+) -> Result<(), (usize, usize)> {
+    decode_into(input, output, backend.hashing(), DecodeOptions::default())
+}
+
+/// Same as `decode`, but converting the pixels to premultiplied alpha or flattening them as they're stored
+pub fn decode_with_alpha(
+    input: &[u8],
+    output: &mut Vec<RGBA>,
+    alpha: AlphaMode,
+) -> Result<(), (usize, usize)> {
+    let options = DecodeOptions {
+        alpha,
+        ..DecodeOptions::default()
+    };
+    decode_with_options(input, output, options)
+}
+
+/// Same as `decode`, but converting alpha and rearranging the channels as the pixels are stored
+pub fn decode_with_options(
+    input: &[u8],
+    output: &mut Vec<RGBA>,
+    options: DecodeOptions,
+) -> Result<(), (usize, usize)> {
+    decode_into(input, output, *HASH_RGBA_MANY, options)
+}
+
+/// Decodes just one channel of the image into `output`, one byte per pixel
+pub fn decode_channel(
+    input: &[u8],
+    output: &mut Vec<u8>,
+    channel: Channel,
+) -> Result<(), (usize, usize)> {
+    let header = QOIHeader::from(input);
     output.reserve_exact(header.image_size());
-    let mut ctx: DecodeContext = DecodeContext::new(input, output);
+    let mut store = ChannelStore { channel, output };
+    let decoded = decode_through(input, DecodeOptions::default(), &mut store);
+    expect_image_size(&header, decoded)
+}
+
+/// Decodes into one byte per pixel if every pixel is opaque gray, returning `true`, or into RGBA bytes otherwise,
+/// returning `false`. It's one pass either way, since the gray bytes written before the first pixel that isn't gray
+/// are widened to RGBA where they are.
+pub fn decode_gray(input: &[u8], output: &mut Vec<u8>) -> Result<bool, (usize, usize)> {
+    let header = QOIHeader::from(input);
+    output.reserve_exact(header.image_size());
+    let mut store = GrayStore {
+        start: output.len(),
+        output,
+        gray: true,
+    };
+    let decoded = decode_through(input, DecodeOptions::default(), &mut store);
+    let gray = store.gray;
+    expect_image_size(&header, decoded).map(|()| gray)
+}
+
+/// Decodes into rows `pitch` bytes apart, such as an aligned GPU upload buffer, leaving the padding between them alone.
+/// Panics if `output` is too small for the image.
+pub fn decode_pitched(
+    input: &[u8],
+    output: &mut [u8],
+    pitch: usize,
+    options: DecodeOptions,
+) -> Result<(), (usize, usize)> {
+    let header = QOIHeader::from(input);
+    let row_bytes = header.width as usize * core::mem::size_of::<RGBA>();
+    let height = header.height as usize;
+    assert!(
+        pitch >= row_bytes,
+        "rows of {row_bytes} bytes can't be {pitch} bytes apart"
+    );
+    assert!(height == 0 || output.len() >= (height - 1) * pitch + row_bytes);
+
+    let mut store = PitchedStore {
+        output,
+        pitch,
+        rows: Rows::new(header.width as usize, height),
+    };
+    expect_image_size(&header, decode_through(input, options, &mut store))
+}
+
+/// Decodes the image flipped or rotated, with each pixel written straight to where it ends up rather than
+/// transformed afterwards, so no second copy of the image is needed. `Orientation::oriented_size` gives its size.
+/// `output` is left as it was if the image is missing pixels.
+pub fn decode_oriented(
+    input: &[u8],
+    output: &mut Vec<RGBA>,
+    orientation: Orientation,
+    options: DecodeOptions,
+) -> Result<(), (usize, usize)> {
+    let header = QOIHeader::from(input);
+    let image_size = header.image_size();
+    output.reserve_exact(image_size);
+
+    let mut store = OrientedStore {
+        orientation,
+        output: &mut output.spare_capacity_mut()[..image_size],
+        rows: Rows::new(header.width as usize, header.height as usize),
+    };
+    let decoded = decode_through(input, options, &mut store);
+    // every pixel has been written somewhere in the spare capacity only if there were enough of them
+    if decoded >= image_size {
+        unsafe { output.set_len(output.len() + image_size) };
+    }
+    expect_image_size(&header, decoded)
+}
+
+/// Decodes the image shrunk by `factor`, averaging each box of pixels as its rows come in, for thumbnails.
+/// Every op is still read, but the full size image is never held, only one band of rows' worth of sums.
+/// `ScaleFactor::scaled_size` gives the size of the result. `output` is left as it was if the image is missing pixels.
+pub fn decode_scaled(
+    input: &[u8],
+    output: &mut Vec<RGBA>,
+    factor: ScaleFactor,
+) -> Result<(), (usize, usize)> {
+    let header = QOIHeader::from(input);
+    let (width, height) = factor.scaled_size(header.width, header.height);
+    let start = output.len();
+    output.reserve_exact(width as usize * height as usize);
+
+    let mut store = ScaledStore {
+        output,
+        sums: alloc::vec![[0; 4]; width as usize],
+        divisor: factor.divisor(),
+        rows: Rows::new(header.width as usize, header.height as usize),
+    };
+    let decoded = decode_through(input, DecodeOptions::default(), &mut store);
+    let result = expect_image_size(&header, decoded);
+    if result.is_err() {
+        output.truncate(start);
+    }
+    result
+}
+
+/// Decodes into a YUV frame, `format.frame_size()` bytes appended to `output`, for a capture pipeline that works in YUV.
+/// Y is written as each pixel comes in, and U and V once each box of pixels sharing them is complete, averaging them.
+/// Alpha is dropped. `output` is left as it was if the image is missing pixels.
+pub fn decode_yuv(
+    input: &[u8],
+    output: &mut Vec<u8>,
+    format: YuvFormat,
+    options: YuvOptions,
+) -> Result<(), (usize, usize)> {
+    let header = QOIHeader::from(input);
+    let (width, height) = (header.width as usize, header.height as usize);
+    let start = output.len();
+    output.resize(start + format.frame_size(width, height), 0);
+
+    let mut store = YuvStore {
+        format,
+        coefficients: options.coefficients(),
+        frame: &mut output[start..],
+        sums: alloc::vec![[0; 3]; width.div_ceil(2)],
+        rows: Rows::new(width, height),
+    };
+    let decoded = decode_through(input, DecodeOptions::default(), &mut store);
+    let result = expect_image_size(&header, decoded);
+    if result.is_err() {
+        output.truncate(start);
+    }
+    result
+}
+
+fn expect_image_size(header: &QOIHeader, decoded: usize) -> Result<(), (usize, usize)> {
+    match decoded == header.image_size() {
+        true => Ok(()),
+        false => Err((decoded, header.image_size())),
+    }
+}
 
+fn decode_into(
+    input: &[u8],
+    output: &mut Vec<RGBA>,
+    hashing: &dyn VectorizedHashing,
+    options: DecodeOptions,
+) -> Result<(), (usize, usize)> {
+    let header = QOIHeader::from(input);
+    output.reserve_exact(header.image_size() + OUTPUT_SLACK);
+
+    let written = decode_pixels(input, output.spare_capacity_mut(), hashing, options);
+    unsafe { output.set_len(output.len() + written) };
+
+    expect_image_size(&header, written)
+}
+
+/// Decodes into the RGBA8 byte buffer `output` instead of a buffer of whole pixels,
+/// which lets callers hand the result to APIs that own their storage as bytes.
+pub fn decode_to_bytes(input: &[u8], output: &mut Vec<u8>) -> Result<(), (usize, usize)> {
+    decode_to_bytes_with_options(input, output, DecodeOptions::default())
+}
+
+/// `decode_to_bytes` with an alpha mode, as for `decode_with_alpha`
+pub fn decode_to_bytes_with_alpha(
+    input: &[u8],
+    output: &mut Vec<u8>,
+    alpha: AlphaMode,
+) -> Result<(), (usize, usize)> {
+    let options = DecodeOptions {
+        alpha,
+        ..DecodeOptions::default()
+    };
+    decode_to_bytes_with_options(input, output, options)
+}
+
+/// `decode_to_bytes` with an alpha mode and channel order, as for `decode_with_options`
+pub fn decode_to_bytes_with_options(
+    input: &[u8],
+    output: &mut Vec<u8>,
+    options: DecodeOptions,
+) -> Result<(), (usize, usize)> {
+    let header = QOIHeader::from(input);
+    let pixel_size = core::mem::size_of::<RGBA>();
+    let start = output.len();
+    output.reserve_exact((header.image_size() + OUTPUT_SLACK) * pixel_size);
+
+    match unsafe { output.spare_capacity_mut().align_to_mut() } {
+        ([], pixels, _) => {
+            let written = decode_pixels(input, pixels, *HASH_RGBA_MANY, options);
+            unsafe { output.set_len(output.len() + written * pixel_size) };
+        }
+        _ => {
+            // the allocator handed back storage that can't hold aligned pixels, so go the long way
+            let mut pixels = Vec::with_capacity(header.image_size() + OUTPUT_SLACK);
+            let written =
+                decode_pixels(input, pixels.spare_capacity_mut(), *HASH_RGBA_MANY, options);
+            unsafe { pixels.set_len(written) };
+            output.extend_from_slice(bytemuck::cast_slice::<RGBA, u8>(&pixels));
+        }
+    }
+
+    expect_image_size(&header, (output.len() - start) / pixel_size)
+}
+
+/// Decodes into RGB8 bytes, blending any transparent pixels over `background`
+pub fn decode_flattened_rgb(
+    input: &[u8],
+    output: &mut Vec<u8>,
+    background: [u8; 3],
+) -> Result<(), (usize, usize)> {
+    let [r, g, b] = background;
+    let background = RGBA::from_ne_bytes([r, g, b, 0xff]);
+    let start = output.len();
+    let result = decode_to_bytes_with_alpha(input, output, AlphaMode::Flatten(background));
+    let mut pixels = output.split_off(start);
+    compact_rgba8_to_rgb8(&mut pixels);
+    output.append(&mut pixels);
+    result
+}
+
+/// Runs the decoding loop over `input`, writing pixels to the start of `output` converted as `options` says.
+/// Returns the number of pixels written, which is `image_size()` for a well-formed image.
+/// `output` must have room for the whole image plus `OUTPUT_SLACK` pixels.
+fn decode_pixels(
+    input: &[u8],
+    output: &mut [MaybeUninit<RGBA>],
+    hashing: &dyn VectorizedHashing,
+    options: DecodeOptions,
+) -> usize {
+    // the loop below runs up to the end marker, so it mustn't see any metadata or trailer after it
+    let input = strip_extensions(input);
+    let header = QOIHeader::from(input);
+    assert!(output.len() >= header.image_size() + OUTPUT_SLACK);
+    match options.alpha == AlphaMode::Straight {
+        true => run_ops(DecodeContext::<false>::new(input, output, hashing, options)),
+        false => run_ops(DecodeContext::<true>::new(input, output, hashing, options)),
+    }
+}
+
+/// Runs the decoding loop over `input` with a small window for output, handing every pixel to `store`
+/// converted as `options` says, for when the caller wants the pixels somewhere other than
+/// one whole image's worth of RGBA. Returns the number of pixels decoded.
+fn decode_through(input: &[u8], options: DecodeOptions, store: &mut dyn StoreStep) -> usize {
+    let input = strip_extensions(input);
+    let header = QOIHeader::from(input);
+    // a window smaller than the image would never be recycled, unless the image holds more pixels than it says
+    let mut window =
+        Vec::with_capacity(header.image_size().clamp(OUTPUT_SLACK, WINDOW_PIXELS) + OUTPUT_SLACK);
+    let output = window.spare_capacity_mut();
+    let window_limit = output.len() - OUTPUT_SLACK;
+    match options.alpha == AlphaMode::Straight {
+        true => {
+            let mut ctx = DecodeContext::<false>::new(input, output, *HASH_RGBA_MANY, options);
+            ctx.store = Some(store);
+            ctx.window_limit = window_limit;
+            run_ops(ctx)
+        }
+        false => {
+            let mut ctx = DecodeContext::<true>::new(input, output, *HASH_RGBA_MANY, options);
+            ctx.store = Some(store);
+            ctx.window_limit = window_limit;
+            run_ops(ctx)
+        }
+    }
+}
+
+fn run_ops<const CONVERTING: bool>(mut ctx: DecodeContext<CONVERTING>) -> usize {
+    let input = ctx.input_buffer;
     let len: usize = input.len() - 8;
 
     // if the first op is a run, black ends up not in the HIA because of the hash-skipping behaviour
-    if DecodeContext::is_run(ctx.get_byte()) {
+    if DecodeContext::<CONVERTING>::is_run(ctx.get_byte()) {
         // this fixes that
-        ctx.hash_index_array
-            .update([unsafe { *ctx.previous_pixel }].as_ref());
+        let black = ctx.previous_pixel;
+        ctx.hash_index_array[hash_in_order(black, ctx.order.multipliers) as usize] = black;
     }
 
     while ctx.input_position < len {
         let next_op: u8 = ctx.get_byte();
 
         unsafe {
+            // any op but a run writes at most four pixels, counting the spare ones
+            if ctx.output_len > ctx.window_limit {
+                ctx.recycle();
+            }
             match next_op {
                 QOI_OP_INDEX..=QOI_OP_INDEX_MAX => ctx.load_index(),
                 QOI_OP_DIFF..=QOI_OP_DIFF_MAX => ctx.load_diff(),
@@ -347,9 +822,6 @@ pub fn decode(input: &Vec<u8>, output: &mut Vec<RGBA>) -> Result<(), (usize, usi
         &input[(pos)..(pos + 8)]
     );
 
-    if header.image_size() == output.len() {
-        Ok(())
-    } else {
-        Err((output.len(), header.image_size()))
-    }
+    unsafe { ctx.finish_window() };
+    ctx.recycled + ctx.output_len
 }
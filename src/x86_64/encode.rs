@@ -2,109 +2,102 @@ use alloc::vec::Vec;
 use core::arch::asm;
 use core::mem::replace;
 
+use crate::alpha::unpremultiply;
 use crate::arch_switch::implementation::hashing::hash_rgba;
 use crate::common::{
     QOIHeader, END_8, HASH, QOI_OP_DIFF, QOI_OP_INDEX, QOI_OP_LUMA, QOI_OP_RGB, QOI_OP_RGBA,
     QOI_OP_RUN, RGBA,
 };
+use crate::layout::{NarrowFormat, PixelLayout};
+use crate::orientation::Orientation;
+use crate::stats::EncodeStats;
+use crate::yuv::{YuvFormat, YuvOptions};
 
-use super::HASH_RGBA_MANY;
+use super::special::{Backend, VectorizedHashing, HASH_MULTIPLIERS_RGBA};
+use super::yuv::convert_row;
 
 // ed is the encoding duration
 pub(crate) struct EncodeContext<'ed> {
-    pixel_count: usize,
-    input_pixels: &'ed Vec<RGBA>,
     output_bytes: &'ed mut Vec<u8>,
+    /// The hashes of the stretch of pixels being encoded
     hashes: Vec<HASH>,
     hash_index_array: [RGBA; 64],
-    position: usize,
-    previous_pixel: &'ed RGBA,
+    previous_pixel: RGBA,
+    /// The run the pixels so far end on, which the next stretch may carry on
+    run_length: usize,
+    pixels_encoded: usize,
+    hashing: &'ed dyn VectorizedHashing,
+    /// The channel order of the input pixels, which only matters for hashing and for the ops that hold channels
+    layout: PixelLayout,
 }
 
 impl<'ed> EncodeContext<'ed> {
-    pub fn new(input_pixels: &'ed Vec<RGBA>, output_bytes: &'ed mut Vec<u8>) -> Self {
-        let pixel_count = input_pixels.len();
+    /// `hashes` is only used for its allocation, so it can be handed back and reused
+    pub fn new(
+        output_bytes: &'ed mut Vec<u8>,
+        hashing: &'ed dyn VectorizedHashing,
+        hashes: Vec<HASH>,
+        layout: PixelLayout,
+    ) -> Self {
         Self {
-            pixel_count,
-            input_pixels,
             output_bytes,
-            hashes: Vec::with_capacity(pixel_count),
+            hashes,
             hash_index_array: [0u32; 64],
-            position: 0,
-            previous_pixel: &0xff000000u32,
+            previous_pixel: *layout.opaque_black(),
+            run_length: 0,
+            pixels_encoded: 0,
+            hashing,
+            layout,
         }
     }
 
     #[inline(never)]
-    pub fn initialize_hashes(&mut self) {
-        let (leftover_pixel_marker, end_of_current_hashes) = unsafe {
-            let chunk_count = self.pixel_count / HASH_RGBA_MANY.hash_chunk_size();
-
-            let start_pixel_ptr = self.input_pixels.as_ptr();
-            let start_hash_ptr = self.hashes.as_mut_ptr();
-            let (end_pixel_ptr, end_hash_ptr) =
-                HASH_RGBA_MANY.hash_chunks(start_pixel_ptr, start_hash_ptr, chunk_count);
-            let pixel_distance = end_pixel_ptr.offset_from(start_pixel_ptr);
-            let hash_distance = end_hash_ptr.offset_from(start_hash_ptr);
-            self.hashes.set_len(self.pixel_count);
-            (pixel_distance as usize, hash_distance as usize)
-        };
-        if leftover_pixel_marker == self.input_pixels.len() {
-            return;
-        }
-        for (offset, pixel) in self.input_pixels[leftover_pixel_marker..]
-            .iter()
-            .enumerate()
-        {
-            self.hashes[end_of_current_hashes + offset] = hash_rgba(pixel);
+    pub fn initialize_hashes(&mut self, input_pixels: &[RGBA]) {
+        let pixel_count = input_pixels.len();
+        self.hashes.clear();
+        self.hashes.reserve(pixel_count);
+        let chunk_count = pixel_count / self.hashing.hash_chunk_size();
+        // the SIMD kernels hash at least one chunk whatever the count
+        if chunk_count > 0 {
+            unsafe {
+                let start_hash_ptr = self.hashes.as_mut_ptr();
+                let (_, end_hash_ptr) = self.hashing.hash_chunks_with(
+                    input_pixels.as_ptr(),
+                    start_hash_ptr,
+                    chunk_count,
+                    self.layout.from_rgba(HASH_MULTIPLIERS_RGBA),
+                );
+                self.hashes
+                    .set_len(end_hash_ptr.offset_from(start_hash_ptr) as usize);
+            }
         }
-    }
-
-    pub fn get_pixel(&self) -> RGBA {
-        self.input_pixels[self.position]
-    }
-
-    unsafe fn get_pixel_ptr(&self) -> *const RGBA {
-        self.input_pixels.as_ptr().add(self.position)
+        let layout = self.layout;
+        let leftover_pixels = &input_pixels[self.hashes.len()..];
+        self.hashes.extend(
+            leftover_pixels
+                .iter()
+                .map(|pixel| hash_rgba(&layout.to_rgba(*pixel))),
+        );
     }
 
     unsafe fn get_output_ptr(&mut self) -> *mut u8 {
         self.output_bytes.as_mut_ptr().add(self.output_bytes.len())
     }
 
-    #[inline(always)]
-    pub fn get_hash(&self) -> HASH {
-        self.hashes[self.position]
-    }
-
-    #[inline(always)]
-    pub fn get_previous_pixel(&self) -> RGBA {
-        *self.previous_pixel
-    }
-
-    pub fn update_pos(&mut self) {
-        self.previous_pixel = &self.input_pixels[self.position];
-        self.position += 1;
-    }
-
-    pub fn swap_hash(&mut self) -> RGBA {
-        let pixel = self.get_pixel();
-        replace(&mut self.hash_index_array[self.get_hash() as usize], pixel)
-    }
-
-    pub fn write_rgba(&mut self) {
+    /// `pixel` is the current one, rearranged into RGBA
+    pub fn write_rgba(&mut self, pixel: RGBA) {
         self.output_bytes.push(QOI_OP_RGBA);
-        self.output_bytes.extend(self.get_pixel().to_ne_bytes());
+        self.output_bytes.extend(pixel.to_ne_bytes());
     }
 
-    pub fn write_rgb(&mut self) {
+    /// `pixel` is the current one, rearranged into RGBA
+    pub fn write_rgb(&mut self, pixel: RGBA) {
         self.output_bytes
-            .extend(((self.get_pixel() << 8) | QOI_OP_RGB as RGBA).to_ne_bytes());
+            .extend(((pixel << 8) | QOI_OP_RGB as RGBA).to_ne_bytes());
     }
 
-    pub fn write_hash_index(&mut self) {
-        self.output_bytes
-            .push(QOI_OP_INDEX | self.hashes[self.position]);
+    pub fn write_hash_index(&mut self, hash: HASH) {
+        self.output_bytes.push(QOI_OP_INDEX | hash);
     }
 
     pub fn write_diff(&mut self, deltas: [u8; 4]) {
@@ -117,8 +110,15 @@ impl<'ed> EncodeContext<'ed> {
         self.output_bytes.push((dr_dg << 4) | db_dg);
     }
 
+    /// Writes the run the pixels so far end on, if they do
     #[inline(always)]
-    pub fn write_run(&mut self, full_runs: usize, remainder: usize) {
+    pub fn write_run(&mut self) {
+        if self.run_length == 0 {
+            return;
+        }
+        let full_runs = self.run_length / 62;
+        let remainder = self.run_length % 62;
+        self.run_length = 0;
         let rem_op = QOI_OP_RUN | ((remainder as u8).wrapping_sub(1) & !QOI_OP_RUN);
 
         if full_runs > 0 {
@@ -136,74 +136,429 @@ impl<'ed> EncodeContext<'ed> {
         }
     }
 
-    #[inline(always)]
-    pub fn find_run_length_at_current_position(&mut self) -> (usize, usize) {
-        let total_run_length = unsafe {
-            let start_ptr = self.get_pixel_ptr();
-            let end_ptr: *const RGBA;
-
-            asm!(
-                "cld",
-                "mov eax, [rdi]",
-                "repe scasd",
-                inout("rdi") start_ptr => end_ptr,
-                inout("rcx") (self.input_pixels.len() - self.position) + 1 => _,
-                out("eax") _
-            );
-
-            let actual_end_ptr = end_ptr.sub(1);
-
-            actual_end_ptr.offset_from(start_ptr)
-        };
+    /// Writes the run the image ends on and returns how many pixels it had
+    pub fn finish(&mut self) -> usize {
+        self.write_run();
+        self.pixels_encoded
+    }
+}
 
-        debug_assert!(total_run_length > 0);
-        let total_run_length = total_run_length as usize;
-        self.position += total_run_length;
+/// How many pixels from `position` on are the same as the one there, without reading past the end
+#[inline(always)]
+fn find_run_length(input_pixels: &[RGBA], position: usize) -> usize {
+    let total_run_length = unsafe {
+        let start_ptr = input_pixels.as_ptr().add(position);
+        let end_ptr: *const RGBA;
+        let stopped_early: u8;
+
+        asm!(
+            "cld",
+            "mov eax, [rdi]",
+            "repe scasd",
+            "setne {stopped_early}",
+            inout("rdi") start_ptr => end_ptr,
+            inout("rcx") input_pixels.len() - position => _,
+            stopped_early = out(reg_byte) stopped_early,
+            out("eax") _
+        );
+
+        // scasd steps past the pixel that differed too, if one did
+        end_ptr.offset_from(start_ptr) as usize - stopped_early as usize
+    };
+
+    debug_assert!(total_run_length > 0);
+    total_run_length
+}
 
-        let n_max_runs = total_run_length / 62;
-        let remaining_run = total_run_length % 62;
+pub fn encode(
+    input_pixels: &[RGBA],
+    output_bytes: &mut Vec<u8>,
+    metadata: QOIHeader,
+) -> Result<(), (usize, usize)> {
+    Encoder::new().encode(input_pixels, output_bytes, metadata)
+}
 
-        (n_max_runs, remaining_run)
-    }
+/// Same as `encode`, but also reports which ops the image took and how well it compressed.
+/// Working that out walks the output once more, which plain `encode` skips.
+pub fn encode_with_stats(
+    input_pixels: &[RGBA],
+    output_bytes: &mut Vec<u8>,
+    metadata: QOIHeader,
+) -> Result<EncodeStats, (usize, usize)> {
+    Encoder::new().encode_with_stats(input_pixels, output_bytes, metadata)
 }
 
-pub fn encode(
-    input_pixels: &Vec<RGBA>,
+/// Same as `encode`, but for pixels in another channel order, see `Encoder::encode_with_layout`
+pub fn encode_with_layout(
+    input_pixels: &[RGBA],
     output_bytes: &mut Vec<u8>,
     metadata: QOIHeader,
+    layout: PixelLayout,
 ) -> Result<(), (usize, usize)> {
-    output_bytes.extend(metadata.to_bytes());
+    Encoder::new().encode_with_layout(input_pixels, output_bytes, metadata, layout)
+}
 
-    let pixel_count = metadata.image_size();
-    assert_eq!(input_pixels.len(), pixel_count);
+/// Same as `encode`, but flipping or rotating the image first, see `Encoder::encode_oriented`
+pub fn encode_oriented(
+    input_pixels: &[RGBA],
+    output_bytes: &mut Vec<u8>,
+    metadata: QOIHeader,
+    orientation: Orientation,
+) -> Result<(), (usize, usize)> {
+    Encoder::new().encode_oriented(input_pixels, output_bytes, metadata, orientation)
+}
 
-    // Create encoding context
-    let ctx = EncodeContext::new(input_pixels, output_bytes);
+/// Same as `encode`, but for gray or alpha-only pixels, see `Encoder::encode_narrow`
+pub fn encode_narrow(
+    input: &[u8],
+    output_bytes: &mut Vec<u8>,
+    metadata: QOIHeader,
+    format: NarrowFormat,
+) -> Result<(), (usize, usize)> {
+    Encoder::new().encode_narrow(input, output_bytes, metadata, format)
+}
 
-    encode_pixels(ctx)?;
-    output_bytes.extend(END_8);
-    Ok(())
+/// Same as `encode`, but for a YUV frame, see `Encoder::encode_yuv`
+pub fn encode_yuv(
+    frame: &[u8],
+    output_bytes: &mut Vec<u8>,
+    metadata: QOIHeader,
+    format: YuvFormat,
+    options: YuvOptions,
+) -> Result<(), (usize, usize)> {
+    Encoder::new().encode_yuv(frame, output_bytes, metadata, format, options)
 }
 
-#[inline(never)]
-fn encode_pixels(mut encode_context: EncodeContext) -> Result<(), (usize, usize)> {
-    encode_context.initialize_hashes();
+/// Same as `encode`, but for rows spaced out in a larger buffer, see `Encoder::encode_strided`
+pub fn encode_strided(
+    buffer: &[u8],
+    stride: usize,
+    output_bytes: &mut Vec<u8>,
+    metadata: QOIHeader,
+    layout: PixelLayout,
+) -> Result<(), (usize, usize)> {
+    Encoder::new().encode_strided(buffer, stride, output_bytes, metadata, layout)
+}
 
-    while encode_context.position < encode_context.pixel_count {
-        let pixel = encode_context.get_pixel();
-        let pixel_of_same_hash = encode_context.swap_hash();
+/// Same as `encode`, but for pixels with premultiplied alpha, see `Encoder::encode_premultiplied`
+pub fn encode_premultiplied(
+    input_pixels: &[RGBA],
+    output_bytes: &mut Vec<u8>,
+    metadata: QOIHeader,
+) -> Result<(), (usize, usize)> {
+    Encoder::new().encode_premultiplied(input_pixels, output_bytes, metadata)
+}
+
+/// Same as `encode`, but hashing with a specific backend instead of the fastest one.
+/// Panics if the backend isn't available, check with `Backend::is_available` first.
+pub fn encode_with_backend(
+    input_pixels: &[RGBA],
+    output_bytes: &mut Vec<u8>,
+    metadata: QOIHeader,
+    backend: Backend,
+) -> Result<(), (usize, usize)> {
+    Encoder::with_backend(backend).encode(input_pixels, output_bytes, metadata)
+}
+
+/// How many rows of a rotated or transposed image `Encoder::encode_oriented` gathers at once,
+/// enough to read a whole cache line from each row of the input
+const ORIENTED_BAND: usize = 16;
+
+/// How many pixels of input that needs converting first `Encoder` converts at a time, few enough to stay in cache
+const CONVERTED_CHUNK: usize = 4096;
+
+/// Encodes one image after another, keeping its scratch space between them
+/// instead of allocating it again for every image, which adds up over the frames of a video.
+pub struct Encoder {
+    backend: Backend,
+    hashing: &'static dyn VectorizedHashing,
+    hashes: Vec<HASH>,
+    /// Stretches of input that can't be encoded where they are, converted, widened, reordered or realigned
+    scratch: Vec<RGBA>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::with_backend(Backend::detected())
+    }
+
+    /// Panics if the backend isn't available, check with `Backend::is_available` first
+    pub fn with_backend(backend: Backend) -> Self {
+        Self {
+            backend,
+            hashing: backend.hashing(),
+            hashes: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Appends the encoded image to `output_bytes`, the same as `encode`
+    pub fn encode(
+        &mut self,
+        input_pixels: &[RGBA],
+        output_bytes: &mut Vec<u8>,
+        metadata: QOIHeader,
+    ) -> Result<(), (usize, usize)> {
+        self.encode_with_layout(input_pixels, output_bytes, metadata, PixelLayout::Rgba)
+    }
+
+    /// Same as `encode`, but for pixels with their channels in another order, such as BGRA from a screen capture.
+    /// The output is the same as rearranging them into RGBA and encoding that, without the pass over the image.
+    pub fn encode_with_layout(
+        &mut self,
+        input_pixels: &[RGBA],
+        output_bytes: &mut Vec<u8>,
+        metadata: QOIHeader,
+        layout: PixelLayout,
+    ) -> Result<(), (usize, usize)> {
+        assert_eq!(input_pixels.len(), metadata.image_size());
+        self.encode_stretches(output_bytes, metadata, layout, |ctx, _| {
+            encode_pixels(ctx, input_pixels)
+        })
+    }
+
+    /// Writes the header, has `feed` hand the image to the context one stretch after another in order,
+    /// along with scratch space to put stretches in, then writes the end marker.
+    /// Runs carry on from one stretch to the next, so the output is the same however the image is split up.
+    fn encode_stretches(
+        &mut self,
+        output_bytes: &mut Vec<u8>,
+        metadata: QOIHeader,
+        layout: PixelLayout,
+        feed: impl FnOnce(&mut EncodeContext, &mut Vec<RGBA>),
+    ) -> Result<(), (usize, usize)> {
+        output_bytes.extend(metadata.to_bytes());
+
+        let hashes = core::mem::take(&mut self.hashes);
+        let mut scratch = core::mem::take(&mut self.scratch);
+        let mut ctx = EncodeContext::new(output_bytes, self.hashing, hashes, layout);
+        feed(&mut ctx, &mut scratch);
+        let pixels_encoded = ctx.finish();
+        self.hashes = ctx.hashes;
+        self.scratch = scratch;
+
+        let pixel_count = metadata.image_size();
+        if pixels_encoded != pixel_count {
+            return Err((pixels_encoded, pixel_count));
+        }
+        output_bytes.extend(END_8);
+        Ok(())
+    }
+
+    /// Same as `encode`, but also reports which ops the image took and how well it compressed
+    pub fn encode_with_stats(
+        &mut self,
+        input_pixels: &[RGBA],
+        output_bytes: &mut Vec<u8>,
+        metadata: QOIHeader,
+    ) -> Result<EncodeStats, (usize, usize)> {
+        let start = output_bytes.len();
+        self.encode(input_pixels, output_bytes, metadata)?;
+        Ok(EncodeStats::from_stream(
+            &output_bytes[start..],
+            self.backend,
+        ))
+    }
+
+    /// Same as `encode`, but for pixels with premultiplied alpha, which QOI can't store.
+    /// They're un-premultiplied `CONVERTED_CHUNK` at a time into scratch space just before they're encoded,
+    /// exactly enough that premultiplying the decoded image gives them back.
+    pub fn encode_premultiplied(
+        &mut self,
+        input_pixels: &[RGBA],
+        output_bytes: &mut Vec<u8>,
+        metadata: QOIHeader,
+    ) -> Result<(), (usize, usize)> {
+        assert_eq!(input_pixels.len(), metadata.image_size());
+        self.encode_stretches(output_bytes, metadata, PixelLayout::Rgba, |ctx, scratch| {
+            for chunk in input_pixels.chunks(CONVERTED_CHUNK) {
+                scratch.clear();
+                scratch.extend(chunk.iter().map(|&pixel| unpremultiply(pixel)));
+                encode_pixels(ctx, scratch);
+            }
+        })
+    }
+
+    /// Same as `encode`, but flipping or rotating the image first. `metadata` describes the input,
+    /// and the header written has the transformed size. Rows are walked in the transformed order
+    /// rather than copying the whole image: upside down rows are encoded where they are,
+    /// mirrored ones reversed into a row of scratch space, and for rotations and transposes,
+    /// where each row is a column of the input, bands of `ORIENTED_BAND` of them at a time.
+    pub fn encode_oriented(
+        &mut self,
+        input_pixels: &[RGBA],
+        output_bytes: &mut Vec<u8>,
+        metadata: QOIHeader,
+        orientation: Orientation,
+    ) -> Result<(), (usize, usize)> {
+        assert_eq!(input_pixels.len(), metadata.image_size());
+        let size = (metadata.width as usize, metadata.height as usize);
+        let (width, height) = orientation.oriented_size(metadata.width, metadata.height);
+        let oriented_metadata = QOIHeader {
+            width,
+            height,
+            ..metadata
+        };
+        let band = match orientation.swaps_dimensions() {
+            true => ORIENTED_BAND,
+            false => 1,
+        };
 
-        if pixel == encode_context.get_previous_pixel() {
-            let (max_runs, last_run) = encode_context.find_run_length_at_current_position();
-            encode_context.write_run(max_runs, last_run);
+        self.encode_stretches(
+            output_bytes,
+            oriented_metadata,
+            PixelLayout::Rgba,
+            |ctx, scratch| match orientation {
+                Orientation::Identity => encode_pixels(ctx, input_pixels),
+                Orientation::FlipVertical => {
+                    for row in input_pixels.chunks_exact(size.0.max(1)).rev() {
+                        encode_pixels(ctx, row);
+                    }
+                }
+                _ => {
+                    for start in (0..height as usize).step_by(band) {
+                        scratch.clear();
+                        let rows = start..(start + band).min(height as usize);
+                        orientation.gather(input_pixels, size, rows, scratch);
+                        encode_pixels(ctx, scratch);
+                    }
+                }
+            },
+        )
+    }
 
+    /// Same as `encode`, but for pixels with fewer channels than RGBA, such as masks, scans and heightmaps.
+    /// They're widened to RGBA `CONVERTED_CHUNK` at a time into scratch space and encoded from there,
+    /// so there's no copy of the whole image. `input` holds `format.bytes_per_pixel()` bytes per pixel.
+    pub fn encode_narrow(
+        &mut self,
+        input: &[u8],
+        output_bytes: &mut Vec<u8>,
+        metadata: QOIHeader,
+        format: NarrowFormat,
+    ) -> Result<(), (usize, usize)> {
+        let bytes_per_pixel = format.bytes_per_pixel();
+        assert_eq!(input.len(), metadata.image_size() * bytes_per_pixel);
+
+        self.encode_stretches(output_bytes, metadata, PixelLayout::Rgba, |ctx, scratch| {
+            for chunk in input.chunks(CONVERTED_CHUNK * bytes_per_pixel) {
+                scratch.clear();
+                format.widen(chunk, scratch);
+                encode_pixels(ctx, scratch);
+            }
+        })
+    }
+
+    /// Same as `encode`, but for a YUV frame from a camera or video capture, `format.frame_size()` bytes long.
+    /// Each row is converted to RGB with SSE2 in scratch space just before it's encoded, so there's no RGB copy of the frame.
+    /// `metadata` would usually have no alpha, since every pixel comes out opaque.
+    pub fn encode_yuv(
+        &mut self,
+        frame: &[u8],
+        output_bytes: &mut Vec<u8>,
+        metadata: QOIHeader,
+        format: YuvFormat,
+        options: YuvOptions,
+    ) -> Result<(), (usize, usize)> {
+        let size = (metadata.width as usize, metadata.height as usize);
+        assert_eq!(frame.len(), format.frame_size(size.0, size.1));
+        let coefficients = options.coefficients();
+
+        self.encode_stretches(output_bytes, metadata, PixelLayout::Rgba, |ctx, row| {
+            row.clear();
+            row.resize(size.0, 0);
+            for y in 0..size.1 {
+                convert_row(&format.row_samples(frame, size, y), &coefficients, row);
+                encode_pixels(ctx, row);
+            }
+        })
+    }
+
+    /// Same as `encode_with_layout`, but for rows `stride` bytes apart in a larger buffer, such as a region of
+    /// a framebuffer, in which case `buffer` starts at the region's top left pixel, `y * stride + x * 4` bytes in.
+    /// Each row is encoded where it is, and runs carry on from one row to the next as usual.
+    /// Only rows that aren't aligned to 4 bytes are copied, one at a time, into scratch space.
+    pub fn encode_strided(
+        &mut self,
+        buffer: &[u8],
+        stride: usize,
+        output_bytes: &mut Vec<u8>,
+        metadata: QOIHeader,
+        layout: PixelLayout,
+    ) -> Result<(), (usize, usize)> {
+        let row_bytes = metadata.width as usize * core::mem::size_of::<RGBA>();
+        let height = metadata.height as usize;
+        assert!(
+            stride >= row_bytes,
+            "rows of {row_bytes} bytes can't be {stride} bytes apart"
+        );
+        assert!(height == 0 || buffer.len() >= (height - 1) * stride + row_bytes);
+
+        // rows with nothing between them are one stretch of pixels, unless they need copying anyway
+        let aligned = buffer.as_ptr().cast::<RGBA>().is_aligned();
+        let (rows, row_bytes) = match stride == row_bytes && aligned {
+            true => (height.min(1), row_bytes * height),
+            false => (height, row_bytes),
+        };
+        self.encode_stretches(output_bytes, metadata, layout, |ctx, scratch| {
+            for row in 0..rows {
+                let row = &buffer[row * stride..row * stride + row_bytes];
+                match bytemuck::try_cast_slice(row) {
+                    Ok(pixels) => encode_pixels(ctx, pixels),
+                    Err(_) => {
+                        scratch.clear();
+                        scratch.extend(row.chunks_exact(4).map(|bytes| {
+                            RGBA::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+                        }));
+                        encode_pixels(ctx, scratch);
+                    }
+                }
+            }
+        })
+    }
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Encodes the next stretch of the image, leaving any run it ends on to carry on into the next one
+#[inline(never)]
+fn encode_pixels(encode_context: &mut EncodeContext, input_pixels: &[RGBA]) {
+    encode_context.initialize_hashes(input_pixels);
+
+    let mut position = 0;
+    while position < input_pixels.len() {
+        let pixel = input_pixels[position];
+        let hash = encode_context.hashes[position];
+        let pixel_of_same_hash =
+            replace(&mut encode_context.hash_index_array[hash as usize], pixel);
+
+        if pixel == encode_context.previous_pixel {
+            let run_length = find_run_length(input_pixels, position);
+            encode_context.run_length += run_length;
+            position += run_length;
             continue;
         }
+        encode_context.write_run();
 
         if pixel == pixel_of_same_hash {
-            encode_context.write_hash_index();
-        } else if (pixel & 0xff000000) != (encode_context.get_previous_pixel() & 0xff000000) {
-            encode_context.write_rgba();
+            encode_context.write_hash_index(hash);
+            encode_context.previous_pixel = pixel;
+            position += 1;
+            continue;
+        }
+
+        // the rest of the ops hold channels, so they need the pixels in QOI's order
+        let layout = encode_context.layout;
+        let (pixel, previous_pixel) = (
+            layout.to_rgba(pixel),
+            layout.to_rgba(encode_context.previous_pixel),
+        );
+        if (pixel & 0xff000000) != (previous_pixel & 0xff000000) {
+            encode_context.write_rgba(pixel);
         } else {
             let mut delta_pixel: u32;
 
@@ -222,7 +577,7 @@ fn encode_pixels(mut encode_context: EncodeContext) -> Result<(), (usize, usize)
 
                 // input
                 pixel = in(reg) pixel,
-                last_pixel = in(reg) encode_context.get_previous_pixel(),
+                last_pixel = in(reg) previous_pixel,
                 bias = in(reg) 0x00020202,
 
                 // output ptrs
@@ -255,17 +610,13 @@ fn encode_pixels(mut encode_context: EncodeContext) -> Result<(), (usize, usize)
                 if dg_bias32 < 64 && dr_dg_bias8 < 16 && db_dg_bias8 < 16 {
                     encode_context.write_luma(dg_bias32, dr_dg_bias8, db_dg_bias8);
                 } else {
-                    encode_context.write_rgb();
+                    encode_context.write_rgb(pixel);
                 }
             }
         }
 
-        encode_context.update_pos();
-    }
-    let last_pos = encode_context.position;
-    if last_pos == encode_context.pixel_count {
-        Ok(())
-    } else {
-        Err((last_pos, encode_context.pixel_count))
+        encode_context.previous_pixel = input_pixels[position];
+        position += 1;
     }
+    encode_context.pixels_encoded += input_pixels.len();
 }
@@ -4,27 +4,12 @@ use core::arch::asm;
 use crate::common::{HASH, RGBA};
 pub(crate) use crate::Hashing;
 
-use super::HASH_RGBA_MANY;
+use super::special::{VectorizedHashing, HASH_MULTIPLIERS_RGBA};
 
+#[cfg(target_feature = "ssse3")]
 static MOD64MASK: u64 = 0x003f003f003f003fu64;
-static HASHING_NUMS_RGBA: u64 = 0x0b0705030b070503u64;
 
 impl Hashing for [RGBA; 64] {
-    fn update(&mut self, pixel_feed: &[RGBA]) {
-        match pixel_feed.len() {
-            0 => (),
-            1 => {
-                self.swap(pixel_feed.first().unwrap());
-            }
-            _ => {
-                pixel_feed
-                    .iter()
-                    .zip(hashes_rgba(pixel_feed).iter())
-                    .for_each(|(&pixel, &hash)| *unsafe { self.fetch_mut(hash) } = pixel);
-            }
-        };
-    }
-
     unsafe fn fetch(&self, hash: HASH) -> &RGBA {
         self.get_unchecked(hash as usize)
     }
@@ -32,14 +17,31 @@ impl Hashing for [RGBA; 64] {
     unsafe fn fetch_mut(&mut self, hash: HASH) -> &mut RGBA {
         self.get_unchecked_mut(hash as usize)
     }
+}
 
-    #[inline(always)]
-    fn swap(&mut self, pixel: &RGBA) -> (RGBA, HASH) {
-        let hash = hash_rgba(pixel);
-        let pixel2 = core::mem::replace(unsafe { self.fetch_mut(hash) }, *pixel);
-        (pixel2, hash)
-    }
+/// Puts each pixel in `pixel_feed` into the hash index array, hashing with the given vectorized implementation,
+/// for pixels whose channels are in the same order as the weights in `multipliers`
+pub(crate) fn update_with(
+    index: &mut [RGBA; 64],
+    pixel_feed: &[RGBA],
+    hashing: &dyn VectorizedHashing,
+    multipliers: u32,
+) {
+    match pixel_feed.len() {
+        0 => (),
+        1 => {
+            let pixel = pixel_feed[0];
+            *unsafe { index.fetch_mut(hash_in_order(pixel, multipliers)) } = pixel;
+        }
+        _ => {
+            pixel_feed
+                .iter()
+                .zip(hashes_with(pixel_feed, hashing, multipliers).iter())
+                .for_each(|(&pixel, &hash)| *unsafe { index.fetch_mut(hash) } = pixel);
+        }
+    };
 }
+
 /// Unsigned quotient and remainder without checking for zero divisor
 fn div_rem(n: usize, d: usize) -> (usize, usize) {
     let (q, r): (usize, usize);
@@ -66,24 +68,35 @@ fn div_rem(n: usize, d: usize) -> (usize, usize) {
     (q, r)
 }
 
-pub fn hashes_rgba(pixels: &[RGBA]) -> Vec<HASH> {
+pub(crate) fn hashes_with(
+    pixels: &[RGBA],
+    hashing: &dyn VectorizedHashing,
+    multipliers: u32,
+) -> Vec<HASH> {
     // this wraps the "unsafe" enclosed function to make the most efficient use of SIMD
     let count = pixels.len();
+    // the scalar backend stays scalar all the way down, so it can be compared against the others
     #[cfg(target_feature = "ssse3")]
-    if count <= 8 {
-        return unsafe { simd_hashes_lt8(pixels, count) };
+    if count <= 8 && hashing.hash_chunk_size() > 1 {
+        return unsafe { simd_hashes_lt8(pixels, count, multipliers) };
     }
     unsafe {
-        let chunk_size = HASH_RGBA_MANY.hash_chunk_size();
+        let chunk_size = hashing.hash_chunk_size();
         let (chunk_count, _) = div_rem(count, chunk_size);
         let full_chunk_space = chunk_count * chunk_size;
         let mut hashes: Vec<HASH> = Vec::with_capacity(full_chunk_space + chunk_size);
-        // println!("Trying to write {} hashes into {} bytes", count, hashes.capacity());
-        // println!("The hash buffer starts at {:?}", hashes.as_ptr());
-        HASH_RGBA_MANY.hash_chunks(pixels.as_ptr(), hashes.as_mut_ptr(), chunk_count + 1);
+        // the SIMD kernels hash at least one chunk whatever the count, reading past the end of a shorter feed
+        if chunk_count > 0 {
+            hashing.hash_chunks_with(
+                pixels.as_ptr(),
+                hashes.as_mut_ptr(),
+                chunk_count,
+                multipliers,
+            );
+        }
         hashes.set_len(full_chunk_space); // don't remove this line you doorknob
-        for pixel in &pixels[hashes.len()..] {
-            hashes.push(hash_rgba(pixel))
+        for &pixel in &pixels[hashes.len()..] {
+            hashes.push(hash_in_order(pixel, multipliers))
         }
         hashes
     }
@@ -92,8 +105,9 @@ pub fn hashes_rgba(pixels: &[RGBA]) -> Vec<HASH> {
 #[inline(always)] // because it's wrapped by the above function, a nested call isn't useful
 #[cfg(target_feature = "ssse3")]
 /// A stripped down SIMD hashing for pixel counts between 2 and 8 (inclusive)
-unsafe fn simd_hashes_lt8(bytes: &[RGBA], count: usize) -> Vec<HASH> {
+unsafe fn simd_hashes_lt8(bytes: &[RGBA], count: usize, multipliers: u32) -> Vec<HASH> {
     let mut output: Vec<u8> = Vec::with_capacity(8);
+    let multipliers = multipliers as u64 * 0x00000001_00000001;
 
     asm!(
         "movddup    {multipliers},  [{multipliers_ptr}]",
@@ -119,7 +133,7 @@ unsafe fn simd_hashes_lt8(bytes: &[RGBA], count: usize) -> Vec<HASH> {
         in_ptr      = in(reg)       bytes.as_ptr(),
         hashes_ptr  = in(reg)       output.as_ptr(),
 
-        multipliers_ptr = in(reg)   &HASHING_NUMS_RGBA,
+        multipliers_ptr = in(reg)   &multipliers,
         mask_ptr    = in(reg)       &MOD64MASK,
         multipliers = out(xmm_reg)  _,
         round_mask  = out(xmm_reg)  _,
@@ -134,6 +148,26 @@ unsafe fn simd_hashes_lt8(bytes: &[RGBA], count: usize) -> Vec<HASH> {
     output
 }
 
+/// QOI's hash of a pixel whose channels are in the same order as the weights in `multipliers`,
+/// taking the faster way when that's RGBA
+#[inline(always)]
+pub(crate) fn hash_in_order(pixel: RGBA, multipliers: u32) -> HASH {
+    match multipliers {
+        HASH_MULTIPLIERS_RGBA => hash_rgba(&pixel),
+        _ => hash_with(pixel, multipliers),
+    }
+}
+
+/// QOI's hash of a pixel whose channels are in some other order, with `multipliers` in the same order
+pub(crate) fn hash_with(pixel: RGBA, multipliers: u32) -> HASH {
+    let weighted = pixel
+        .to_ne_bytes()
+        .into_iter()
+        .zip(multipliers.to_ne_bytes())
+        .map(|(channel, multiplier)| channel as u32 * multiplier as u32);
+    (weighted.sum::<u32>() % 64) as HASH
+}
+
 /// A variation on zakarumych's hashing function from rapid-qoi, but with one less & instruction
 pub fn hash_rgba(pixel: &RGBA) -> HASH {
     let pixel = *pixel as u64;
@@ -1,9 +1,13 @@
 extern crate lazy_static;
 extern crate raw_cpuid;
 
+pub use special::Backend;
 pub(crate) use special::HASH_RGBA_MANY;
 
+pub(crate) mod crc32c;
 pub(crate) mod decode;
 pub(crate) mod encode;
 pub(crate) mod hashing;
 pub(crate) mod special;
+pub(crate) mod swizzle;
+pub(crate) mod yuv;
@@ -1,15 +1,16 @@
-use alloc::boxed::Box;
+use alloc::vec::Vec;
 
 use lazy_static::lazy_static;
 
 use crate::common::RGBA;
 
-use super::hashing::hash_rgba;
+use super::hashing::hash_in_order;
 
 #[cfg(target_feature = "ssse3")]
 mod v2;
 #[cfg(target_feature = "avx")]
 mod v3;
+// the VNNI implementations aren't finished, so no `Backend` runs them yet
 #[cfg(target_feature = "avxvnni")]
 mod v3n;
 #[cfg(all(target_feature = "avx512bw", not(feature = "experimental")))]
@@ -18,88 +19,114 @@ mod v4;
 mod v4n;
 
 lazy_static! {
-    pub(crate) static ref HASH_RGBA_MANY: Box<dyn VectorizedHashing> = get_hashing_function();
+    pub(crate) static ref DETECTED_BACKEND: Backend = detect_backend();
+    pub(crate) static ref HASH_RGBA_MANY: &'static dyn VectorizedHashing =
+        DETECTED_BACKEND.hashing();
 }
 
+/// The weights of QOI's hash, `r * 3 + g * 5 + b * 7 + a * 11`, one per byte of an RGBA pixel
+pub(crate) const HASH_MULTIPLIERS_RGBA: u32 = 0x0b070503;
+
 pub(crate) trait VectorizedHashing: Sync + Send {
-    unsafe fn hash_chunks(
+    /// Hashes with the weights in `multipliers` rearranged to match the channel order of the pixels,
+    /// which gives the same hashes as rearranging the pixels into RGBA first
+    unsafe fn hash_chunks_with(
         &self,
         pixel_ptr: *const RGBA,
         hash_ptr: *mut u8,
         count: usize,
+        multipliers: u32,
     ) -> (*const u32, *mut u8);
     fn hash_chunk_size(&self) -> usize;
 }
 
-#[cfg(target_feature = "avxvnni")]
-mod avxvnnistub {
-    pub(crate) struct ExtendedFeaturesLeaf1;
+/// The implementations of the vectorized hashing that the encoder and decoder lean on.
+/// Which ones exist depends on the target features the crate was compiled with,
+/// and which ones can run depends on the CPU.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Backend {
+    Scalar,
+    Ssse3,
+    Avx2,
+    Avx512,
+}
 
-    impl ExtendedFeaturesLeaf1 {
-        pub(crate) fn has_avxvnni(&self) -> bool {
-            unimplemented!()
+impl Backend {
+    pub const ALL: [Backend; 4] = [
+        Backend::Scalar,
+        Backend::Ssse3,
+        Backend::Avx2,
+        Backend::Avx512,
+    ];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Backend::Scalar => "scalar",
+            Backend::Ssse3 => "ssse3",
+            Backend::Avx2 => "avx2",
+            Backend::Avx512 => "avx512",
         }
     }
 
-    pub(crate) fn avx_vnni_stub() -> Option<ExtendedFeaturesLeaf1> {
-        unimplemented!()
+    /// The backend `encode` and `decode` use, the fastest one available
+    pub fn detected() -> Backend {
+        *DETECTED_BACKEND
     }
-}
 
-#[cfg(not(target_feature = "ssse3"))]
-#[inline(always)]
-pub(crate) fn get_hashing_function() -> Box<dyn VectorizedHashing> {
-    Box::new(V1)
-}
-
-#[cfg(target_feature = "ssse3")]
-pub(crate) fn get_hashing_function() -> Box<dyn VectorizedHashing> {
-    use raw_cpuid::CpuId;
-    let cpuid = CpuId::new();
-
-    #[cfg(target_feature = "avx")]
-    if let Some(extended_features) = cpuid.get_extended_feature_info() {
-        #[cfg(target_feature = "avx512vnni")]
-        if extended_features.has_avx512vnni() {
-            // First choice
-            use v4n::AVX512VNNI;
-            return Box::new(AVX512VNNI);
-        }
-
-        #[cfg(target_feature = "avx512bw")]
-        if extended_features.has_avx512bw() {
-            // Second choice
-            use v4::AVX512;
-            return Box::new(AVX512);
-        }
+    /// Every backend that was compiled in and that this CPU supports, slowest first
+    pub fn available() -> Vec<Backend> {
+        Backend::ALL
+            .into_iter()
+            .filter(|backend| backend.is_available())
+            .collect()
+    }
 
-        #[cfg(target_feature = "avxvnni")]
-        if let Some(ext_feats2) = avxvnnistub::avx_vnni_stub() {
-            if ext_feats2.has_avxvnni() {
-                // Third choice
-                use v3n::AVXVNNI;
-                return Box::new(AVXVNNI);
-            }
-        }
-        #[cfg(target_feature = "avx")]
-        if extended_features.has_avx2() {
-            // Fourth choice
-            use v3::AVX;
-            return Box::new(AVX);
+    pub fn is_available(self) -> bool {
+        match self {
+            Backend::Scalar => true,
+            #[cfg(target_feature = "ssse3")]
+            Backend::Ssse3 => raw_cpuid::CpuId::new()
+                .get_feature_info()
+                .is_some_and(|features| features.has_ssse3()),
+            #[cfg(target_feature = "avx")]
+            Backend::Avx2 => raw_cpuid::CpuId::new()
+                .get_extended_feature_info()
+                .is_some_and(|features| features.has_avx2()),
+            #[cfg(all(target_feature = "avx512bw", not(feature = "experimental")))]
+            Backend::Avx512 => raw_cpuid::CpuId::new()
+                .get_extended_feature_info()
+                .is_some_and(|features| features.has_avx512bw()),
+            #[allow(unreachable_patterns)]
+            _ => false,
         }
     }
-    // if the extended features struct can't be obtained or none of the methods from above returned,
-    // try to see if the processor at least supports ssse3
-    #[cfg(target_feature = "ssse3")]
-    if let Some(features) = cpuid.get_feature_info() {
-        if features.has_ssse3() {
-            // Fifth choice
-            use v2::SSSE3;
-            return Box::new(SSSE3);
+
+    /// Panics if the backend isn't available
+    pub(crate) fn hashing(self) -> &'static dyn VectorizedHashing {
+        assert!(
+            self.is_available(),
+            "the {} backend isn't available here",
+            self.name()
+        );
+        match self {
+            #[cfg(target_feature = "ssse3")]
+            Backend::Ssse3 => &v2::SSSE3,
+            #[cfg(target_feature = "avx")]
+            Backend::Avx2 => &v3::AVX,
+            #[cfg(all(target_feature = "avx512bw", not(feature = "experimental")))]
+            Backend::Avx512 => &v4::AVX512,
+            _ => &V1,
         }
     }
-    // If nothing else returns early, return the least optimized function
-    return Box::new(V1);
+}
+
+/// Picks the fastest available backend, in the same order of preference as `Backend::ALL` reversed
+fn detect_backend() -> Backend {
+    Backend::ALL
+        .into_iter()
+        .rev()
+        .find(|backend| backend.is_available())
+        .unwrap_or(Backend::Scalar)
 }
 
 // pub(crate) fn ssse3_hash_rgba(rgba_bytes: &Vec<u32>, pixel_count: usize) -> Vec<u8> {
@@ -128,14 +155,15 @@ pub(crate) fn get_hashing_function() -> Box<dyn VectorizedHashing> {
 pub struct V1;
 
 impl VectorizedHashing for V1 {
-    unsafe fn hash_chunks(
+    unsafe fn hash_chunks_with(
         &self,
         mut pixel_ptr: *const u32,
         mut hash_ptr: *mut u8,
         count: usize,
+        multipliers: u32,
     ) -> (*const u32, *mut u8) {
         for _ in 0..count {
-            *hash_ptr = hash_rgba(pixel_ptr.as_ref().unwrap());
+            *hash_ptr = hash_in_order(*pixel_ptr, multipliers);
             pixel_ptr = pixel_ptr.add(1);
             hash_ptr = hash_ptr.add(1);
         }
@@ -5,13 +5,14 @@ use super::VectorizedHashing;
 pub(crate) struct SSSE3;
 
 impl VectorizedHashing for SSSE3 {
-    unsafe fn hash_chunks(
+    unsafe fn hash_chunks_with(
         &self,
         pixel_ptr: *const u32,
         hash_ptr: *mut u8,
         count: usize,
+        multipliers: u32,
     ) -> (*const u32, *mut u8) {
-        hash_chunks_of_16(pixel_ptr, hash_ptr, count)
+        hash_chunks_of_16(pixel_ptr, hash_ptr, count, multipliers)
     }
 
     fn hash_chunk_size(&self) -> usize {
@@ -23,6 +24,7 @@ unsafe fn hash_chunks_of_16(
     mut pixel_read_ptr: *const u32,
     mut hash_write_ptr: *mut u8,
     chunk_count: usize,
+    multipliers: u32,
 ) -> (*const u32, *mut u8) {
     asm!(
     "movddup    {multipliers},  {multipliers}",
@@ -54,7 +56,7 @@ unsafe fn hash_chunks_of_16(
     "cmp        {pixels_ptr},   {end_address}",
     "jne 2b",  // Loop until the stop address is reached (there are no more full pixel chunks)
 
-    multipliers = in(xmm_reg)   HASH_MULTIPLIERS_RGBA,
+    multipliers = in(xmm_reg)   multipliers as u64 * 0x00000001_00000001,
     round_mask  = in(xmm_reg)   MOD_64_MASK,
 
     pixels_ptr  = inout(reg)    pixel_read_ptr,
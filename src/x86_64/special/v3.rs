@@ -2,21 +2,20 @@ use core::arch::asm;
 
 use super::VectorizedHashing;
 
-const HASH_MULTIPLIER_RGBA: u32 = 0x0b070503;
-
 const MASK_64: u32 = 0x003f; // should be u16 but broadcast only takes r or e regs
 const REORDERING_INDICES: [u8; 8] = [0, 4, 1, 5, 2, 6, 3, 7];
 
 pub(crate) struct AVX;
 
 impl VectorizedHashing for AVX {
-    unsafe fn hash_chunks(
+    unsafe fn hash_chunks_with(
         &self,
         pixel_ptr: *const u32,
         hash_ptr: *mut u8,
         count: usize,
+        multipliers: u32,
     ) -> (*const u32, *mut u8) {
-        hash_chunk_of_32_avx(pixel_ptr, hash_ptr, count)
+        hash_chunk_of_32_avx(pixel_ptr, hash_ptr, count, multipliers)
     }
 
     fn hash_chunk_size(&self) -> usize {
@@ -29,6 +28,7 @@ pub unsafe fn hash_chunk_of_32_avx(
     mut pixel_read_ptr: *const u32,
     mut hash_write_ptr: *mut u8,
     count: usize,
+    multipliers: u32,
 ) -> (*const u32, *mut u8) {
     asm!(
     "vpbroadcastd   {multipliers},  [{multiplier}]",
@@ -58,7 +58,7 @@ pub unsafe fn hash_chunk_of_32_avx(
     "cmp        {pixels_ptr},   {pixels_end_ptr}",
     "jne 2b",
 
-    multiplier  = in(reg)       &HASH_MULTIPLIER_RGBA,
+    multiplier  = in(reg)       &multipliers,
     byte_mask   = in(reg)       &MASK_64,
     reorder_ptr = in(reg)       &REORDERING_INDICES,
 
@@ -1,6 +1,6 @@
 use core::arch::asm;
 
-use super::{VectorizedHashing, HASH_MULTIPLIER_RGBA};
+use super::VectorizedHashing;
 
 const MOVQB_REPLACEMENT: [u8; 16] = [
     0, 4, 8, 12, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128,
@@ -10,13 +10,14 @@ const CHUNK_REORDER: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
 pub(crate) struct AVXVNNI;
 
 impl VectorizedHashing for AVXVNNI {
-    unsafe fn hash_chunks(
+    unsafe fn hash_chunks_with(
         &self,
         pixel_ptr: *const u32,
         hash_ptr: *mut u8,
         count: usize,
+        multipliers: u32,
     ) -> (*const u32, *mut u8) {
-        hash_chunk_of_32_avx_vnni(pixel_ptr, hash_ptr, count)
+        hash_chunk_of_32_avx_vnni(pixel_ptr, hash_ptr, count, multipliers)
     }
 
     fn hash_chunk_size(&self) -> usize {
@@ -29,6 +30,7 @@ pub unsafe fn hash_chunk_of_32_avx_vnni(
     mut pixel_read_ptr: *const u32,
     mut hash_write_ptr: *mut u8,
     count: usize,
+    multipliers: u32,
 ) -> (*const u32, *mut u8) {
     asm!(
     "vbroadcasti128 {gather},       [{movqb_ptr}]",
@@ -63,7 +65,7 @@ pub unsafe fn hash_chunk_of_32_avx_vnni(
     //"# LLVM-MCA-END 32nn",
     "jne 2b",
 
-    multiplier  = in(reg)   &HASH_MULTIPLIER_RGBA,
+    multiplier  = in(reg)   &multipliers,
     byte_mask   = in(reg)   &0x3f,
     movqb_ptr   = in(reg)   &MOVQB_REPLACEMENT,
     reorder_ptr = in(reg)   &CHUNK_REORDER,
@@ -4,7 +4,6 @@ use super::VectorizedHashing;
 
 // TODO: Find a way to actually make this faster than AVX
 const RGBA_ARGB_SHUFFLE: [u8; 16] = [3, 0, 1, 2, 7, 4, 5, 6, 11, 8, 9, 10, 15, 12, 13, 14];
-const HASH_MULTIPLIER_RGBA: u32 = 0x0b070503u32;
 // const HASH_MULTIPLIER_ARGB: u32 = 0x07050b03u32;
 const HASH_MULTIPLIER_ARGB: u32 = 0x0705030bu32;
 
@@ -23,13 +22,14 @@ const BIG_GATHER_WORDS: [u16; 32] = [
 pub(crate) struct AVX512;
 
 impl VectorizedHashing for AVX512 {
-    unsafe fn hash_chunks(
+    unsafe fn hash_chunks_with(
         &self,
         pixel_ptr: *const u32,
         hash_ptr: *mut u8,
         count: usize,
+        multipliers: u32,
     ) -> (*const u32, *mut u8) {
-        hash_chunk_of_16_avx_512(pixel_ptr, hash_ptr, count)
+        hash_chunk_of_16_avx_512(pixel_ptr, hash_ptr, count, multipliers)
     }
 
     fn hash_chunk_size(&self) -> usize {
@@ -42,6 +42,7 @@ pub unsafe fn hash_chunk_of_16_avx_512(
     mut pixel_read_ptr: *const u32,
     mut hash_write_ptr: *mut u8,
     count: usize,
+    multipliers: u32,
 ) -> (*const u32, *mut u8) {
     asm!(
     // uses feature BW
@@ -69,7 +70,7 @@ pub unsafe fn hash_chunk_of_16_avx_512(
     "# LLVM-MCA-END 16-512",
     "jne 2b",
 
-    multiplier  = in(reg)       HASH_MULTIPLIER_RGBA,
+    multiplier  = in(reg)       multipliers,
     byte_mask   = in(reg)       0x3f,
 
     pixels_ptr  = inout(reg)    pixel_read_ptr,
@@ -2,18 +2,17 @@ use core::arch::asm;
 
 use super::VectorizedHashing;
 
-const HASH_MULTIPLIER_RGBA: u32 = 0x0b070503u32;
-
 pub(crate) struct AVX512VNNI;
 
 impl VectorizedHashing for AVX512VNNI {
-    unsafe fn hash_chunks(
+    unsafe fn hash_chunks_with(
         &self,
         pixel_ptr: *const u32,
         hash_ptr: *mut u8,
         count: usize,
+        multipliers: u32,
     ) -> (*const u32, *mut u8) {
-        hash_chunk_of_160_avx_512_vnni(pixel_ptr, hash_ptr, count)
+        hash_chunk_of_160_avx_512_vnni(pixel_ptr, hash_ptr, count, multipliers)
     }
 
     fn hash_chunk_size(&self) -> usize {
@@ -27,6 +26,7 @@ pub unsafe fn hash_chunk_of_160_avx_512_vnni(
     mut pixel_read_ptr: *const u32,
     mut hash_write_ptr: *mut u8,
     count: usize,
+    multipliers: u32,
 ) -> (*const u32, *mut u8) {
     todo!();
     asm!(
@@ -79,7 +79,7 @@ pub unsafe fn hash_chunk_of_160_avx_512_vnni(
     //"# LLVM-MCA-END 160-512-VNNI",
     "jne 2b",
 
-    multiplier  = in(reg)       HASH_MULTIPLIER_RGBA,
+    multiplier  = in(reg)       multipliers,
     byte_mask   = in(reg)       0x3f,
 
     pixels_ptr  = inout(reg)    pixel_read_ptr,
@@ -0,0 +1,54 @@
+#[cfg(target_feature = "ssse3")]
+use core::arch::asm;
+
+use crate::common::RGBA;
+
+/// Rearranges the bytes of every pixel in place, byte `i` of each pixel becoming its byte `order[i]`
+pub(crate) fn swizzle(pixels: &mut [RGBA], order: [u8; 4]) {
+    #[cfg(target_feature = "ssse3")]
+    let pixels = {
+        let (chunks, rest) = pixels.split_at_mut(pixels.len() / 4 * 4);
+        if !chunks.is_empty() {
+            unsafe { swizzle_chunks(chunks, shuffle_mask(order)) };
+        }
+        rest
+    };
+    for pixel in pixels {
+        let bytes = pixel.to_ne_bytes();
+        *pixel = RGBA::from_ne_bytes(order.map(|index| bytes[index as usize]));
+    }
+}
+
+/// `order` repeated for each of the four pixels in an xmm register
+#[cfg(target_feature = "ssse3")]
+const fn shuffle_mask(order: [u8; 4]) -> [u8; 16] {
+    let mut mask = [0; 16];
+    let mut i = 0;
+    while i < 16 {
+        mask[i] = (i & !3) as u8 + order[i & 3];
+        i += 1;
+    }
+    mask
+}
+
+/// Shuffles four pixels at a time, `pixels` holding a non-zero multiple of four of them
+#[cfg(target_feature = "ssse3")]
+unsafe fn swizzle_chunks(pixels: &mut [RGBA], mask: [u8; 16]) {
+    asm!(
+    "movdqu     {mask},     [{mask_ptr}]",
+    "2:",
+    "movdqu     {chunk},    [{pixels_ptr}]",
+    "pshufb     {chunk},    {mask}",
+    "movdqu     [{pixels_ptr}], {chunk}",
+    "add        {pixels_ptr}, 16",
+    "sub        {chunks},   1",
+    "jnz        2b",
+
+    mask_ptr    = in(reg)       mask.as_ptr(),
+    pixels_ptr  = inout(reg)    pixels.as_mut_ptr() => _,
+    chunks      = inout(reg)    pixels.len() / 4 => _,
+    mask        = out(xmm_reg)  _,
+    chunk       = out(xmm_reg)  _,
+    options(nostack)
+    )
+}
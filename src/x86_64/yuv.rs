@@ -0,0 +1,236 @@
+use core::arch::asm;
+
+use crate::common::RGBA;
+use crate::yuv::{Coefficients, RowSamples, RGB_FRACTION_BITS, RGB_HALF};
+
+/// Converts a row of samples to RGBA in `row`, eight pixels at a time in SSE2, which every x86_64 CPU has.
+/// The pixels left over after the last eight are converted one by one, to exactly the same values.
+pub(crate) fn convert_row(samples: &RowSamples, coefficients: &Coefficients, row: &mut [RGBA]) {
+    let (chunks, rest) = row.split_at_mut(row.len() / 8 * 8);
+    unsafe { convert_chunks(samples, coefficients, chunks) };
+    let start = chunks.len();
+    for (x, pixel) in (start..).zip(rest) {
+        let (y, u, v) = samples.get(x);
+        *pixel = coefficients.to_rgba(y, u, v);
+    }
+}
+
+/// Everything `convert_chunks` weighs and offsets by, aligned to be SSE2 memory operands
+#[repr(C, align(16))]
+struct Constants {
+    /// Y and V weighed in one pair of 16-bit lanes, for R, G and B at offsets 0, 16 and 32
+    luma_v_weights: [[i16; 8]; 3],
+    /// U and the rounding weighed in the other, for R, G and B at offsets 48, 64 and 80
+    u_one_weights: [[i16; 8]; 3],
+    /// at offset 96
+    luma_offset: [i16; 8],
+    /// at offset 112
+    chroma_offset: [i16; 8],
+    /// at offset 128
+    ones: [i16; 8],
+    /// at offset 144, the low byte of every 16-bit lane
+    low_bytes: [i16; 8],
+}
+
+/// Two 16-bit weights repeated in every pair of lanes, for `pmaddwd` to multiply each pair by
+fn weight_pair(first: i16, second: i16) -> [i16; 8] {
+    [first, second, first, second, first, second, first, second]
+}
+
+/// Splits U V U V ... in 16-bit lanes of `{t}` into U U ... in `{u}` and V V ... in `{v}`,
+/// each repeated for the two pixels it covers
+macro_rules! split_chroma {
+    () => {
+        concat!(
+            // 0xa0 picks lanes 0 0 2 2 and 0xf5 picks lanes 1 1 3 3 of each half
+            "pshuflw    {u},    {t},    0xa0\n",
+            "pshufhw    {u},    {u},    0xa0\n",
+            "pshuflw    {v},    {t},    0xf5\n",
+            "pshufhw    {v},    {v},    0xf5\n",
+        )
+    };
+}
+
+/// Converts eight pixels of Y, U and V in the 16-bit lanes of `{y}`, `{u}` and `{v}` to RGBA at `{out}`
+macro_rules! convert_and_store {
+    () => {
+        concat!(
+            "psubw      {y},        [{c} + 96]\n",
+            "psubw      {u},        [{c} + 112]\n",
+            "psubw      {v},        [{c} + 112]\n",
+            // Y and V interleaved, then U and ones, each in a low and a high half
+            "movdqa     {yv_high},  {y}\n",
+            "punpcklwd  {y},        {v}\n",
+            "punpckhwd  {yv_high},  {v}\n",
+            "movdqa     {u_high},   {u}\n",
+            "punpcklwd  {u},        [{c} + 128]\n",
+            "punpckhwd  {u_high},   [{c} + 128]\n",
+            convert_channel!("r", 0, 48),
+            convert_channel!("g", 16, 64),
+            convert_channel!("b", 32, 80),
+            "punpcklbw  {r},        {g}\n",
+            "pcmpeqb    {t},        {t}\n",
+            "punpcklbw  {b},        {t}\n",
+            "movdqa     {t},        {r}\n",
+            "punpcklwd  {r},        {b}\n",
+            "punpckhwd  {t},        {b}\n",
+            "movdqu     [{out}],        {r}\n",
+            "movdqu     [{out} + 16],   {t}\n",
+        )
+    };
+}
+
+/// One channel's eight bytes in the low half of its register.
+/// Both packs saturate, which clamps to 0 to 255 the same as the scalar conversion.
+macro_rules! convert_channel {
+    ($channel:literal, $luma_v:literal, $u_one:literal) => {
+        concat!(
+            "movdqa     {",
+            $channel,
+            "},  {y}\n",
+            "pmaddwd    {",
+            $channel,
+            "},  [{c} + ",
+            $luma_v,
+            "]\n",
+            "movdqa     {t},    {u}\n",
+            "pmaddwd    {t},    [{c} + ",
+            $u_one,
+            "]\n",
+            "paddd      {",
+            $channel,
+            "},  {t}\n",
+            "psrad      {",
+            $channel,
+            "},  {bits}\n",
+            "movdqa     {high}, {yv_high}\n",
+            "pmaddwd    {high}, [{c} + ",
+            $luma_v,
+            "]\n",
+            "movdqa     {t},    {u_high}\n",
+            "pmaddwd    {t},    [{c} + ",
+            $u_one,
+            "]\n",
+            "paddd      {high}, {t}\n",
+            "psrad      {high}, {bits}\n",
+            "packssdw   {",
+            $channel,
+            "},  {high}\n",
+            "packuswb   {",
+            $channel,
+            "},  {",
+            $channel,
+            "}\n",
+        )
+    };
+}
+
+/// `pixels` holds a multiple of eight of them
+unsafe fn convert_chunks(samples: &RowSamples, coefficients: &Coefficients, pixels: &mut [RGBA]) {
+    let [luma, r_v, g_v, g_u, b_u] = coefficients.to_rgb;
+    let constants = Constants {
+        luma_v_weights: [
+            weight_pair(luma, r_v),
+            weight_pair(luma, g_v),
+            weight_pair(luma, 0),
+        ],
+        u_one_weights: [
+            weight_pair(0, RGB_HALF),
+            weight_pair(g_u, RGB_HALF),
+            weight_pair(b_u, RGB_HALF),
+        ],
+        luma_offset: [coefficients.luma_offset; 8],
+        chroma_offset: [128; 8],
+        ones: [1; 8],
+        low_bytes: [0xff; 8],
+    };
+    let c = &constants as *const Constants;
+
+    for (chunk, x) in pixels.chunks_exact_mut(8).zip((0..).step_by(8)) {
+        let out = chunk.as_mut_ptr();
+        match *samples {
+            RowSamples::Nv12 { luma, uv } => asm!(
+            "pxor       {zero},     {zero}",
+            "movq       {y},        qword ptr [{luma_ptr}]",
+            "punpcklbw  {y},        {zero}",
+            "movq       {t},        qword ptr [{uv_ptr}]",
+            "punpcklbw  {t},        {zero}",
+            split_chroma!(),
+            convert_and_store!(),
+
+            luma_ptr    = in(reg)       luma[x..x + 8].as_ptr(),
+            uv_ptr      = in(reg)       uv[x..x + 8].as_ptr(),
+            c           = in(reg)       c,
+            out         = in(reg)       out,
+            bits        = const         RGB_FRACTION_BITS,
+            zero        = out(xmm_reg)  _,
+            y           = out(xmm_reg)  _,
+            u           = out(xmm_reg)  _,
+            v           = out(xmm_reg)  _,
+            yv_high     = out(xmm_reg)  _,
+            u_high      = out(xmm_reg)  _,
+            r           = out(xmm_reg)  _,
+            g           = out(xmm_reg)  _,
+            b           = out(xmm_reg)  _,
+            high        = out(xmm_reg)  _,
+            t           = out(xmm_reg)  _,
+            options(preserves_flags, nostack)
+            ),
+            RowSamples::I420 { luma, u, v } => asm!(
+            "pxor       {zero},     {zero}",
+            "movq       {y},        qword ptr [{luma_ptr}]",
+            "punpcklbw  {y},        {zero}",
+            "movd       {u},        dword ptr [{u_ptr}]",
+            "punpcklbw  {u},        {u}",
+            "punpcklbw  {u},        {zero}",
+            "movd       {v},        dword ptr [{v_ptr}]",
+            "punpcklbw  {v},        {v}",
+            "punpcklbw  {v},        {zero}",
+            convert_and_store!(),
+
+            luma_ptr    = in(reg)       luma[x..x + 8].as_ptr(),
+            u_ptr       = in(reg)       u[x / 2..x / 2 + 4].as_ptr(),
+            v_ptr       = in(reg)       v[x / 2..x / 2 + 4].as_ptr(),
+            c           = in(reg)       c,
+            out         = in(reg)       out,
+            bits        = const         RGB_FRACTION_BITS,
+            zero        = out(xmm_reg)  _,
+            y           = out(xmm_reg)  _,
+            u           = out(xmm_reg)  _,
+            v           = out(xmm_reg)  _,
+            yv_high     = out(xmm_reg)  _,
+            u_high      = out(xmm_reg)  _,
+            r           = out(xmm_reg)  _,
+            g           = out(xmm_reg)  _,
+            b           = out(xmm_reg)  _,
+            high        = out(xmm_reg)  _,
+            t           = out(xmm_reg)  _,
+            options(preserves_flags, nostack)
+            ),
+            RowSamples::Yuyv { pairs } => asm!(
+            "movdqu     {t},        [{pairs_ptr}]",
+            "movdqa     {y},        {t}",
+            "pand       {y},        [{c} + 144]",
+            "psrlw      {t},        8",
+            split_chroma!(),
+            convert_and_store!(),
+
+            pairs_ptr   = in(reg)       pairs[x * 2..x * 2 + 16].as_ptr(),
+            c           = in(reg)       c,
+            out         = in(reg)       out,
+            bits        = const         RGB_FRACTION_BITS,
+            y           = out(xmm_reg)  _,
+            u           = out(xmm_reg)  _,
+            v           = out(xmm_reg)  _,
+            yv_high     = out(xmm_reg)  _,
+            u_high      = out(xmm_reg)  _,
+            r           = out(xmm_reg)  _,
+            g           = out(xmm_reg)  _,
+            b           = out(xmm_reg)  _,
+            high        = out(xmm_reg)  _,
+            t           = out(xmm_reg)  _,
+            options(preserves_flags, nostack)
+            ),
+        }
+    }
+}
@@ -0,0 +1,317 @@
+//! YUV frames as cameras and video capture deliver them, converted to and from RGB as they're encoded and decoded.
+//! Frames are tightly packed, the way `ffmpeg -f rawvideo` writes them, with chroma sides rounded up for odd sizes.
+
+use crate::common::RGBA;
+use crate::layout::{RowStore, Rows};
+
+/// How the planes or samples of a frame are laid out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum YuvFormat {
+    /// A plane of Y, then a plane of U and V interleaved at half the width and height
+    Nv12,
+    /// Planes of Y, U and V, the last two at half the width and height
+    I420,
+    /// Y0 U Y1 V for each pair of pixels, chroma at half the width. An odd width's last Y1 is padding.
+    Yuyv,
+}
+
+impl YuvFormat {
+    /// The size of the chroma planes, or of the chroma samples in each row for `Yuyv`
+    const fn chroma_size(self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            YuvFormat::Nv12 | YuvFormat::I420 => (width.div_ceil(2), height.div_ceil(2)),
+            YuvFormat::Yuyv => (width.div_ceil(2), height),
+        }
+    }
+
+    /// The size of a whole frame in bytes
+    pub const fn frame_size(self, width: usize, height: usize) -> usize {
+        let (chroma_width, chroma_height) = self.chroma_size(width, height);
+        match self {
+            YuvFormat::Nv12 | YuvFormat::I420 => width * height + chroma_width * chroma_height * 2,
+            YuvFormat::Yuyv => chroma_width * 4 * height,
+        }
+    }
+
+    /// Where the first Y byte of row `y` is in the frame, and how far apart the row's Y bytes are
+    fn luma_row(self, y: usize, (width, height): (usize, usize)) -> (usize, usize) {
+        let (chroma_width, _) = self.chroma_size(width, height);
+        match self {
+            YuvFormat::Nv12 | YuvFormat::I420 => (y * width, 1),
+            YuvFormat::Yuyv => (y * chroma_width * 4, 2),
+        }
+    }
+
+    /// Where the first U and V bytes for row `y` are in the frame, and how far apart each are along the row
+    fn chroma_row(self, y: usize, (width, height): (usize, usize)) -> (usize, usize, usize) {
+        let (chroma_width, chroma_height) = self.chroma_size(width, height);
+        match self {
+            YuvFormat::Nv12 => {
+                let uv = width * height + (y / 2) * chroma_width * 2;
+                (uv, uv + 1, 2)
+            }
+            YuvFormat::I420 => {
+                let u = width * height + (y / 2) * chroma_width;
+                (u, u + chroma_width * chroma_height, 1)
+            }
+            YuvFormat::Yuyv => {
+                let pairs = y * chroma_width * 4;
+                (pairs + 1, pairs + 3, 4)
+            }
+        }
+    }
+
+    /// The samples of row `y` of `frame`
+    pub(crate) fn row_samples(
+        self,
+        frame: &[u8],
+        (width, height): (usize, usize),
+        y: usize,
+    ) -> RowSamples<'_> {
+        let (chroma_width, _) = self.chroma_size(width, height);
+        let (luma, _) = self.luma_row(y, (width, height));
+        let (u, v, _) = self.chroma_row(y, (width, height));
+        match self {
+            YuvFormat::Nv12 => RowSamples::Nv12 {
+                luma: &frame[luma..][..width],
+                uv: &frame[u..][..chroma_width * 2],
+            },
+            YuvFormat::I420 => RowSamples::I420 {
+                luma: &frame[luma..][..width],
+                u: &frame[u..][..chroma_width],
+                v: &frame[v..][..chroma_width],
+            },
+            YuvFormat::Yuyv => RowSamples::Yuyv {
+                pairs: &frame[luma..][..chroma_width * 4],
+            },
+        }
+    }
+}
+
+/// The samples of one row of a frame, sliced out of it
+pub(crate) enum RowSamples<'a> {
+    /// Y for each pixel, then U and V interleaved for each pair of pixels
+    Nv12 { luma: &'a [u8], uv: &'a [u8] },
+    I420 {
+        luma: &'a [u8],
+        u: &'a [u8],
+        v: &'a [u8],
+    },
+    /// Y0 U Y1 V for each pair of pixels
+    Yuyv { pairs: &'a [u8] },
+}
+
+impl RowSamples<'_> {
+    /// The Y, U and V of pixel `x`
+    #[inline(always)]
+    pub(crate) fn get(&self, x: usize) -> (u8, u8, u8) {
+        match *self {
+            RowSamples::Nv12 { luma, uv } => (luma[x], uv[x / 2 * 2], uv[x / 2 * 2 + 1]),
+            RowSamples::I420 { luma, u, v } => (luma[x], u[x / 2], v[x / 2]),
+            RowSamples::Yuyv { pairs } => {
+                let pair = &pairs[x / 2 * 4..x / 2 * 4 + 4];
+                (pair[x % 2 * 2], pair[1], pair[3])
+            }
+        }
+    }
+}
+
+/// The weights of red and blue in luma
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum YuvMatrix {
+    /// Standard definition video and most webcams
+    #[default]
+    Bt601,
+    /// High definition video
+    Bt709,
+}
+
+/// Whether Y, U and V take up all 256 values or leave video's headroom
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum YuvRange {
+    /// Y from 16 to 235 and chroma from 16 to 240, as most video is
+    #[default]
+    Limited,
+    /// All of 0 to 255, as JPEG and some webcams have it
+    Full,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct YuvOptions {
+    pub matrix: YuvMatrix,
+    pub range: YuvRange,
+}
+
+const FRACTION_BITS: u32 = 16;
+const HALF: i32 = 1 << (FRACTION_BITS - 1);
+/// Converting to RGB has fewer fraction bits, so that its weights fit in 16 bits for SIMD to multiply in pairs
+pub(crate) const RGB_FRACTION_BITS: u32 = 13;
+pub(crate) const RGB_HALF: i16 = 1 << (RGB_FRACTION_BITS - 1);
+
+fn fixed(value: f64, fraction_bits: u32) -> i32 {
+    let scaled = value * (1 << fraction_bits) as f64;
+    match scaled < 0.0 {
+        true => (scaled - 0.5) as i32,
+        false => (scaled + 0.5) as i32,
+    }
+}
+
+fn clamp_to_u8(value: i32) -> u8 {
+    (value >> FRACTION_BITS).clamp(0, 255) as u8
+}
+
+/// The conversion both ways in fixed point, worked out once per image
+pub(crate) struct Coefficients {
+    /// Rows for Y, U and V, each weighing R, G and B, with `FRACTION_BITS`
+    to_yuv: [[i32; 3]; 3],
+    /// Y's weight in R, G and B, then V's in R and G, then U's in G and B, with `RGB_FRACTION_BITS`
+    pub(crate) to_rgb: [i16; 5],
+    pub(crate) luma_offset: i16,
+}
+
+impl YuvOptions {
+    pub(crate) fn coefficients(self) -> Coefficients {
+        let (kr, kb) = match self.matrix {
+            YuvMatrix::Bt601 => (0.299, 0.114),
+            YuvMatrix::Bt709 => (0.2126, 0.0722),
+        };
+        let kg = 1.0 - kr - kb;
+        let (luma_scale, chroma_scale, luma_offset) = match self.range {
+            YuvRange::Full => (1.0, 1.0, 0),
+            YuvRange::Limited => (219.0 / 255.0, 224.0 / 255.0, 16),
+        };
+        let (u_divisor, v_divisor) = (2.0 * (1.0 - kb), 2.0 * (1.0 - kr));
+        Coefficients {
+            to_yuv: [
+                [kr, kg, kb].map(|weight| weight * luma_scale),
+                [-kr / u_divisor, -kg / u_divisor, 0.5].map(|weight| weight * chroma_scale),
+                [0.5, -kg / v_divisor, -kb / v_divisor].map(|weight| weight * chroma_scale),
+            ]
+            .map(|weights| weights.map(|weight| fixed(weight, FRACTION_BITS))),
+            to_rgb: [
+                1.0 / luma_scale,
+                v_divisor / chroma_scale,
+                -v_divisor * kr / kg / chroma_scale,
+                -u_divisor * kb / kg / chroma_scale,
+                u_divisor / chroma_scale,
+            ]
+            .map(|weight| fixed(weight, RGB_FRACTION_BITS) as i16),
+            luma_offset,
+        }
+    }
+
+    /// Converts one pixel to opaque RGBA
+    pub fn to_rgba(self, y: u8, u: u8, v: u8) -> RGBA {
+        self.coefficients().to_rgba(y, u, v)
+    }
+
+    /// Converts one pixel to Y, U and V, ignoring alpha
+    pub fn from_rgba(self, pixel: RGBA) -> [u8; 3] {
+        let [r, g, b, _] = pixel.to_ne_bytes().map(i32::from);
+        self.coefficients().to_yuv_sums([r, g, b], 1)
+    }
+}
+
+impl Coefficients {
+    /// Converts one pixel the same way the SIMD conversion does, down to the rounding and clamping
+    #[inline(always)]
+    pub(crate) fn to_rgba(&self, y: u8, u: u8, v: u8) -> RGBA {
+        let [luma, r_v, g_v, g_u, b_u] = self.to_rgb.map(i32::from);
+        let y = luma * (y as i32 - self.luma_offset as i32) + RGB_HALF as i32;
+        let (u, v) = (u as i32 - 128, v as i32 - 128);
+        let clamp_to_u8 = |value: i32| (value >> RGB_FRACTION_BITS).clamp(0, 255) as u8;
+        RGBA::from_ne_bytes([
+            clamp_to_u8(y + r_v * v),
+            clamp_to_u8(y + g_v * v + g_u * u),
+            clamp_to_u8(y + b_u * u),
+            0xff,
+        ])
+    }
+
+    /// Converts the average of `count` pixels, given the sums of their channels.
+    /// Rounds halves up, whichever side of zero the weighted sum is on before the offset.
+    fn to_yuv_sums(&self, sums: [i32; 3], count: i32) -> [u8; 3] {
+        let offsets = [self.luma_offset as i32, 128, 128];
+        core::array::from_fn(|row| {
+            let sum: i32 = self.to_yuv[row]
+                .iter()
+                .zip(sums)
+                .map(|(weight, channel)| weight * channel)
+                .sum();
+            let offset_sum = sum + (offsets[row] << FRACTION_BITS) * count;
+            (offset_sum + HALF * count)
+                .div_euclid(count << FRACTION_BITS)
+                .clamp(0, 255) as u8
+        })
+    }
+
+    fn to_luma(&self, [r, g, b]: [i32; 3]) -> u8 {
+        let [r_weight, g_weight, b_weight] = self.to_yuv[0];
+        let luma = r_weight * r + g_weight * g + b_weight * b;
+        clamp_to_u8(luma + ((self.luma_offset as i32) << FRACTION_BITS) + HALF)
+    }
+}
+
+/// Writes each pixel's Y straight into the frame, and sums the RGB of each box of pixels
+/// that share chroma until the box is complete, then writes the chroma of their average
+pub(crate) struct YuvStore<'a> {
+    pub(crate) format: YuvFormat,
+    pub(crate) coefficients: Coefficients,
+    pub(crate) frame: &'a mut [u8],
+    pub(crate) sums: alloc::vec::Vec<[i32; 3]>,
+    pub(crate) rows: Rows,
+}
+
+impl YuvStore<'_> {
+    fn finish_chroma_row(&mut self, y: usize) {
+        let rows = match self.format {
+            YuvFormat::Nv12 | YuvFormat::I420 => y % 2 + 1,
+            YuvFormat::Yuyv => 1,
+        };
+        let (u_start, v_start, step) = self
+            .format
+            .chroma_row(y, (self.rows.width, self.rows.height));
+        for (chroma_x, sums) in self.sums.iter_mut().enumerate() {
+            let columns = (self.rows.width - chroma_x * 2).min(2);
+            let [_, u, v] = self
+                .coefficients
+                .to_yuv_sums(*sums, (columns * rows) as i32);
+            self.frame[u_start + chroma_x * step] = u;
+            self.frame[v_start + chroma_x * step] = v;
+            *sums = [0; 3];
+        }
+    }
+}
+
+impl RowStore for YuvStore<'_> {
+    fn rows(&mut self) -> &mut Rows {
+        &mut self.rows
+    }
+
+    fn place_row(&mut self, pixels: &[RGBA], x: usize, y: usize) {
+        let (width, height) = (self.rows.width, self.rows.height);
+        let (luma_start, step) = self.format.luma_row(y, (width, height));
+        let lumas = self.frame[luma_start + x * step..].iter_mut().step_by(step);
+        for ((column, &pixel), luma) in (x..).zip(pixels).zip(lumas) {
+            let [r, g, b, _] = pixel.to_ne_bytes().map(i32::from);
+            *luma = self.coefficients.to_luma([r, g, b]);
+            let sums = &mut self.sums[column / 2];
+            *sums = [sums[0] + r, sums[1] + g, sums[2] + b];
+        }
+
+        if x + pixels.len() == width {
+            if self.format == YuvFormat::Yuyv && width % 2 == 1 {
+                // the padding Y1 repeats the last pixel's Y
+                let last = luma_start + (width - 1) * step;
+                self.frame[last + 2] = self.frame[last];
+            }
+            let last_of_box = match self.format {
+                YuvFormat::Nv12 | YuvFormat::I420 => y % 2 == 1 || y == height - 1,
+                YuvFormat::Yuyv => true,
+            };
+            if last_of_box {
+                self.finish_chroma_row(y);
+            }
+        }
+    }
+}
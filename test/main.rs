@@ -7,11 +7,27 @@ use std::path::Path;
 use std::time::{Duration, Instant};
 
 use bytemuck::cast_slice;
-use image::{io::Reader, DynamicImage, ImageFormat};
+use image::{io::Reader, DynamicImage, ImageDecoder, ImageError, ImageFormat};
 
+use hardqoi::alpha::{flatten, premultiply, unpremultiply};
 use hardqoi::common::*;
 use hardqoi::decode;
 use hardqoi::encode;
+use hardqoi::inspect::{first_divergence, Divergence, OpTrace, TraceEnd};
+use hardqoi::integrity::{
+    append_trailer, crc32c, decode_checked, split_trailer, Trailer, TRAILER_SIZE, TRAILER_TAG,
+};
+use hardqoi::metadata::{append_metadata, strip_extensions, Chunk, Metadata, MetadataError};
+use hardqoi::{
+    decode_channel, decode_flattened_rgb, decode_gray, decode_lenient, decode_oriented,
+    decode_pitched, decode_scaled, decode_to_bytes, decode_to_bytes_with_options,
+    decode_to_dynamic_image, decode_with_alpha, decode_with_backend, decode_with_options,
+    decode_yuv, encode_dynamic_image, encode_narrow, encode_oriented, encode_premultiplied,
+    encode_strided, encode_with_backend, encode_with_stats, encode_yuv, ops, png_linear_rgb,
+    validate, AlphaMode, Backend, Channel, DecodeOptions, Encoder, NarrowFormat, OpError,
+    Orientation, PixelLayout, QoiDecoder, QoiEncoder, QoiOp, QoiWriter, ScaleFactor, WriteError,
+    YuvFormat, YuvMatrix, YuvOptions, YuvRange,
+};
 
 fn open_file(path: &str) -> (ImageFormat, DynamicImage) {
     let path = Path::new(path);
@@ -146,6 +162,1382 @@ fn compete_rapidqoi(png_path: &str) {
     // assert_eq!(our_qoi, reference_qoi);
 }
 
+fn image_trait_round_trip(img: DynamicImage) {
+    let mut qoi_data = Vec::new();
+    img.write_with_encoder(QoiEncoder::new(&mut qoi_data))
+        .expect("Encoding through ImageEncoder failed");
+
+    let decoder = QoiDecoder::new(qoi_data.as_slice()).expect("Invalid QOI header");
+    let decoded =
+        DynamicImage::from_decoder(decoder).expect("Decoding through ImageDecoder failed");
+    assert_eq!(img.color(), decoded.color());
+    assert_eq!(img.as_bytes(), decoded.as_bytes());
+}
+
+#[test]
+fn test_image_traits() {
+    let (_, img) = open_file("test/wonke.png");
+    image_trait_round_trip(DynamicImage::ImageRgba8(img.to_rgba8()));
+    image_trait_round_trip(DynamicImage::ImageRgb8(img.to_rgb8()));
+
+    // a header with nothing after it, or a stream cut short, is turned away before anything is decoded
+    let qoi_data = encode_dynamic_image(&img).unwrap();
+    for end in [14, qoi_data.len() / 2, qoi_data.len() - 1] {
+        let result = QoiDecoder::new(&qoi_data[..end]);
+        assert!(matches!(result, Err(ImageError::Decoding(_))), "{end}");
+    }
+}
+
+#[test]
+fn test_dynamic_image_conversions() {
+    let (_, img) = open_file("test/thonk.png");
+    for img in [
+        DynamicImage::ImageRgba8(img.to_rgba8()),
+        DynamicImage::ImageRgb8(img.to_rgb8()),
+    ] {
+        let qoi_data = encode_dynamic_image(&img).unwrap();
+        let decoded = decode_to_dynamic_image(&qoi_data).unwrap();
+        assert_eq!(img.color(), decoded.color());
+        assert_eq!(img.as_bytes(), decoded.as_bytes());
+    }
+
+    // more pixels than the header has room for are turned away rather than decoded
+    let mut overflowing = QOIHeader {
+        width: 1,
+        height: 1,
+        has_alpha: false,
+        linear_rgb: false,
+    }
+    .to_bytes();
+    overflowing.extend([QOI_OP_RUN | 61; 64]);
+    overflowing.extend(END_8);
+    assert_eq!(
+        decode_to_dynamic_image(&overflowing),
+        Err(OpError::RunOverflow(14))
+    );
+}
+
+fn run_cli(args: &[&str]) -> String {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_hardqoi"))
+        .args(args)
+        .output()
+        .expect("Unable to run the hardqoi binary");
+    assert!(
+        output.status.success(),
+        "hardqoi {args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn run_cli_with_stdin(args: &[&str], stdin: &[u8]) -> Vec<u8> {
+    use std::process::{Command, Stdio};
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hardqoi"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Unable to run the hardqoi binary");
+    let mut child_stdin = child.stdin.take().unwrap();
+    let stdin = stdin.to_vec();
+    let writer = std::thread::spawn(move || child_stdin.write_all(&stdin));
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap().unwrap();
+    assert!(
+        output.status.success(),
+        "hardqoi {args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output.stdout
+}
+
+fn tmp_path(filename: &str) -> String {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(filename);
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn test_cli_convert_dir() {
+    let source = Path::new(&tmp_path("convert-dir-source")).to_path_buf();
+    let destination = tmp_path("convert-dir-destination");
+    std::fs::create_dir_all(source.join("nested")).unwrap();
+    std::fs::copy("test/wonke.png", source.join("wonke.png")).unwrap();
+    std::fs::copy("test/gradient.png", source.join("nested/gradient.png")).unwrap();
+    std::fs::write(source.join("notes.txt"), "not an image").unwrap();
+
+    let source = source.to_str().unwrap();
+    let report = run_cli(&["convert-dir", source, &destination, "--force", "--verify"]);
+    assert!(
+        report.starts_with("2 converted, 0 up to date, 0 failed"),
+        "{report}"
+    );
+    let header = QOIHeader::from(
+        std::fs::read(Path::new(&destination).join("nested/gradient.qoi"))
+            .unwrap()
+            .as_slice(),
+    );
+    assert_eq!((header.width, header.height), (128, 68));
+
+    let report = run_cli(&["convert-dir", source, &destination]);
+    assert!(
+        report.starts_with("0 converted, 2 up to date, 0 failed"),
+        "{report}"
+    );
+}
+
+#[test]
+fn test_cli_diff() {
+    let qoi_path = tmp_path("cli-diff-wonke.qoi");
+    run_cli(&["convert", "test/wonke.png", &qoi_path]);
+    let report = run_cli(&["diff", "test/wonke.png", &qoi_path]);
+    assert_eq!(report, "The images are pixel-identical, 128x128\n");
+
+    let mut changed = open_file("test/wonke.png").1.into_rgba8();
+    changed.get_pixel_mut(3, 5).0[0] ^= 0x10;
+    changed.get_pixel_mut(9, 7).0[2] ^= 0x01;
+    let changed_path = tmp_path("cli-diff-changed.png");
+    changed.save(&changed_path).unwrap();
+
+    let heatmap_path = tmp_path("cli-diff-heatmap.qoi");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_hardqoi"))
+        .args(["diff", &qoi_path, &changed_path, "--heatmap", &heatmap_path])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let report = String::from_utf8(output.stdout).unwrap();
+    assert!(report.starts_with("2 of 16384 pixels differ"), "{report}");
+    assert!(
+        report.contains("bounding box: (3, 5) to (9, 7), 7x3"),
+        "{report}"
+    );
+    assert!(
+        report.contains("largest difference: r 16, g 0, b 1, a 0"),
+        "{report}"
+    );
+
+    let heatmap = std::fs::read(&heatmap_path).unwrap();
+    assert_eq!(QOIHeader::from(heatmap.as_slice()).image_size(), 128 * 128);
+}
+
+#[test]
+fn test_cli_view() {
+    let qoi_path = tmp_path("cli-view-wonke.qoi");
+    run_cli(&["convert", "test/wonke.png", &qoi_path, "--channels", "4"]);
+
+    let preview = run_cli(&["view", &qoi_path, "--width", "20", "--height", "5"]);
+    let lines: Vec<&str> = preview.lines().collect();
+    // 128x128 shrinks to 10x10 to fit 10 pixel rows, drawn two to a line
+    assert_eq!(lines.len(), 6);
+    assert_eq!(lines[0].matches('\u{2580}').count(), 10);
+    assert!(lines[0].starts_with("\x1b[38;2;"));
+    assert!(lines[5].ends_with("128x128, 4 channels, sRGB, 10649 bytes, shown at 10x10"));
+
+    let empty_path = tmp_path("cli-view-empty.qoi");
+    let mut empty = QOIHeader {
+        width: 0,
+        height: 0,
+        has_alpha: false,
+        linear_rgb: false,
+    }
+    .to_bytes();
+    empty.extend(END_8);
+    std::fs::write(&empty_path, &empty).unwrap();
+    let preview = run_cli(&["view", &empty_path]);
+    assert!(preview.ends_with("0x0, 3 channels, sRGB, 22 bytes, shown at 0x0\n"));
+
+    // more pixels than the header has room for are turned away rather than decoded
+    let overflowing_path = tmp_path("cli-view-overflowing.qoi");
+    let mut overflowing = QOIHeader {
+        width: 1,
+        height: 1,
+        has_alpha: false,
+        linear_rgb: false,
+    }
+    .to_bytes();
+    overflowing.extend([QOI_OP_RUN | 61; 64]);
+    overflowing.extend(END_8);
+    std::fs::write(&overflowing_path, &overflowing).unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_hardqoi"))
+        .args(["view", &overflowing_path])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_cli_pipe() {
+    let frame = open_file("test/place.png").1.into_rgba8();
+    let (width, height) = (frame.width().to_string(), frame.height().to_string());
+    let frames = [frame.as_raw().as_slice(), frame.as_raw()].concat();
+    let size = ["--width", &width, "--height", &height];
+
+    let qoi_stream = run_cli_with_stdin(&[&["pipe"], &size[..]].concat(), &frames);
+    assert!(qoi_stream.starts_with(&MAGIC_QOIF));
+
+    let decoded = run_cli_with_stdin(&["pipe", "--decode"], &qoi_stream);
+    assert_eq!(decoded, frames);
+    let decoded = run_cli_with_stdin(
+        &[&["pipe", "--decode", "--format", "rgb"], &size[..]].concat(),
+        &qoi_stream,
+    );
+    assert_eq!(decoded.len(), frames.len() / 4 * 3);
+
+    // metadata and a trailer after an image belong to it, rather than starting the next one
+    let pixels = rgba8_to_pixels(frame.as_raw());
+    let mut qoi_data = Vec::new();
+    encode(
+        &pixels,
+        &mut qoi_data,
+        QOIHeader::from(&DynamicImage::ImageRgba8(frame)),
+    )
+    .unwrap();
+    let mut metadata = Metadata::new();
+    metadata.set(Chunk::Orientation(6));
+    append_metadata(&mut qoi_data, &metadata);
+    append_trailer(&mut qoi_data, &pixels);
+    let decoded = run_cli_with_stdin(&["pipe", "--decode"], &qoi_data.repeat(2));
+    assert_eq!(decoded, frames);
+
+    // a run past the end of the image is turned away rather than decoded
+    let mut overflowing = QOIHeader {
+        width: 1,
+        height: 1,
+        has_alpha: false,
+        linear_rgb: false,
+    }
+    .to_bytes();
+    overflowing.extend([QOI_OP_RUN | 61; 64]);
+    overflowing.extend(END_8);
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_hardqoi"))
+        .args(["pipe", "--decode"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(&overflowing).unwrap();
+    assert!(!child.wait().unwrap().success());
+}
+
+#[test]
+fn test_every_backend() {
+    let (_, img) = open_file("test/wonke.png");
+    let meta = QOIHeader::from(&img);
+    let raw = rgba8_to_pixels(img.to_rgba8().as_raw());
+
+    let mut reference = Vec::new();
+    encode(&raw, &mut reference, meta).unwrap();
+    for backend in Backend::available() {
+        let mut qoi_data = Vec::new();
+        encode_with_backend(&raw, &mut qoi_data, meta, backend).unwrap();
+        assert_eq!(
+            qoi_data,
+            reference,
+            "{} encodes differently",
+            backend.name()
+        );
+
+        let mut decoded = Vec::new();
+        decode_with_backend(&qoi_data, &mut decoded, backend).unwrap();
+        assert_eq!(decoded, raw, "{} decodes differently", backend.name());
+
+        let mut encoder = Encoder::with_backend(backend);
+        for layout in [PixelLayout::Bgra, PixelLayout::Argb, PixelLayout::Abgr] {
+            let swizzled: Vec<RGBA> = raw.iter().map(|&pixel| layout.from_rgba(pixel)).collect();
+            let mut qoi_data = Vec::new();
+            encoder
+                .encode_with_layout(&swizzled, &mut qoi_data, meta, layout)
+                .unwrap();
+            assert_eq!(
+                qoi_data,
+                reference,
+                "{} encodes {layout:?} differently",
+                backend.name()
+            );
+        }
+    }
+}
+
+#[test]
+fn test_cli_bench() {
+    let directory = tmp_path("bench");
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::copy(
+        "test/gradient.png",
+        Path::new(&directory).join("gradient.png"),
+    )
+    .unwrap();
+
+    let json = run_cli(&["bench", &directory, "--iterations", "2", "--json"]);
+    for backend in Backend::available() {
+        let codec = format!("\"codec\": \"hardqoi/{}\"", backend.name());
+        assert!(json.contains(&codec), "{codec} is missing from {json}");
+    }
+    let table = run_cli(&["bench", &directory, "--iterations", "1"]);
+    assert!(table.contains("total (1 images)"));
+}
+
+#[test]
+fn test_png_colorspace() {
+    assert_eq!(
+        png_linear_rgb(&std::fs::read("test/thonk.png").unwrap()),
+        Some(false)
+    );
+    assert_eq!(
+        png_linear_rgb(&std::fs::read("test/wonke.png").unwrap()),
+        None
+    );
+    assert_eq!(png_linear_rgb(b"qoif"), None);
+}
+
+#[test]
+fn test_cli_convert() {
+    let qoi_path = tmp_path("cli-gradient.qoi");
+    let png_path = tmp_path("cli-gradient.png");
+    run_cli(&["convert", "test/gradient.png", &qoi_path, "--verify"]);
+    run_cli(&["convert", &qoi_path, &png_path, "--verify"]);
+
+    // gradient.png has no alpha channel and no colorspace chunks
+    let header = QOIHeader::from(std::fs::read(&qoi_path).unwrap().as_slice());
+    assert!(!header.has_alpha);
+    assert!(!header.linear_rgb);
+
+    run_cli(&[
+        "convert",
+        "test/wonke.png",
+        &qoi_path,
+        "--linear",
+        "--channels",
+        "4",
+    ]);
+    let header = QOIHeader::from(std::fs::read(&qoi_path).unwrap().as_slice());
+    assert!(header.has_alpha);
+    assert!(header.linear_rgb);
+}
+
+#[test]
+fn test_op_trace() {
+    let (_, img) = open_file("test/wonke.png");
+    let qoi_data = encode_dynamic_image(&img).unwrap();
+    let decoded = decode_to_dynamic_image(&qoi_data).unwrap().to_rgba8();
+
+    let mut trace = OpTrace::new(&qoi_data).unwrap();
+    for record in trace.by_ref() {
+        for i in record.pixels() {
+            assert_eq!(
+                record.pixel.to_ne_bytes(),
+                decoded.as_raw()[i * 4..i * 4 + 4]
+            );
+        }
+    }
+    assert_eq!(trace.end(), Some(TraceEnd::Complete));
+    assert_eq!(trace.pixels_decoded(), trace.header().image_size());
+
+    let complete = OpTrace::new(&qoi_data).unwrap();
+    let truncated = OpTrace::new(&qoi_data[..qoi_data.len() / 2]).unwrap();
+    match first_divergence(complete, truncated) {
+        Some(Divergence::Op {
+            left: Some(_),
+            right: None,
+            ..
+        }) => (),
+        other => panic!("Expected the truncated stream to run out of ops, found {other:?}"),
+    }
+    let (left, right) = (OpTrace::new(&qoi_data), OpTrace::new(&qoi_data));
+    assert_eq!(first_divergence(left.unwrap(), right.unwrap()), None);
+}
+
+#[test]
+fn test_ops() {
+    let (_, img) = open_file("test/wonke.png");
+    let qoi_data = encode_dynamic_image(&img).unwrap();
+
+    let parsed: Vec<(usize, QoiOp)> = ops(&qoi_data).map(Result::unwrap).collect();
+    let traced: Vec<(usize, QoiOp)> = OpTrace::new(&qoi_data)
+        .unwrap()
+        .map(|record| (record.offset, record.op))
+        .collect();
+    assert_eq!(parsed, traced);
+    let (last_offset, last_op) = *parsed.last().unwrap();
+    assert_eq!(last_offset + last_op.size() + 8, qoi_data.len());
+
+    let last = |data: &[u8]| ops(data).last().unwrap();
+    assert_eq!(last(&qoi_data[..10]), Err(OpError::NotQoi));
+    assert_eq!(
+        last(&qoi_data[..last_offset + last_op.size()]),
+        Err(OpError::MissingEndMarker(last_offset + last_op.size()))
+    );
+    assert_eq!(last(&qoi_data[..14]), Err(OpError::Truncated(14)));
+
+    // a maximal run straight after the header of a 2x2 image
+    let mut overflowing = QOIHeader {
+        width: 2,
+        height: 2,
+        has_alpha: true,
+        linear_rgb: false,
+    }
+    .to_bytes();
+    overflowing.push(QOI_OP_RUN | 61);
+    assert_eq!(last(&overflowing), Err(OpError::RunOverflow(14)));
+}
+
+#[test]
+fn test_validate() {
+    let (_, img) = open_file("test/wonke.png");
+    let qoi_data = encode_dynamic_image(&img).unwrap();
+    let length = qoi_data.len();
+    assert_eq!(validate(&qoi_data), Ok(QOIHeader::from(&img)));
+
+    let mut trailing = qoi_data.clone();
+    trailing.push(0);
+    assert_eq!(validate(&trailing), Err(OpError::TrailingBytes(length)));
+
+    let mut no_marker = qoi_data.clone();
+    no_marker[length - 1] = 0;
+    assert_eq!(
+        validate(&no_marker),
+        Err(OpError::MissingEndMarker(length - 8))
+    );
+    assert!(matches!(
+        validate(&qoi_data[..length / 2]),
+        Err(OpError::Truncated(_))
+    ));
+
+    let mut bad_channels = qoi_data.clone();
+    bad_channels[12] = 5;
+    assert_eq!(validate(&bad_channels), Err(OpError::InvalidHeader));
+    assert_eq!(validate(b"qoif"), Err(OpError::NotQoi));
+
+    // more pixels than the header promises, with the last op running over the end
+    let mut too_small = qoi_data.clone();
+    too_small[4..8].copy_from_slice(&1u32.to_be_bytes());
+    too_small[8..12].copy_from_slice(&1u32.to_be_bytes());
+    too_small[14] = QOI_OP_RUN | 1;
+    assert_eq!(validate(&too_small), Err(OpError::RunOverflow(14)));
+}
+
+#[test]
+fn test_decode_lenient() {
+    let (_, img) = open_file("test/wonke.png");
+    let qoi_data = encode_dynamic_image(&img).unwrap();
+    let mut expected = Vec::new();
+    decode(&qoi_data, &mut expected).unwrap();
+    let fill = RGBA::from_ne_bytes([255, 0, 255, 255]);
+
+    let complete = decode_lenient(&qoi_data, fill).unwrap();
+    assert!(complete.is_complete());
+    assert_eq!(complete.error, None);
+    assert_eq!(complete.pixels, expected);
+
+    let partial = decode_lenient(&qoi_data[..qoi_data.len() / 2], fill).unwrap();
+    assert!(!partial.is_complete());
+    assert!(matches!(partial.error, Some(OpError::Truncated(_))));
+    let decoded = partial.pixels_decoded;
+    assert!(decoded > 0);
+    assert_eq!(partial.pixels[..decoded], expected[..decoded]);
+    assert!(partial.pixels[decoded..].iter().all(|&pixel| pixel == fill));
+
+    let mut trailing = qoi_data.clone();
+    trailing.extend([0xff; 64]);
+    let trailing = decode_lenient(&trailing, fill).unwrap();
+    assert_eq!(trailing.error, Some(OpError::TrailingBytes(qoi_data.len())));
+    assert_eq!(trailing.pixels, expected);
+
+    let unmarked = decode_lenient(&qoi_data[..qoi_data.len() - 8], fill).unwrap();
+    assert!(unmarked.is_complete());
+    assert!(matches!(unmarked.error, Some(OpError::MissingEndMarker(_))));
+    assert_eq!(unmarked.pixels, expected);
+
+    // a run that's too long is cut short to fill the image
+    let mut overflowing = QOIHeader {
+        width: 2,
+        height: 1,
+        has_alpha: true,
+        linear_rgb: false,
+    }
+    .to_bytes();
+    overflowing.push(QOI_OP_RUN | 61);
+    overflowing.extend(END_8);
+    let cut_short = decode_lenient(&overflowing, fill).unwrap();
+    assert!(cut_short.is_complete());
+    assert_eq!(cut_short.error, Some(OpError::RunOverflow(14)));
+    assert_eq!(cut_short.pixels, [RGBA::from_ne_bytes([0, 0, 0, 255]); 2]);
+
+    assert_eq!(
+        decode_lenient(b"not a qoi file", fill),
+        Err(OpError::NotQoi)
+    );
+}
+
+#[test]
+fn test_integrity_trailer() {
+    assert_eq!(crc32c(b"123456789"), 0xe3069283);
+    assert_eq!(crc32c(&[]), 0);
+
+    let (_, img) = open_file("test/wonke.png");
+    let pixels = rgba8_to_pixels(img.to_rgba8().as_raw());
+    let mut qoi_data = Vec::new();
+    encode(&pixels, &mut qoi_data, QOIHeader::from(&img)).unwrap();
+    append_trailer(&mut qoi_data, &pixels);
+    let trailer_offset = qoi_data.len() - TRAILER_SIZE;
+
+    assert!(validate(&qoi_data).is_ok());
+    let mut decoded = Vec::new();
+    assert_eq!(decode_checked(&qoi_data, &mut decoded), Ok(true));
+    assert_eq!(decoded, pixels);
+    let mut trace = OpTrace::new(&qoi_data).unwrap();
+    trace.by_ref().for_each(drop);
+    assert_eq!(trace.end(), Some(TraceEnd::Complete));
+    let mut plain = Vec::new();
+    decode(&qoi_data, &mut plain).unwrap();
+    assert_eq!(plain, pixels);
+
+    // a flipped bit in the color of an RGB or RGBA op leaves the stream well formed
+    let (offset, _) = ops(&qoi_data)
+        .map(Result::unwrap)
+        .find(|(_, op)| matches!(op, QoiOp::Rgb { .. } | QoiOp::Rgba { .. }))
+        .unwrap();
+    let mut flipped = qoi_data.clone();
+    flipped[offset + 1] ^= 0x04;
+    assert_eq!(
+        validate(&flipped),
+        Err(OpError::ChecksumMismatch(trailer_offset))
+    );
+
+    // a wrong pixel checksum only shows once the pixels are decoded
+    let mut wrong_pixels = qoi_data.clone();
+    let trailer = Trailer::parse(&qoi_data[trailer_offset..]).unwrap();
+    let wrong = Trailer {
+        pixels_crc: !trailer.pixels_crc,
+        ..trailer
+    };
+    wrong_pixels[trailer_offset..].copy_from_slice(&wrong.to_bytes());
+    assert!(validate(&wrong_pixels).is_ok());
+    // and salvaging a file with a broken trailer keeps its pixels
+    let salvaged = decode_lenient(&flipped, 0).unwrap();
+    assert_eq!(salvaged.pixels_decoded, pixels.len());
+    assert_eq!(
+        salvaged.error,
+        Some(OpError::ChecksumMismatch(trailer_offset))
+    );
+    let mut decoded = Vec::new();
+    assert_eq!(
+        decode_checked(&wrong_pixels, &mut decoded),
+        Err(OpError::ChecksumMismatch(trailer_offset))
+    );
+    assert!(decoded.is_empty());
+
+    let mut untrailed = Vec::new();
+    encode(&pixels, &mut untrailed, QOIHeader::from(&img)).unwrap();
+    assert_eq!(decode_checked(&untrailed, &mut decoded), Ok(false));
+
+    // a plain stream whose last ops happen to spell the tag after what looks like an end marker
+    let mut lookalike = QOIHeader {
+        width: 12,
+        height: 1,
+        has_alpha: true,
+        linear_rgb: false,
+    }
+    .to_bytes();
+    lookalike.extend([0, 0, 0, 0, 0, 0, 0, 1]);
+    lookalike.extend(TRAILER_TAG);
+    lookalike.extend(END_8);
+    assert_eq!(split_trailer(&lookalike), (&lookalike[..], None));
+    assert!(validate(&lookalike).is_ok());
+    let mut decoded = vec![0; 3];
+    decode(&lookalike, &mut decoded).unwrap();
+    assert_eq!(decoded.len(), 3 + 12);
+    let mut bytes = vec![0; 5];
+    decode_to_bytes(&lookalike, &mut bytes).unwrap();
+    assert_eq!(bytes.len(), 5 + 12 * 4);
+    assert_eq!(decode_checked(&lookalike, &mut decoded), Ok(false));
+
+    let qoi_path = tmp_path("cli-checksum.qoi");
+    run_cli(&["convert", "test/wonke.png", &qoi_path, "--checksum"]);
+    let written = std::fs::read(&qoi_path).unwrap();
+    assert_eq!(written[written.len() - TRAILER_SIZE..][..4], *b"qcrc");
+    assert_eq!(decode_checked(&written, &mut Vec::new()), Ok(true));
+}
+
+#[test]
+fn test_metadata() {
+    let mut metadata = Metadata::new();
+    metadata.set(Chunk::IccProfile(vec![1, 2, 3, 4, 5]));
+    metadata.set(Chunk::Orientation(3));
+    metadata.set(Chunk::Text {
+        key: "Author".into(),
+        value: "nobody".into(),
+    });
+    metadata.set(Chunk::Unknown {
+        tag: *b"zzzz",
+        data: vec![0; 3],
+    });
+    // replaces the first orientation, and adds to the text rather than replacing it
+    metadata.set(Chunk::Orientation(6));
+    metadata.set(Chunk::Text {
+        key: "Software".into(),
+        value: "hardqoi \u{1f980}".into(),
+    });
+    assert_eq!(metadata.chunks.len(), 5);
+    assert_eq!(metadata.orientation(), Some(6));
+    assert_eq!(metadata.text("Author"), Some("nobody"));
+    assert_eq!(metadata.text("Software"), Some("hardqoi \u{1f980}"));
+
+    let (_, img) = open_file("test/wonke.png");
+    let pixels = rgba8_to_pixels(img.to_rgba8().as_raw());
+    let mut plain = Vec::new();
+    encode(&pixels, &mut plain, QOIHeader::from(&img)).unwrap();
+    let mut qoi_data = plain.clone();
+    append_metadata(&mut qoi_data, &metadata);
+    assert_eq!(Metadata::read(&qoi_data), Ok(metadata.clone()));
+    assert_eq!(strip_extensions(&qoi_data), plain);
+    assert!(validate(&qoi_data).is_ok());
+    let mut decoded = Vec::new();
+    decode(&qoi_data, &mut decoded).unwrap();
+    assert_eq!(decoded, pixels);
+
+    // the trailer goes after the metadata, and covers it
+    append_trailer(&mut qoi_data, &pixels);
+    assert_eq!(Metadata::read(&qoi_data), Ok(metadata.clone()));
+    assert_eq!(strip_extensions(&qoi_data), plain);
+    assert_eq!(decode_checked(&qoi_data, &mut Vec::new()), Ok(true));
+    let mut flipped = qoi_data.clone();
+    flipped[plain.len() + 8] ^= 1;
+    assert!(matches!(
+        validate(&flipped),
+        Err(OpError::ChecksumMismatch(_))
+    ));
+    assert_eq!(Metadata::read(&plain), Ok(Metadata::new()));
+
+    let mut bad_orientation = plain.clone();
+    let mut orientation = Metadata::new();
+    orientation.set(Chunk::Orientation(3));
+    append_metadata(&mut bad_orientation, &orientation);
+    bad_orientation[plain.len() + 8] = 9;
+    assert_eq!(
+        Metadata::read(&bad_orientation),
+        Err(MetadataError::InvalidOrientation)
+    );
+
+    // chunk lengths that don't add up mean it isn't metadata after all
+    let mut broken = plain.clone();
+    append_metadata(&mut broken, &metadata);
+    broken[plain.len() + 4] ^= 1;
+    assert_eq!(validate(&broken), Err(OpError::TrailingBytes(plain.len())));
+
+    let mut encoded = Vec::new();
+    img.write_with_encoder(QoiEncoder::new(&mut encoded).with_icc_profile(vec![9; 100]))
+        .unwrap();
+    let mut decoder = QoiDecoder::new(encoded.as_slice()).unwrap();
+    assert_eq!(decoder.icc_profile(), Some(vec![9; 100]));
+
+    // converting QOI to QOI keeps the metadata
+    let (from, to) = (tmp_path("metadata-from.qoi"), tmp_path("metadata-to.qoi"));
+    std::fs::write(&from, &qoi_data).unwrap();
+    run_cli(&["convert", &from, &to]);
+    assert_eq!(Metadata::read(&std::fs::read(&to).unwrap()), Ok(metadata));
+}
+
+#[test]
+fn test_qoi_writer() {
+    let header = QOIHeader {
+        width: 3,
+        height: 2,
+        has_alpha: true,
+        linear_rgb: false,
+    };
+    let mut qoi_data = Vec::new();
+    let mut writer = QoiWriter::new(&mut qoi_data, header);
+    let mut expected = Vec::new();
+
+    // a leading run repeats the implicit opaque black, and puts it in the index like any other pixel
+    let black = writer.run(2).unwrap();
+    expected.extend([black; 2]);
+    assert_eq!(black, RGBA::from_ne_bytes([0, 0, 0, 255]));
+    assert_eq!(writer.index_array()[53], black);
+    expected.push(writer.rgba(10, 20, 30, 40).unwrap());
+    expected.push(writer.diff(1, -2, 0).unwrap());
+    assert_eq!(writer.previous_pixel().to_ne_bytes(), [11, 18, 30, 40]);
+    expected.push(writer.luma(-32, 7, -8).unwrap());
+    assert_eq!(writer.previous_pixel().to_ne_bytes(), [242, 242, 246, 40]);
+
+    assert_eq!(
+        writer.diff(2, 0, 0),
+        Err(WriteError::OutOfRange(QoiOp::Diff {
+            dr: 2,
+            dg: 0,
+            db: 0
+        }))
+    );
+    assert!(matches!(writer.run(63), Err(WriteError::OutOfRange(_))));
+    assert!(matches!(writer.index(64), Err(WriteError::OutOfRange(_))));
+    assert_eq!(
+        writer.run(2),
+        Err(WriteError::TooManyPixels {
+            written: 5,
+            expected: 6
+        })
+    );
+    expected.push(writer.index(53).unwrap());
+    writer.finish().unwrap();
+
+    let mut decoded = Vec::with_capacity(header.image_size());
+    decode(&qoi_data, &mut decoded).unwrap();
+    assert_eq!(decoded, expected);
+    assert_eq!(ops(&qoi_data).filter(Result::is_err).count(), 0);
+
+    let mut unfinished = Vec::new();
+    let mut writer = QoiWriter::new(&mut unfinished, header);
+    writer.rgb(1, 2, 3).unwrap();
+    assert_eq!(
+        writer.finish(),
+        Err(WriteError::MissingPixels {
+            written: 1,
+            expected: 6
+        })
+    );
+}
+
+#[test]
+fn test_encode_stats() {
+    let (_, img) = open_file("test/wonke.png");
+    let header = QOIHeader::from(&img);
+    let pixels = rgba8_to_pixels(img.to_rgba8().as_raw());
+    let mut qoi_data = Vec::new();
+    let stats = encode_with_stats(&pixels, &mut qoi_data, header).unwrap();
+
+    assert_eq!(stats.backend, Backend::detected());
+    assert_eq!(stats.output_bytes, qoi_data.len());
+    assert_eq!(stats.input_bytes, pixels.len() * 4);
+    assert_eq!(stats.ops().bytes + 14 + 8, qoi_data.len());
+    assert_eq!(stats.ops().count, ops(&qoi_data).count());
+    assert_eq!(stats.index_hits.iter().sum::<usize>(), stats.index.count);
+    assert_eq!(stats.run_lengths.iter().sum::<usize>(), stats.run.count);
+    let run_pixels: usize = (1..=62).map(|n| n * stats.run_lengths[n - 1]).sum();
+    assert_eq!(
+        stats.ops().count - stats.run.count + run_pixels,
+        pixels.len()
+    );
+    assert!(stats.alpha_changes > 0);
+    assert!(stats.compression_ratio() < 1.0);
+    assert!(stats.byte_share(stats.rgb) < 1.0);
+
+    let mut plain = Vec::new();
+    encode(&pixels, &mut plain, header).unwrap();
+    assert_eq!(plain, qoi_data);
+}
+
+#[test]
+fn test_alpha_modes() {
+    // every premultiplied pixel survives un-premultiplying and premultiplying again
+    for a in 0..=255u8 {
+        for c in 0..=a {
+            let premultiplied = RGBA::from_ne_bytes([c, c / 2, 0, a]);
+            assert_eq!(premultiply(unpremultiply(premultiplied)), premultiplied);
+        }
+        for c in 0..=255u8 {
+            let expected = (c as f64 * a as f64 / 255.0).round() as u8;
+            let [r, _, _, alpha] = premultiply(RGBA::from_ne_bytes([c, c, c, a])).to_ne_bytes();
+            assert_eq!((r, alpha), (expected, a));
+        }
+    }
+
+    let (_, img) = open_file("test/wonke.png");
+    let header = QOIHeader::from(&img);
+    let pixels = rgba8_to_pixels(img.to_rgba8().as_raw());
+    let mut qoi_data = Vec::new();
+    encode(&pixels, &mut qoi_data, header).unwrap();
+
+    let mut premultiplied = Vec::new();
+    decode_with_alpha(&qoi_data, &mut premultiplied, AlphaMode::Premultiplied).unwrap();
+    let expected: Vec<RGBA> = pixels.iter().map(|&pixel| premultiply(pixel)).collect();
+    assert_eq!(premultiplied, expected);
+
+    let background = RGBA::from_ne_bytes([0x20, 0x80, 0xe0, 0xff]);
+    let mut flattened = Vec::new();
+    decode_with_alpha(&qoi_data, &mut flattened, AlphaMode::Flatten(background)).unwrap();
+    let expected: Vec<RGBA> = pixels
+        .iter()
+        .map(|&pixel| flatten(pixel, background))
+        .collect();
+    assert_eq!(flattened, expected);
+    let mut rgb = Vec::new();
+    decode_flattened_rgb(&qoi_data, &mut rgb, [0x20, 0x80, 0xe0]).unwrap();
+    let mut expected_rgb = vec![0; expected.len() * 3];
+    pixels_to_rgb8(&expected, &mut expected_rgb);
+    assert_eq!(rgb, expected_rgb);
+
+    let mut round_trip = Vec::new();
+    encode_premultiplied(&premultiplied, &mut round_trip, header).unwrap();
+    let mut decoded = Vec::new();
+    decode_with_alpha(&round_trip, &mut decoded, AlphaMode::Premultiplied).unwrap();
+    assert_eq!(decoded, premultiplied);
+}
+
+#[test]
+fn test_pixel_layouts() {
+    let (_, img) = open_file("test/wonke.png");
+    let header = QOIHeader::from(&img);
+    let pixels = rgba8_to_pixels(img.to_rgba8().as_raw());
+    let mut qoi_data = Vec::new();
+    encode(&pixels, &mut qoi_data, header).unwrap();
+
+    let pixel = RGBA::from_ne_bytes([1, 2, 3, 4]);
+    for (layout, bytes) in [
+        (PixelLayout::Rgba, [1, 2, 3, 4]),
+        (PixelLayout::Bgra, [3, 2, 1, 4]),
+        (PixelLayout::Argb, [4, 1, 2, 3]),
+        (PixelLayout::Abgr, [4, 3, 2, 1]),
+    ] {
+        assert_eq!(layout.from_rgba(pixel).to_ne_bytes(), bytes);
+
+        for alpha in [AlphaMode::Straight, AlphaMode::Premultiplied] {
+            let options = DecodeOptions { alpha, layout };
+            let expected: Vec<RGBA> = pixels
+                .iter()
+                .map(|&pixel| match alpha {
+                    AlphaMode::Straight => layout.from_rgba(pixel),
+                    _ => layout.from_rgba(premultiply(pixel)),
+                })
+                .collect();
+            let mut decoded = Vec::new();
+            decode_with_options(&qoi_data, &mut decoded, options).unwrap();
+            assert!(decoded == expected, "{options:?}");
+            let mut bytes = Vec::new();
+            decode_to_bytes_with_options(&qoi_data, &mut bytes, options).unwrap();
+            assert!(bytes == cast_slice::<RGBA, u8>(&expected), "{options:?}");
+        }
+    }
+
+    for channel in [Channel::Alpha, Channel::Luma] {
+        let mut mask = Vec::new();
+        decode_channel(&qoi_data, &mut mask, channel).unwrap();
+        let expected: Vec<u8> = pixels.iter().map(|&pixel| channel.extract(pixel)).collect();
+        assert_eq!(mask, expected);
+    }
+}
+
+#[test]
+fn test_strided_and_pitched() {
+    // runs of one color that wrap from each row onto the next, around a few odd pixels
+    let (width, height) = (7usize, 5usize);
+    let mut pixels = vec![RGBA::from_ne_bytes([10, 20, 30, 255]); width * height];
+    pixels[3] = RGBA::from_ne_bytes([200, 20, 30, 128]);
+    pixels[width * 2 + 1] = RGBA::from_ne_bytes([10, 21, 30, 255]);
+    pixels[width * 4 - 1] = RGBA::from_ne_bytes([0; 4]);
+    let header = QOIHeader {
+        width: width as u32,
+        height: height as u32,
+        has_alpha: true,
+        linear_rgb: false,
+    };
+    let mut packed = Vec::new();
+    encode(&pixels, &mut packed, header).unwrap();
+    assert!(
+        ops(&packed).any(|op| matches!(op, Ok((_, QoiOp::Run(length))) if length > width as u8))
+    );
+
+    // the image as a region at (3, 2) of a bigger BGRA canvas full of something else
+    let (stride, x, y) = (61, 3, 2);
+    let mut canvas = vec![0xee; stride * (y + height) + 5];
+    for (row, row_pixels) in pixels.chunks_exact(width).enumerate() {
+        for (column, &pixel) in row_pixels.iter().enumerate() {
+            let start = (y + row) * stride + (x + column) * 4;
+            canvas[start..start + 4]
+                .copy_from_slice(&PixelLayout::Bgra.from_rgba(pixel).to_ne_bytes());
+        }
+    }
+    let mut strided = Vec::new();
+    encode_strided(
+        &canvas[y * stride + x * 4..],
+        stride,
+        &mut strided,
+        header,
+        PixelLayout::Bgra,
+    )
+    .unwrap();
+    assert_eq!(strided, packed);
+
+    // packed rows a byte off alignment, which are copied a row at a time
+    let mut shifted = vec![0; width * height * 4 + 1];
+    shifted[1..].copy_from_slice(cast_slice(&pixels));
+    let mut strided = Vec::new();
+    encode_strided(
+        &shifted[1..],
+        width * 4,
+        &mut strided,
+        header,
+        PixelLayout::Rgba,
+    )
+    .unwrap();
+    assert_eq!(strided, packed);
+
+    let pitch = 256;
+    let mut buffer = vec![0xee; pitch * height];
+    decode_pitched(&packed, &mut buffer, pitch, DecodeOptions::default()).unwrap();
+    for (row, row_pixels) in pixels.chunks_exact(width).enumerate() {
+        let row_bytes = &buffer[row * pitch..(row + 1) * pitch];
+        assert_eq!(&row_bytes[..width * 4], cast_slice::<RGBA, u8>(row_pixels));
+        assert!(row_bytes[width * 4..].iter().all(|&byte| byte == 0xee));
+    }
+
+    let (_, img) = open_file("test/wonke.png");
+    let header = QOIHeader::from(&img);
+    let pixels = rgba8_to_pixels(img.to_rgba8().as_raw());
+    let mut qoi_data = Vec::new();
+    encode(&pixels, &mut qoi_data, header).unwrap();
+    let row_bytes = header.width as usize * 4;
+    let pitch = row_bytes.next_multiple_of(256);
+    let options = DecodeOptions {
+        alpha: AlphaMode::Premultiplied,
+        layout: PixelLayout::Bgra,
+    };
+    let mut buffer = vec![0; pitch * header.height as usize];
+    decode_pitched(&qoi_data, &mut buffer, pitch, options).unwrap();
+    let mut expected = Vec::new();
+    decode_to_bytes_with_options(&qoi_data, &mut expected, options).unwrap();
+    for (row, expected_row) in expected.chunks_exact(row_bytes).enumerate() {
+        assert_eq!(&buffer[row * pitch..row * pitch + row_bytes], expected_row);
+    }
+}
+
+#[test]
+fn test_orientations() {
+    // bigger than the decoder's working window, with runs longer than it as well as noise
+    let (width, height) = (397usize, 331usize);
+    let mut state = 0x2545f491u32;
+    let pixels: Vec<RGBA> = (0..width * height)
+        .map(|index| match index / 70_000 % 2 {
+            0 => RGBA::from_ne_bytes([40, 80, 120, 255]),
+            _ => {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state & 0x0f0f0fff | 0xf0_00_00_00
+            }
+        })
+        .collect();
+    let header = QOIHeader {
+        width: width as u32,
+        height: height as u32,
+        has_alpha: true,
+        linear_rgb: false,
+    };
+    let mut qoi_data = Vec::new();
+    encode(&pixels, &mut qoi_data, header).unwrap();
+
+    let mut luma = Vec::new();
+    decode_channel(&qoi_data, &mut luma, Channel::Luma).unwrap();
+    let expected: Vec<u8> = pixels.iter().map(|&p| Channel::Luma.extract(p)).collect();
+    assert_eq!(luma, expected);
+
+    let options = DecodeOptions {
+        alpha: AlphaMode::Premultiplied,
+        layout: PixelLayout::Argb,
+    };
+    let converted: Vec<RGBA> = pixels
+        .iter()
+        .map(|&p| PixelLayout::Argb.from_rgba(premultiply(p)))
+        .collect();
+    for exif in 1..=8 {
+        let orientation = Orientation::from_exif(exif).unwrap();
+        let (to_width, to_height) = orientation.oriented_size(header.width, header.height);
+        let mut transformed = vec![0; width * height];
+        for (index, &pixel) in converted.iter().enumerate() {
+            let (x, y) = orientation.map(index % width, index / width, width, height);
+            transformed[y * to_width as usize + x] = pixel;
+        }
+
+        let mut decoded = vec![7];
+        decode_oriented(&qoi_data, &mut decoded, orientation, options).unwrap();
+        assert_eq!(decoded[0], 7);
+        assert!(decoded[1..] == transformed, "decoding as {orientation:?}");
+
+        let mut transformed_raw = vec![0; width * height];
+        for (index, &pixel) in pixels.iter().enumerate() {
+            let (x, y) = orientation.map(index % width, index / width, width, height);
+            transformed_raw[y * to_width as usize + x] = pixel;
+        }
+        let mut expected = Vec::new();
+        let oriented_header = QOIHeader {
+            width: to_width,
+            height: to_height,
+            ..header
+        };
+        encode(&transformed_raw, &mut expected, oriented_header).unwrap();
+        let mut encoded = Vec::new();
+        encode_oriented(&pixels, &mut encoded, header, orientation).unwrap();
+        assert!(encoded == expected, "encoding as {orientation:?}");
+    }
+    assert_eq!(Orientation::from_exif(0), None);
+    assert_eq!(Orientation::from_exif(9), None);
+
+    // a header promising a row more than there is
+    let mut short = qoi_data.clone();
+    short[8..12].copy_from_slice(&(height as u32 + 1).to_be_bytes());
+    let mut decoded = vec![7];
+    let result = decode_oriented(&short, &mut decoded, Orientation::Rotate90, options);
+    assert!(result.is_err());
+    assert_eq!(decoded, [7]);
+
+    // no width, but pixels all the same, which have no row to go in
+    let mut widthless = QOIHeader {
+        width: 0,
+        height: 3,
+        has_alpha: false,
+        linear_rgb: false,
+    }
+    .to_bytes();
+    widthless.extend([QOI_OP_RGB, 1, 2, 3, QOI_OP_RUN | 5]);
+    widthless.extend(END_8);
+    let mut decoded = Vec::new();
+    assert!(decode_oriented(&widthless, &mut decoded, Orientation::Rotate90, options).is_err());
+    assert!(decode_pitched(&widthless, &mut [0; 32], 16, options).is_err());
+    assert!(decode_scaled(&widthless, &mut decoded, ScaleFactor::Half).is_err());
+    let mut frame = Vec::new();
+    let full_601 = YuvOptions {
+        matrix: YuvMatrix::Bt601,
+        range: YuvRange::Full,
+    };
+    assert!(decode_yuv(&widthless, &mut frame, YuvFormat::I420, full_601).is_err());
+}
+
+/// Box filters the straightforward way, weighting color by alpha
+fn box_filter(pixels: &[RGBA], width: usize, height: usize, divisor: usize) -> Vec<RGBA> {
+    let mut output = Vec::new();
+    for out_y in 0..height.div_ceil(divisor) {
+        for out_x in 0..width.div_ceil(divisor) {
+            let mut sums = [0u32; 4];
+            let mut count = 0;
+            for y in out_y * divisor..((out_y + 1) * divisor).min(height) {
+                for x in out_x * divisor..((out_x + 1) * divisor).min(width) {
+                    let [r, g, b, a] = pixels[y * width + x].to_ne_bytes().map(u32::from);
+                    sums = [
+                        sums[0] + r * a,
+                        sums[1] + g * a,
+                        sums[2] + b * a,
+                        sums[3] + a,
+                    ];
+                    count += 1;
+                }
+            }
+            let a = sums[3];
+            output.push(match a {
+                0 => 0,
+                _ => RGBA::from_ne_bytes([
+                    ((sums[0] + a / 2) / a) as u8,
+                    ((sums[1] + a / 2) / a) as u8,
+                    ((sums[2] + a / 2) / a) as u8,
+                    ((a + count / 2) / count) as u8,
+                ]),
+            });
+        }
+    }
+    output
+}
+
+#[test]
+fn test_scaled() {
+    let (_, img) = open_file("test/wonke.png");
+    let header = QOIHeader::from(&img);
+    let pixels = rgba8_to_pixels(img.to_rgba8().as_raw());
+    let mut wonke = Vec::new();
+    encode(&pixels, &mut wonke, header).unwrap();
+
+    // sides that don't divide evenly, and alpha that varies within boxes
+    let (width, height) = (203usize, 117usize);
+    let noise: Vec<RGBA> = (0..width * height)
+        .map(|index| match index % 7 {
+            0 => 0,
+            _ => (index as u32).wrapping_mul(0x9e3779b9) | 0x10_00_00_00,
+        })
+        .collect();
+    let noise_header = QOIHeader {
+        width: width as u32,
+        height: height as u32,
+        has_alpha: true,
+        linear_rgb: false,
+    };
+    let mut noisy = Vec::new();
+    encode(&noise, &mut noisy, noise_header).unwrap();
+
+    for factor in [ScaleFactor::Half, ScaleFactor::Quarter, ScaleFactor::Eighth] {
+        for (qoi_data, header, pixels) in
+            [(&wonke, header, &pixels), (&noisy, noise_header, &noise)]
+        {
+            let (width, height) = (header.width as usize, header.height as usize);
+            let (scaled_width, scaled_height) = factor.scaled_size(header.width, header.height);
+            let mut scaled = vec![7];
+            decode_scaled(qoi_data, &mut scaled, factor).unwrap();
+            assert_eq!(
+                scaled.len(),
+                1 + scaled_width as usize * scaled_height as usize
+            );
+            assert!(
+                scaled[1..] == box_filter(pixels, width, height, factor.divisor()),
+                "{width}x{height} scaled by {factor:?}"
+            );
+        }
+    }
+
+    let mut short = noisy.clone();
+    short[8..12].copy_from_slice(&(height as u32 + 1).to_be_bytes());
+    let mut scaled = vec![7];
+    assert!(decode_scaled(&short, &mut scaled, ScaleFactor::Quarter).is_err());
+    assert_eq!(scaled, [7]);
+}
+
+#[test]
+fn test_narrow_formats() {
+    // gradients, noise and long flat stretches, so every op comes up
+    let (width, height) = (311usize, 257usize);
+    let bytes: Vec<u8> = (0..width * height * 2)
+        .map(|index| match index / 20_000 % 3 {
+            0 => (index / 7) as u8,
+            1 => (index as u32).wrapping_mul(0x9e3779b9).to_be_bytes()[0],
+            _ => 0x80,
+        })
+        .collect();
+    for format in [
+        NarrowFormat::L8,
+        NarrowFormat::La8,
+        NarrowFormat::L16,
+        NarrowFormat::A8,
+    ] {
+        let input = &bytes[..width * height * format.bytes_per_pixel()];
+        let header = QOIHeader {
+            width: width as u32,
+            height: height as u32,
+            has_alpha: matches!(format, NarrowFormat::La8 | NarrowFormat::A8),
+            linear_rgb: false,
+        };
+        let widened: Vec<RGBA> = input
+            .chunks_exact(format.bytes_per_pixel())
+            .map(|pixel| format.to_rgba(pixel))
+            .collect();
+        let mut expected = Vec::new();
+        encode(&widened, &mut expected, header).unwrap();
+        let mut encoded = Vec::new();
+        encode_narrow(input, &mut encoded, header, format).unwrap();
+        assert!(encoded == expected, "encoding {format:?}");
+
+        // only the opaque ones come back as gray
+        let mut decoded = vec![7];
+        let gray = decode_gray(&encoded, &mut decoded).unwrap();
+        assert_eq!(decoded[0], 7);
+        match format {
+            NarrowFormat::L8 => assert!(gray && decoded[1..] == *input),
+            NarrowFormat::L16 => assert!(gray && decoded[1..] == *bytes_of_l16(input)),
+            NarrowFormat::La8 | NarrowFormat::A8 => {
+                assert!(!gray && decoded[1..] == *cast_slice::<RGBA, u8>(&widened))
+            }
+        }
+    }
+
+    // a single pixel of color at the very end widens everything before it
+    let mut pixels = rgba8_to_pixels(&bytes[..width * height * 4 / 2].repeat(2));
+    for pixel in pixels.iter_mut() {
+        let luma = pixel.to_ne_bytes()[1];
+        *pixel = RGBA::from_ne_bytes([luma, luma, luma, 0xff]);
+    }
+    *pixels.last_mut().unwrap() = RGBA::from_ne_bytes([1, 2, 3, 0xff]);
+    let header = QOIHeader {
+        width: width as u32,
+        height: height as u32,
+        has_alpha: false,
+        linear_rgb: false,
+    };
+    let mut qoi_data = Vec::new();
+    encode(&pixels, &mut qoi_data, header).unwrap();
+    let mut decoded = Vec::new();
+    assert!(!decode_gray(&qoi_data, &mut decoded).unwrap());
+    assert_eq!(decoded, cast_slice::<RGBA, u8>(&pixels));
+
+    // gray images from the image crate take the same route
+    let gray = image::GrayImage::from_raw(
+        width as u32,
+        height as u32,
+        bytes[..width * height].to_vec(),
+    );
+    let gray = DynamicImage::ImageLuma8(gray.unwrap());
+    let widened = rgba8_to_pixels(gray.to_rgba8().as_raw());
+    let mut expected = Vec::new();
+    encode(&widened, &mut expected, QOIHeader::from(&gray)).unwrap();
+    assert!(encode_dynamic_image(&gray).unwrap() == expected);
+    let mut encoded = Vec::new();
+    gray.write_with_encoder(QoiEncoder::new(&mut encoded))
+        .unwrap();
+    assert!(encoded == expected);
+}
+
+fn bytes_of_l16(input: &[u8]) -> Vec<u8> {
+    input
+        .chunks_exact(2)
+        .map(|pixel| (u16::from_ne_bytes([pixel[0], pixel[1]]) >> 8) as u8)
+        .collect()
+}
+
+/// Samples a YUV frame the straightforward way, pixel by pixel
+fn yuv_to_pixels(
+    frame: &[u8],
+    width: usize,
+    height: usize,
+    format: YuvFormat,
+    options: YuvOptions,
+) -> Vec<RGBA> {
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+    let chroma_plane = chroma_width * chroma_height;
+    let mut pixels = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            let chroma = y / 2 * chroma_width + x / 2;
+            let [luma, u, v] = match format {
+                YuvFormat::Nv12 => [
+                    y * width + x,
+                    width * height + chroma * 2,
+                    width * height + chroma * 2 + 1,
+                ],
+                YuvFormat::I420 => [
+                    y * width + x,
+                    width * height + chroma,
+                    width * height + chroma_plane + chroma,
+                ],
+                YuvFormat::Yuyv => {
+                    let pair = (y * chroma_width + x / 2) * 4;
+                    [pair + x % 2 * 2, pair + 1, pair + 3]
+                }
+            };
+            pixels.push(options.to_rgba(frame[luma], frame[u], frame[v]));
+        }
+    }
+    pixels
+}
+
+#[test]
+fn test_yuv() {
+    let full_601 = YuvOptions {
+        matrix: YuvMatrix::Bt601,
+        range: YuvRange::Full,
+    };
+    let limited_709 = YuvOptions {
+        matrix: YuvMatrix::Bt709,
+        range: YuvRange::Limited,
+    };
+    let white = RGBA::from_ne_bytes([255; 4]);
+    assert_eq!(full_601.from_rgba(white), [255, 128, 128]);
+    assert_eq!(limited_709.from_rgba(white), [235, 128, 128]);
+    assert_eq!(limited_709.from_rgba(0), [16, 128, 128]);
+    assert_eq!(limited_709.to_rgba(235, 128, 128), white);
+    let red = RGBA::from_ne_bytes([255, 0, 0, 255]);
+    let full_709 = YuvOptions {
+        matrix: YuvMatrix::Bt709,
+        range: YuvRange::Full,
+    };
+    assert_eq!(full_709.from_rgba(red), [54, 99, 255]);
+
+    let formats = [YuvFormat::Nv12, YuvFormat::I420, YuvFormat::Yuyv];
+    let all_options = [YuvOptions::default(), full_601, limited_709, full_709];
+
+    // a solid color comes out as flat planes, whatever the size
+    let teal = RGBA::from_ne_bytes([20, 140, 130, 255]);
+    let (width, height) = (17usize, 3usize);
+    let header = QOIHeader {
+        width: width as u32,
+        height: height as u32,
+        has_alpha: false,
+        linear_rgb: false,
+    };
+    let mut qoi_data = Vec::new();
+    encode(&vec![teal; width * height], &mut qoi_data, header).unwrap();
+    for format in formats {
+        let mut frame = vec![7];
+        decode_yuv(&qoi_data, &mut frame, format, full_601).unwrap();
+        assert_eq!(frame.len(), 1 + format.frame_size(width, height));
+        let pixels = yuv_to_pixels(&frame[1..], width, height, format, full_601);
+        let [y, u, v] = full_601.from_rgba(teal);
+        let expected = full_601.to_rgba(y, u, v);
+        assert!(pixels.iter().all(|&pixel| pixel == expected), "{format:?}");
+    }
+
+    // frames of noise at an odd size encode the same as converting them first
+    let (width, height) = (203usize, 117usize);
+    let header = QOIHeader {
+        width: width as u32,
+        height: height as u32,
+        has_alpha: false,
+        linear_rgb: false,
+    };
+    for format in formats {
+        let frame: Vec<u8> = (0..format.frame_size(width, height))
+            .map(|index| match index / 3000 % 2 {
+                0 => (index as u32).wrapping_mul(0x9e3779b9).to_be_bytes()[0],
+                _ => 0x70,
+            })
+            .collect();
+        for options in all_options {
+            let mut expected = Vec::new();
+            encode(
+                &yuv_to_pixels(&frame, width, height, format, options),
+                &mut expected,
+                header,
+            )
+            .unwrap();
+            let mut encoded = Vec::new();
+            encode_yuv(&frame, &mut encoded, header, format, options).unwrap();
+            assert!(encoded == expected, "encoding {format:?} with {options:?}");
+        }
+    }
+
+    // decoding to YUV and encoding that again comes back within rounding of where it started,
+    // for muted colors that stay in gamut when pixels sharing chroma have different luma
+    let (width, height) = (97usize, 61usize);
+    let pixels: Vec<RGBA> = (0..width * height)
+        .map(|index| {
+            let (x, y) = ((index % width) as u32, (index / width) as u32);
+            RGBA::from_ne_bytes([
+                64 + x as u8,
+                96 + (y * 2) as u8,
+                160 - (x + y) as u8 / 2,
+                255,
+            ])
+        })
+        .collect();
+    let header = QOIHeader {
+        width: width as u32,
+        height: height as u32,
+        has_alpha: false,
+        linear_rgb: false,
+    };
+    let mut qoi_data = Vec::new();
+    encode(&pixels, &mut qoi_data, header).unwrap();
+    for format in formats {
+        for options in all_options {
+            let mut frame = Vec::new();
+            decode_yuv(&qoi_data, &mut frame, format, options).unwrap();
+            let mut again = Vec::new();
+            encode_yuv(&frame, &mut again, header, format, options).unwrap();
+            let mut round_trip = Vec::new();
+            decode_yuv(&again, &mut round_trip, format, options).unwrap();
+            let worst = frame
+                .iter()
+                .zip(&round_trip)
+                .map(|(a, b)| a.abs_diff(*b))
+                .max();
+            assert!(
+                worst <= Some(1),
+                "{format:?} with {options:?} is off by {worst:?}"
+            );
+        }
+    }
+
+    let mut short = qoi_data.clone();
+    short[8..12].copy_from_slice(&(header.height + 1).to_be_bytes());
+    let mut frame = vec![7];
+    assert!(decode_yuv(&short, &mut frame, YuvFormat::Nv12, full_601).is_err());
+    assert_eq!(frame, [7]);
+}
+
 #[test]
 fn test_wonke() {
     test_and_bench("test/wonke.png");